@@ -0,0 +1,80 @@
+use crate::{Auth, BoxError, Report, TokenArgs};
+use clap::{Args, Subcommand};
+
+#[derive(Subcommand, Debug)]
+pub enum GroupCommand {
+    /// Create a device group from registration tokens.
+    Create(GroupArgs),
+
+    /// Add registration tokens to a device group.
+    Add(GroupKeyArgs),
+
+    /// Remove registration tokens from a device group. FCM deletes the group
+    /// when its last token is removed.
+    Remove(GroupKeyArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct GroupArgs {
+    /// Sender id of the project the group belongs to.
+    #[arg(long, env = "FCM_SENDER_ID")]
+    sender_id: String,
+
+    /// Name of the device group.
+    #[arg(long)]
+    name: String,
+
+    #[command(flatten)]
+    tokens: TokenArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct GroupKeyArgs {
+    #[command(flatten)]
+    group: GroupArgs,
+
+    /// Notification key of the device group.
+    #[arg(long)]
+    key: String,
+}
+
+pub async fn run(auth: &Auth, command: &GroupCommand) -> Result<Vec<Report>, BoxError> {
+    let api_key = auth.api_key()?;
+    let client = auth.client()?;
+
+    let (group, result) = match command {
+        GroupCommand::Create(group) => {
+            let tokens = group.tokens.tokens()?;
+            let result = client
+                .create_device_group(api_key, &group.sender_id, &group.name, &tokens)
+                .await;
+
+            (group, result)
+        }
+        GroupCommand::Add(args) => {
+            let group = &args.group;
+            let tokens = group.tokens.tokens()?;
+            let result = client
+                .add_to_device_group(api_key, &group.sender_id, &group.name, &args.key, &tokens)
+                .await;
+
+            (group, result)
+        }
+        GroupCommand::Remove(args) => {
+            let group = &args.group;
+            let tokens = group.tokens.tokens()?;
+            let result = client
+                .remove_from_device_group(api_key, &group.sender_id, &group.name, &args.key, &tokens)
+                .await;
+
+            (group, result)
+        }
+    };
+
+    let report = match result {
+        Ok(response) => Report::ok(&group.name).with("notification_key", response.notification_key),
+        Err(e) => Report::error(&group.name, e),
+    };
+
+    Ok(vec![report])
+}
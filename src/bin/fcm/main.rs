@@ -0,0 +1,313 @@
+//! The `fcm` command-line tool. Build it with the `cli` feature:
+//!
+//! ```sh
+//! cargo install fcm --features cli
+//! fcm --api-key "$FCM_API_KEY" send --token "$TOKEN" --data data.json
+//! fcm --service-account key.json send --topic news --notification - < notification.json
+//! ```
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use fcm::v1::{Credentials, ServiceAccountKey};
+use fcm::{Client, ClientBuilder, Endpoints};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::error::Error;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+mod group;
+mod send;
+mod token;
+mod topic;
+
+#[cfg(test)]
+mod tests;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+/// Exit code when the command failed for some, but not all, targets.
+const EXIT_PARTIAL_FAILURE: u8 = 3;
+
+const EXIT_CODES: &str = "Exit codes: 0 if all targets succeeded, 3 if some failed, 1 if all failed or the \
+                          command could not run, 2 for usage errors.";
+
+/// Send and inspect Firebase Cloud Messaging messages.
+#[derive(Parser, Debug)]
+#[command(name = "fcm", version, after_help = EXIT_CODES)]
+struct Cli {
+    #[command(flatten)]
+    auth: Auth,
+
+    /// How to print the results.
+    #[arg(long, value_enum, default_value_t = Output::Text, global = true)]
+    output: Output,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Send a message to tokens, a topic or a condition.
+    Send(send::SendArgs),
+
+    /// Manage topic subscriptions.
+    #[command(subcommand)]
+    Topic(topic::TopicCommand),
+
+    /// Inspect and validate registration tokens.
+    #[command(subcommand)]
+    Token(token::TokenCommand),
+
+    /// Manage device groups.
+    #[command(subcommand)]
+    Group(group::GroupCommand),
+}
+
+/// Registration tokens, given directly or in a file.
+#[derive(Args, Debug)]
+#[group(required = true, multiple = false)]
+struct TokenArgs {
+    /// Registration token. Repeat for several tokens.
+    #[arg(long = "token", value_name = "TOKEN")]
+    tokens: Vec<String>,
+
+    /// File with one registration token per line, or `-` for stdin. Empty
+    /// lines and lines starting with `#` are skipped.
+    #[arg(long, value_name = "FILE")]
+    tokens_file: Option<PathBuf>,
+}
+
+impl TokenArgs {
+    fn tokens(&self) -> Result<Vec<String>, BoxError> {
+        read_tokens(&self.tokens, self.tokens_file.as_deref())
+    }
+}
+
+/// How requests are authorized. A server key selects the legacy API,
+/// otherwise a service account key selects the v1 API.
+#[derive(Args, Debug)]
+struct Auth {
+    /// Server key for the legacy API.
+    #[arg(long, env = "FCM_API_KEY", global = true, hide_env_values = true)]
+    api_key: Option<String>,
+
+    /// Service account key file for the v1 API.
+    #[arg(long, env = "GOOGLE_APPLICATION_CREDENTIALS", global = true)]
+    service_account: Option<PathBuf>,
+
+    /// Send all requests to this base URL instead of the Google APIs.
+    #[arg(long, env = "FCM_BASE_URL", global = true, hide = true)]
+    base_url: Option<String>,
+}
+
+/// The API a command talks to, with its credentials.
+enum Api {
+    Legacy { api_key: String },
+    V1 { credentials: Box<Credentials> },
+}
+
+impl Auth {
+    fn api(&self) -> Result<Api, BoxError> {
+        if let Some(ref api_key) = self.api_key {
+            return Ok(Api::Legacy {
+                api_key: api_key.clone(),
+            });
+        }
+
+        match self.service_account {
+            Some(ref path) => Ok(Api::V1 {
+                credentials: Box::new(Credentials::service_account(ServiceAccountKey::from_file(path)?)),
+            }),
+            None => Err("either --api-key or --service-account is required".into()),
+        }
+    }
+
+    /// The server key, for commands that only the legacy APIs support.
+    fn api_key(&self) -> Result<&str, BoxError> {
+        self.api_key
+            .as_deref()
+            .ok_or_else(|| "--api-key is required for this command".into())
+    }
+
+    fn client(&self) -> Result<Client, BoxError> {
+        let mut builder = ClientBuilder::new();
+
+        if let Some(ref base_url) = self.base_url {
+            builder.endpoints(Endpoints::all(base_url));
+        }
+
+        Ok(builder.finalize()?)
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Output {
+    /// One line per target.
+    Text,
+
+    /// A JSON array with one object per target.
+    Json,
+}
+
+/// The outcome of a request for a single target.
+#[derive(Serialize, Debug, Default, PartialEq)]
+struct Report {
+    target: String,
+
+    #[serde(flatten)]
+    details: Map<String, Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Report {
+    fn ok(target: &str) -> Report {
+        Report {
+            target: target.to_string(),
+            ..Report::default()
+        }
+    }
+
+    fn error(target: &str, error: impl ToString) -> Report {
+        Report {
+            target: target.to_string(),
+            error: Some(error.to_string()),
+            ..Report::default()
+        }
+    }
+
+    /// Add a detail, unless `value` is null.
+    fn with(mut self, key: &str, value: impl Into<Value>) -> Report {
+        let value = value.into();
+
+        if !value.is_null() {
+            self.details.insert(key.to_string(), value);
+        }
+
+        self
+    }
+}
+
+fn print_reports(output: Output, reports: &[Report]) {
+    match output {
+        Output::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(reports).expect("reports serialize to JSON")
+        ),
+        Output::Text => {
+            for report in reports {
+                let details: Vec<String> = report
+                    .details
+                    .iter()
+                    .map(|(key, value)| format!("{}={}", key, display(value)))
+                    .collect();
+
+                match report.error {
+                    Some(ref error) if details.is_empty() => println!("{}: error: {}", report.target, error),
+                    Some(ref error) => println!("{}: error: {} ({})", report.target, error, details.join(", ")),
+                    None if details.is_empty() => println!("{}: ok", report.target),
+                    None => println!("{}: {}", report.target, details.join(", ")),
+                }
+            }
+        }
+    }
+}
+
+/// Strings without quotes and lists as comma separated values.
+fn display(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        Value::Array(values) => values.iter().map(display).collect::<Vec<_>>().join(","),
+        value => value.to_string(),
+    }
+}
+
+/// The exit code for the reports of a command.
+fn exit_code(reports: &[Report]) -> ExitCode {
+    let failed = reports.iter().filter(|report| report.error.is_some()).count();
+
+    if failed == 0 {
+        ExitCode::SUCCESS
+    } else if failed < reports.len() {
+        ExitCode::from(EXIT_PARTIAL_FAILURE)
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Read a file, or stdin if `path` is `-`.
+fn read_input(path: &Path) -> Result<String, BoxError> {
+    let mut input = String::new();
+
+    if path == Path::new("-") {
+        std::io::stdin().read_to_string(&mut input)?;
+    } else {
+        input = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+
+    Ok(input)
+}
+
+/// The tokens given directly or, if `path` is given, read from a token list
+/// file.
+fn read_tokens(tokens: &[String], path: Option<&Path>) -> Result<Vec<String>, BoxError> {
+    let tokens = match path {
+        Some(path) => parse_tokens(&read_input(path)?),
+        None => tokens.to_vec(),
+    };
+
+    if tokens.is_empty() {
+        return Err("no registration tokens given".into());
+    }
+
+    Ok(tokens)
+}
+
+/// The tokens of a token list file.
+fn parse_tokens(input: &str) -> Vec<String> {
+    input
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+/// Read a JSON object from a file, or stdin if `path` is `-`.
+fn read_json(path: &Path) -> Result<Value, BoxError> {
+    let json: Value = serde_json::from_str(&read_input(path)?).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    if !json.is_object() {
+        return Err(format!("{}: expected a JSON object", path.display()).into());
+    }
+
+    Ok(json)
+}
+
+async fn run(cli: &Cli) -> Result<Vec<Report>, BoxError> {
+    match cli.command {
+        Command::Send(ref args) => send::run(&cli.auth, args).await,
+        Command::Topic(ref command) => topic::run(&cli.auth, command).await,
+        Command::Token(ref command) => token::run(&cli.auth, command).await,
+        Command::Group(ref command) => group::run(&cli.auth, command).await,
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(&cli).await {
+        Ok(reports) => {
+            print_reports(cli.output, &reports);
+            exit_code(&reports)
+        }
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
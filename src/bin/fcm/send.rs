@@ -0,0 +1,370 @@
+use crate::{read_json, read_tokens, Api, Auth, BoxError, Report};
+use clap::{Args, ValueEnum};
+use fcm::v1::{self, Credentials};
+use fcm::{Client, FcmResponse, MessageBuilder, NotificationBuilder};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::path::{Path, PathBuf};
+
+/// The most tokens the legacy API accepts in a single request.
+const MAX_TOKENS_PER_REQUEST: usize = 1000;
+
+#[derive(Args, Debug)]
+pub struct SendArgs {
+    #[command(flatten)]
+    target: TargetArgs,
+
+    /// JSON object with custom data, read from FILE or `-` for stdin.
+    #[arg(long, value_name = "FILE")]
+    data: Option<PathBuf>,
+
+    /// JSON object with the notification, read from FILE or `-` for stdin.
+    /// The legacy API reads title, body, icon, sound, badge, tag, color and
+    /// click_action, the v1 API reads title, body and image.
+    #[arg(long, value_name = "FILE")]
+    notification: Option<PathBuf>,
+
+    /// Delivery priority.
+    #[arg(long, value_enum)]
+    priority: Option<Priority>,
+
+    /// How long FCM keeps the message if the device is offline.
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u32).range(..=2_419_200))]
+    ttl: Option<u32>,
+
+    /// Identifies a group of messages of which only the last is delivered.
+    #[arg(long)]
+    collapse_key: Option<String>,
+
+    /// Only validate the message, without delivering it.
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+#[group(required = true, multiple = false)]
+struct TargetArgs {
+    /// Registration token. Repeat for several tokens.
+    #[arg(long = "token", value_name = "TOKEN")]
+    tokens: Vec<String>,
+
+    /// File with one registration token per line, or `-` for stdin. Empty
+    /// lines and lines starting with `#` are skipped.
+    #[arg(long, value_name = "FILE")]
+    tokens_file: Option<PathBuf>,
+
+    /// Topic name, with or without the `/topics/` prefix.
+    #[arg(long)]
+    topic: Option<String>,
+
+    /// Topic condition, e.g. "'dogs' in topics || 'cats' in topics".
+    #[arg(long)]
+    condition: Option<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Priority {
+    Normal,
+    High,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Target {
+    Tokens(Vec<String>),
+    Topic(String),
+    Condition(String),
+}
+
+impl TargetArgs {
+    fn target(&self) -> Result<Target, BoxError> {
+        if let Some(ref topic) = self.topic {
+            return Ok(Target::Topic(topic.trim_start_matches("/topics/").to_string()));
+        }
+
+        if let Some(ref condition) = self.condition {
+            return Ok(Target::Condition(condition.clone()));
+        }
+
+        read_tokens(&self.tokens, self.tokens_file.as_deref()).map(Target::Tokens)
+    }
+}
+
+/// The notification fields supported by the legacy API.
+#[derive(Deserialize, Debug, Default)]
+struct LegacyNotification {
+    title: Option<String>,
+    body: Option<String>,
+    icon: Option<String>,
+    sound: Option<String>,
+    #[serde(default, deserialize_with = "string_or_number")]
+    badge: Option<String>,
+    tag: Option<String>,
+    color: Option<String>,
+    click_action: Option<String>,
+}
+
+/// A string, or a number as APNs payloads usually carry for the badge.
+fn string_or_number<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrNumber {
+        String(String),
+        Number(serde_json::Number),
+    }
+
+    Ok(
+        Option::<StringOrNumber>::deserialize(deserializer)?.map(|value| match value {
+            StringOrNumber::String(string) => string,
+            StringOrNumber::Number(number) => number.to_string(),
+        }),
+    )
+}
+
+pub async fn run(auth: &Auth, args: &SendArgs) -> Result<Vec<Report>, BoxError> {
+    let from_stdin = |path: &Option<PathBuf>| path.as_deref() == Some(Path::new("-"));
+    let stdin_inputs = [&args.data, &args.notification, &args.target.tokens_file];
+
+    if stdin_inputs.iter().filter(|path| from_stdin(path)).count() > 1 {
+        return Err("only one input can be read from stdin".into());
+    }
+
+    let target = args.target.target()?;
+    let data = args.data.as_deref().map(read_json).transpose()?;
+    let notification = args.notification.as_deref().map(read_json).transpose()?;
+
+    let client = auth.client()?;
+
+    match auth.api()? {
+        Api::Legacy { api_key } => {
+            let notification = notification.map(serde_json::from_value).transpose()?;
+            send_legacy(&client, &api_key, args, &target, data.as_ref(), notification.as_ref()).await
+        }
+        Api::V1 { credentials } => {
+            let notification = notification.map(serde_json::from_value).transpose()?;
+            Ok(send_v1(&client, &credentials, args, &target, data.as_ref(), notification).await)
+        }
+    }
+}
+
+async fn send_legacy(
+    client: &Client,
+    api_key: &str,
+    args: &SendArgs,
+    target: &Target,
+    data: Option<&Value>,
+    notification: Option<&LegacyNotification>,
+) -> Result<Vec<Report>, BoxError> {
+    let mut reports = Vec::new();
+
+    match target {
+        Target::Tokens(tokens) => {
+            for chunk in tokens.chunks(MAX_TOKENS_PER_REQUEST) {
+                let mut builder = MessageBuilder::new_multi(api_key, chunk);
+                apply_legacy_options(&mut builder, args, data, notification)?;
+
+                match client.send(builder.finalize()).await {
+                    Ok(response) => {
+                        let results = response.results.unwrap_or_default();
+
+                        reports.extend(chunk.iter().enumerate().map(|(i, token)| {
+                            match results.get(i) {
+                                Some(result) => Report {
+                                    error: result.error.as_ref().map(ToString::to_string),
+                                    ..Report::ok(token)
+                                        .with("message_id", result.message_id.clone())
+                                        .with("canonical_id", result.registration_id.clone())
+                                },
+                                None => Report::error(token, "missing from the response"),
+                            }
+                        }));
+                    }
+                    Err(e) => reports.extend(chunk.iter().map(|token| Report::error(token, &e))),
+                }
+            }
+        }
+        Target::Topic(topic) => {
+            let to = format!("/topics/{}", topic);
+            let mut builder = MessageBuilder::new(api_key, &to);
+            apply_legacy_options(&mut builder, args, data, notification)?;
+
+            reports.push(topic_report(&to, client.send(builder.finalize()).await));
+        }
+        Target::Condition(condition) => {
+            let mut builder = MessageBuilder::new_condition(api_key, condition);
+            apply_legacy_options(&mut builder, args, data, notification)?;
+
+            reports.push(topic_report(condition, client.send(builder.finalize()).await));
+        }
+    }
+
+    Ok(reports)
+}
+
+fn topic_report(target: &str, result: Result<FcmResponse, fcm::FcmError>) -> Report {
+    match result {
+        Ok(response) => Report {
+            error: response.error.map(|error| error.to_string()),
+            ..Report::ok(target).with("message_id", response.message_id)
+        },
+        Err(e) => Report::error(target, e),
+    }
+}
+
+fn apply_legacy_options<'a>(
+    builder: &mut MessageBuilder<'a>,
+    args: &'a SendArgs,
+    data: Option<&Value>,
+    notification: Option<&'a LegacyNotification>,
+) -> Result<(), BoxError> {
+    if let Some(data) = data {
+        builder.data(data)?;
+    }
+
+    if let Some(notification) = notification {
+        let mut notification_builder = NotificationBuilder::new();
+
+        if let Some(ref title) = notification.title {
+            notification_builder.title(title);
+        }
+        if let Some(ref body) = notification.body {
+            notification_builder.body(body);
+        }
+        if let Some(ref icon) = notification.icon {
+            notification_builder.icon(icon);
+        }
+        if let Some(ref sound) = notification.sound {
+            notification_builder.sound(sound);
+        }
+        if let Some(ref badge) = notification.badge {
+            notification_builder.badge(badge);
+        }
+        if let Some(ref tag) = notification.tag {
+            notification_builder.tag(tag);
+        }
+        if let Some(ref color) = notification.color {
+            notification_builder.color(color);
+        }
+        if let Some(ref click_action) = notification.click_action {
+            notification_builder.click_action(click_action);
+        }
+
+        builder.notification(notification_builder.finalize());
+    }
+
+    match args.priority {
+        Some(Priority::High) => {
+            builder.priority(fcm::Priority::High);
+        }
+        Some(Priority::Normal) => {
+            builder.priority(fcm::Priority::Normal);
+        }
+        None => {}
+    }
+
+    if let Some(ttl) = args.ttl {
+        builder.time_to_live(ttl as i32);
+    }
+
+    if let Some(ref collapse_key) = args.collapse_key {
+        builder.collapse_key(collapse_key);
+    }
+
+    if args.dry_run {
+        builder.dry_run(true);
+    }
+
+    Ok(())
+}
+
+async fn send_v1(
+    client: &Client,
+    credentials: &Credentials,
+    args: &SendArgs,
+    target: &Target,
+    data: Option<&Value>,
+    notification: Option<v1::Notification>,
+) -> Vec<Report> {
+    let targets: Vec<(String, v1::Target)> = match target {
+        Target::Tokens(tokens) => tokens
+            .iter()
+            .map(|token| (token.clone(), v1::Target::Token(token.clone())))
+            .collect(),
+        Target::Topic(topic) => vec![(format!("/topics/{}", topic), v1::Target::Topic(topic.clone()))],
+        Target::Condition(condition) => vec![(condition.clone(), v1::Target::Condition(condition.clone()))],
+    };
+
+    let mut reports = Vec::with_capacity(targets.len());
+
+    for (name, target) in targets {
+        let message = v1_message(target, args, data, notification.clone());
+
+        reports.push(match client.send_v1(credentials, &message).await {
+            Ok(response) => Report::ok(&name).with("message_id", response.name),
+            Err(e) => Report::error(&name, e),
+        });
+    }
+
+    reports
+}
+
+/// A v1 message with the options of `args`. Data values that are not strings
+/// are sent as JSON, as the v1 API only accepts strings.
+pub(crate) fn v1_message(
+    target: v1::Target,
+    args: &SendArgs,
+    data: Option<&Value>,
+    notification: Option<v1::Notification>,
+) -> v1::Message {
+    let mut builder = v1::MessageBuilder::new(target);
+
+    if let Some(Value::Object(data)) = data {
+        builder.data(data.iter().map(|(key, value)| {
+            let value = match value {
+                Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+
+            (key.clone(), value)
+        }));
+    }
+
+    if let Some(notification) = notification {
+        builder.notification(notification);
+    }
+
+    let mut android = Map::new();
+    let mut apns_headers = Map::new();
+
+    if let Some(priority) = args.priority {
+        let (android_priority, apns_priority) = match priority {
+            Priority::High => ("high", "10"),
+            Priority::Normal => ("normal", "5"),
+        };
+
+        android.insert("priority".to_string(), json!(android_priority));
+        apns_headers.insert("apns-priority".to_string(), json!(apns_priority));
+    }
+
+    if let Some(ttl) = args.ttl {
+        android.insert("ttl".to_string(), json!(format!("{}s", ttl)));
+    }
+
+    if let Some(ref collapse_key) = args.collapse_key {
+        android.insert("collapse_key".to_string(), json!(collapse_key));
+        apns_headers.insert("apns-collapse-id".to_string(), json!(collapse_key));
+    }
+
+    if !android.is_empty() {
+        builder.android(Value::Object(android));
+    }
+
+    if !apns_headers.is_empty() {
+        builder.apns(json!({ "headers": apns_headers }));
+    }
+
+    builder.validate_only(args.dry_run);
+    builder.finalize()
+}
@@ -0,0 +1,196 @@
+use crate::send::v1_message;
+use crate::{exit_code, parse_tokens, Cli, Command, Report};
+use clap::{CommandFactory, Parser};
+use serde_json::json;
+use std::process::ExitCode;
+
+#[test]
+fn should_have_a_valid_command_line() {
+    Cli::command().debug_assert();
+}
+
+#[test]
+fn should_require_exactly_one_target() {
+    assert!(Cli::try_parse_from(["fcm", "send"]).is_err());
+    assert!(Cli::try_parse_from(["fcm", "send", "--topic", "news", "--token", "a"]).is_err());
+    assert!(Cli::try_parse_from(["fcm", "send", "--token", "a", "--token", "b"]).is_ok());
+    assert!(Cli::try_parse_from(["fcm", "topic", "subscribe", "--topic", "news"]).is_err());
+}
+
+#[test]
+fn should_skip_comments_and_empty_lines_in_token_files() {
+    let tokens = parse_tokens("# tokens\none\n\n  two  \n");
+
+    assert_eq!(vec!["one", "two"], tokens);
+}
+
+#[test]
+fn should_reflect_partial_failures_in_the_exit_code() {
+    let ok = || Report::ok("a");
+    let failed = || Report::error("b", "NotRegistered");
+
+    assert_eq!(ExitCode::SUCCESS, exit_code(&[ok(), ok()]));
+    assert_eq!(ExitCode::from(3), exit_code(&[ok(), failed()]));
+    assert_eq!(ExitCode::FAILURE, exit_code(&[failed()]));
+}
+
+#[test]
+fn should_map_options_to_v1_configs() {
+    let cli = Cli::try_parse_from([
+        "fcm",
+        "send",
+        "--token",
+        "a",
+        "--priority",
+        "high",
+        "--ttl",
+        "60",
+        "--collapse-key",
+        "score",
+        "--dry-run",
+    ])
+    .unwrap();
+
+    let args = match cli.command {
+        Command::Send(ref args) => args,
+        _ => unreachable!(),
+    };
+
+    let data = json!({ "text": "Howdy!", "count": 3 });
+    let message = v1_message(fcm::v1::Target::Token("a".to_string()), args, Some(&data), None);
+
+    assert_eq!("Howdy!", message.data["text"]);
+    assert_eq!("3", message.data["count"]);
+    assert_eq!(
+        Some(json!({ "priority": "high", "ttl": "60s", "collapse_key": "score" })),
+        message.android
+    );
+    assert_eq!(
+        Some(json!({ "headers": { "apns-priority": "10", "apns-collapse-id": "score" } })),
+        message.apns
+    );
+    assert!(message.validate_only);
+}
+
+#[cfg(feature = "fake-server")]
+mod fake_server {
+    use crate::{run, Cli, Report};
+    use clap::Parser;
+    use fcm::fake_server::FakeFcm;
+    use fcm::ErrorReason;
+    use serde_json::json;
+
+    async fn run_with(server: &FakeFcm, args: &[&str]) -> Vec<Report> {
+        let url = server.url();
+        let mut command_line = vec!["fcm", "--api-key", "key", "--base-url", &url];
+        command_line.extend_from_slice(args);
+
+        run(&Cli::try_parse_from(command_line).unwrap()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_send_legacy_messages() {
+        let server = FakeFcm::start().await.unwrap();
+        server.token_error("gone", ErrorReason::NotRegistered);
+
+        let reports = run_with(&server, &["send", "--token", "one", "--token", "gone"]).await;
+
+        assert_eq!("one", reports[0].target);
+        assert!(reports[0].details.contains_key("message_id"));
+        assert_eq!(Some("NotRegistered".to_string()), reports[1].error);
+        assert_eq!(1, server.inbox("one").len());
+    }
+
+    #[tokio::test]
+    async fn should_accept_numeric_badges() {
+        let server = FakeFcm::start().await.unwrap();
+
+        let path = std::env::temp_dir().join(format!("fcm-cli-badge-{}.json", std::process::id()));
+        std::fs::write(&path, json!({ "title": "Hey!", "badge": 3 }).to_string()).unwrap();
+
+        let reports = run_with(
+            &server,
+            &["send", "--token", "one", "--notification", path.to_str().unwrap()],
+        )
+        .await;
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(reports[0].error.is_none());
+        assert_eq!(json!("3"), server.inbox("one")[0].body["notification"]["badge"]);
+    }
+
+    #[tokio::test]
+    async fn should_manage_topics_and_inspect_tokens() {
+        let server = FakeFcm::start().await.unwrap();
+        server.token_error("gone", ErrorReason::NotRegistered);
+
+        let reports = run_with(
+            &server,
+            &[
+                "topic",
+                "subscribe",
+                "--topic",
+                "news",
+                "--token",
+                "one",
+                "--token",
+                "gone",
+            ],
+        )
+        .await;
+
+        assert!(reports[0].error.is_none());
+        assert_eq!(Some("NOT_FOUND".to_string()), reports[1].error);
+
+        let reports = run_with(&server, &["token", "info", "--token", "one"]).await;
+        assert_eq!(Some(&json!(["news"])), reports[0].details.get("topics"));
+
+        let reports = run_with(&server, &["token", "validate", "--token", "one", "--token", "gone"]).await;
+        assert_eq!(Some(&json!("valid")), reports[0].details.get("outcome"));
+        assert_eq!(Some(&json!("invalid")), reports[1].details.get("outcome"));
+        assert!(server.inbox("one").is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_manage_device_groups() {
+        let server = FakeFcm::start().await.unwrap();
+
+        let reports = run_with(
+            &server,
+            &[
+                "group",
+                "create",
+                "--sender-id",
+                "1",
+                "--name",
+                "family",
+                "--token",
+                "a",
+            ],
+        )
+        .await;
+        let key = reports[0].details["notification_key"].as_str().unwrap().to_string();
+
+        run_with(
+            &server,
+            &[
+                "group",
+                "add",
+                "--sender-id",
+                "1",
+                "--name",
+                "family",
+                "--key",
+                &key,
+                "--token",
+                "b",
+            ],
+        )
+        .await;
+
+        assert_eq!(
+            Some(vec!["a".to_string(), "b".to_string()]),
+            server.device_group("family")
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{Api, Auth, BoxError, Report, TokenArgs};
+use clap::Subcommand;
+use fcm::v1::{self, Credentials};
+use fcm::{Client, ErrorReason, FcmError, MessageBuilder};
+
+/// The most tokens the legacy API accepts in a single request.
+const MAX_TOKENS_PER_REQUEST: usize = 1000;
+
+#[derive(Subcommand, Debug)]
+pub enum TokenCommand {
+    /// Show the Instance ID details of registration tokens, including their
+    /// topics.
+    Info(TokenArgs),
+
+    /// Check whether registration tokens are still valid with a dry-run send.
+    /// Each token is classified as valid, invalid (remove it), unavailable
+    /// (try again later) or error.
+    Validate(TokenArgs),
+}
+
+/// How a token fared in a dry-run send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Valid,
+    Invalid,
+    Unavailable,
+    Error,
+}
+
+impl Outcome {
+    pub(crate) fn of_reason(reason: &ErrorReason) -> Outcome {
+        if reason.should_remove_token() {
+            Outcome::Invalid
+        } else if reason.is_retryable() {
+            Outcome::Unavailable
+        } else {
+            Outcome::Error
+        }
+    }
+
+    pub(crate) fn of_error(error: &FcmError) -> Outcome {
+        if error.should_remove_token() {
+            Outcome::Invalid
+        } else if error.is_retryable() {
+            Outcome::Unavailable
+        } else {
+            Outcome::Error
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Valid => "valid",
+            Outcome::Invalid => "invalid",
+            Outcome::Unavailable => "unavailable",
+            Outcome::Error => "error",
+        }
+    }
+
+    fn report(self, token: &str, error: Option<String>) -> Report {
+        Report {
+            error,
+            ..Report::ok(token).with("outcome", self.as_str())
+        }
+    }
+}
+
+pub async fn run(auth: &Auth, command: &TokenCommand) -> Result<Vec<Report>, BoxError> {
+    let client = auth.client()?;
+
+    match command {
+        TokenCommand::Info(args) => info(&client, auth.api_key()?, &args.tokens()?).await,
+        TokenCommand::Validate(args) => {
+            let tokens = args.tokens()?;
+
+            match auth.api()? {
+                Api::Legacy { api_key } => Ok(validate_legacy(&client, &api_key, &tokens).await),
+                Api::V1 { credentials } => Ok(validate_v1(&client, &credentials, &tokens).await),
+            }
+        }
+    }
+}
+
+async fn info(client: &Client, api_key: &str, tokens: &[String]) -> Result<Vec<Report>, BoxError> {
+    let mut reports = Vec::with_capacity(tokens.len());
+
+    for token in tokens {
+        reports.push(match client.token_info(api_key, token).await {
+            Ok(info) => Report::ok(token)
+                .with("platform", info.platform.clone())
+                .with("application", info.application.clone())
+                .with("authorized_entity", info.authorized_entity.clone())
+                .with("topics", info.topics()),
+            Err(e) => Report::error(token, e),
+        });
+    }
+
+    Ok(reports)
+}
+
+async fn validate_legacy(client: &Client, api_key: &str, tokens: &[String]) -> Vec<Report> {
+    let mut reports = Vec::with_capacity(tokens.len());
+
+    for chunk in tokens.chunks(MAX_TOKENS_PER_REQUEST) {
+        let mut builder = MessageBuilder::new_multi(api_key, chunk);
+        builder.dry_run(true);
+
+        match client.send(builder.finalize()).await {
+            Ok(response) => {
+                let results = response.results.unwrap_or_default();
+
+                reports.extend(chunk.iter().enumerate().map(|(i, token)| {
+                    match results.get(i).map(|result| result.error.as_ref()) {
+                        Some(None) => Outcome::Valid.report(token, None),
+                        Some(Some(reason)) => Outcome::of_reason(reason).report(token, Some(reason.to_string())),
+                        None => Outcome::Error.report(token, Some("missing from the response".to_string())),
+                    }
+                }));
+            }
+            Err(e) => {
+                let outcome = Outcome::of_error(&e);
+                reports.extend(chunk.iter().map(|token| outcome.report(token, Some(e.to_string()))));
+            }
+        }
+    }
+
+    reports
+}
+
+async fn validate_v1(client: &Client, credentials: &Credentials, tokens: &[String]) -> Vec<Report> {
+    let mut reports = Vec::with_capacity(tokens.len());
+
+    for token in tokens {
+        let mut builder = v1::MessageBuilder::new(v1::Target::Token(token.clone()));
+        builder.validate_only(true);
+
+        reports.push(match client.send_v1(credentials, &builder.finalize()).await {
+            Ok(_) => Outcome::Valid.report(token, None),
+            Err(e) => Outcome::of_error(&e).report(token, Some(e.to_string())),
+        });
+    }
+
+    reports
+}
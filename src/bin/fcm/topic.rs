@@ -0,0 +1,63 @@
+use crate::{Auth, BoxError, Report, TokenArgs};
+use clap::{Args, Subcommand};
+
+/// The most tokens the Instance ID API accepts in a single request.
+const MAX_TOKENS_PER_REQUEST: usize = 1000;
+
+#[derive(Subcommand, Debug)]
+pub enum TopicCommand {
+    /// Subscribe registration tokens to a topic.
+    Subscribe(TopicArgs),
+
+    /// Unsubscribe registration tokens from a topic.
+    Unsubscribe(TopicArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct TopicArgs {
+    /// Topic name, with or without the `/topics/` prefix.
+    #[arg(long)]
+    topic: String,
+
+    #[command(flatten)]
+    tokens: TokenArgs,
+}
+
+pub async fn run(auth: &Auth, command: &TopicCommand) -> Result<Vec<Report>, BoxError> {
+    let (subscribe, args) = match command {
+        TopicCommand::Subscribe(args) => (true, args),
+        TopicCommand::Unsubscribe(args) => (false, args),
+    };
+
+    let api_key = auth.api_key()?;
+    let client = auth.client()?;
+    let tokens = args.tokens.tokens()?;
+
+    let mut reports = Vec::with_capacity(tokens.len());
+
+    for chunk in tokens.chunks(MAX_TOKENS_PER_REQUEST) {
+        let result = if subscribe {
+            client.subscribe_to_topic(api_key, &args.topic, chunk).await
+        } else {
+            client.unsubscribe_from_topic(api_key, &args.topic, chunk).await
+        };
+
+        match result {
+            Ok(response) => reports.extend(
+                chunk
+                    .iter()
+                    .enumerate()
+                    .map(|(i, token)| match response.results.get(i) {
+                        Some(result) => match result.error {
+                            Some(ref error) => Report::error(token, error),
+                            None => Report::ok(token),
+                        },
+                        None => Report::error(token, "missing from the response"),
+                    }),
+            ),
+            Err(e) => reports.extend(chunk.iter().map(|token| Report::error(token, &e))),
+        }
+    }
+
+    Ok(reports)
+}
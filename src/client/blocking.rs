@@ -0,0 +1,241 @@
+//! A blocking client for code that does not run an async runtime.
+//!
+//! Enable the `blocking` feature to use it.
+//!
+//! # Examples:
+//!
+//! ```no_run
+//! # fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+//! let client = fcm::blocking::Client::new();
+//!
+//! let mut builder = fcm::NotificationBuilder::new();
+//! builder.title("Hey!");
+//!
+//! let mut message_builder = fcm::MessageBuilder::new("<FCM API Key>", "<registration id>");
+//! message_builder.notification(builder.finalize());
+//!
+//! let response = client.send(message_builder.finalize())?;
+//! println!("Sent: {:?}", response);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::client::device_group::{device_group_request, DeviceGroupOperation};
+use crate::client::transport::{HttpRequest, HttpResponse};
+use crate::client::{
+    json_response, send_response, topic_management_payload, topic_management_response, Endpoints, TopicOperation,
+};
+use crate::message::Message;
+use crate::{DeviceGroupResponse, FcmError, FcmResponse, TokenInfo, TopicManagementResponse};
+
+/// A blocking client for sending the notification payload. It has the same
+/// operations as the async [Client](../struct.Client.html), but always uses
+/// `reqwest::blocking` instead of a [Transport](../transport/trait.Transport.html).
+///
+/// It must not be used from within an async runtime.
+pub struct Client {
+    http_client: reqwest::blocking::Client,
+    endpoints: Endpoints,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Client {
+    /// Get a new instance of Client.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the TLS backend cannot be initialized. Use
+    /// [try_new](#method.try_new) to handle that case.
+    pub fn new() -> Client {
+        Self::try_new().expect("failed to initialize the HTTP client")
+    }
+
+    /// Get a new instance of Client, or an error if the underlying HTTP
+    /// client cannot be initialized.
+    pub fn try_new() -> Result<Client, FcmError> {
+        let http_client = reqwest::blocking::ClientBuilder::new()
+            .pool_max_idle_per_host(usize::MAX)
+            .build()?;
+
+        Ok(Client {
+            http_client,
+            endpoints: Endpoints::default(),
+        })
+    }
+
+    /// Send requests to `endpoints` instead of the Google APIs.
+    pub fn with_endpoints(mut self, endpoints: Endpoints) -> Client {
+        self.endpoints = endpoints;
+        self
+    }
+
+    /// Try sending a `Message` to FCM. See
+    /// [Client::send](../struct.Client.html#method.send).
+    pub fn send(&self, message: Message<'_>) -> Result<FcmResponse, FcmError> {
+        let payload = serde_json::to_vec(&message.body).map_err(FcmError::Serialization)?;
+
+        let request = HttpRequest::post_json(
+            self.endpoints.legacy_send(),
+            &format!("key={}", message.api_key),
+            payload,
+        )?;
+        let response = self.execute(request)?;
+
+        send_response(response.status, &response.headers, &response.body)
+    }
+
+    /// Subscribe registration tokens to a topic. See
+    /// [Client::subscribe_to_topic](../struct.Client.html#method.subscribe_to_topic).
+    pub fn subscribe_to_topic<S>(
+        &self,
+        api_key: &str,
+        topic: &str,
+        tokens: &[S],
+    ) -> Result<TopicManagementResponse, FcmError>
+    where
+        S: AsRef<str>,
+    {
+        self.manage_topic(TopicOperation::Subscribe, api_key, topic, tokens)
+    }
+
+    /// Unsubscribe registration tokens from a topic. See
+    /// [Client::unsubscribe_from_topic](../struct.Client.html#method.unsubscribe_from_topic).
+    pub fn unsubscribe_from_topic<S>(
+        &self,
+        api_key: &str,
+        topic: &str,
+        tokens: &[S],
+    ) -> Result<TopicManagementResponse, FcmError>
+    where
+        S: AsRef<str>,
+    {
+        self.manage_topic(TopicOperation::Unsubscribe, api_key, topic, tokens)
+    }
+
+    /// Get the Instance ID details of a registration token. See
+    /// [Client::token_info](../struct.Client.html#method.token_info).
+    pub fn token_info(&self, api_key: &str, token: &str) -> Result<TokenInfo, FcmError> {
+        let request = HttpRequest::get(self.endpoints.instance_info(token), &format!("key={}", api_key))?;
+        let response = self.execute(request)?;
+
+        json_response(response.status, &response.headers, &response.body)
+    }
+
+    /// Create a device group. See
+    /// [Client::create_device_group](../struct.Client.html#method.create_device_group).
+    pub fn create_device_group<S>(
+        &self,
+        api_key: &str,
+        sender_id: &str,
+        name: &str,
+        tokens: &[S],
+    ) -> Result<DeviceGroupResponse, FcmError>
+    where
+        S: AsRef<str>,
+    {
+        self.manage_device_group(DeviceGroupOperation::Create, api_key, sender_id, name, None, tokens)
+    }
+
+    /// Add registration tokens to a device group. See
+    /// [Client::add_to_device_group](../struct.Client.html#method.add_to_device_group).
+    pub fn add_to_device_group<S>(
+        &self,
+        api_key: &str,
+        sender_id: &str,
+        name: &str,
+        notification_key: &str,
+        tokens: &[S],
+    ) -> Result<DeviceGroupResponse, FcmError>
+    where
+        S: AsRef<str>,
+    {
+        let operation = DeviceGroupOperation::Add;
+        self.manage_device_group(operation, api_key, sender_id, name, Some(notification_key), tokens)
+    }
+
+    /// Remove registration tokens from a device group. See
+    /// [Client::remove_from_device_group](../struct.Client.html#method.remove_from_device_group).
+    pub fn remove_from_device_group<S>(
+        &self,
+        api_key: &str,
+        sender_id: &str,
+        name: &str,
+        notification_key: &str,
+        tokens: &[S],
+    ) -> Result<DeviceGroupResponse, FcmError>
+    where
+        S: AsRef<str>,
+    {
+        let operation = DeviceGroupOperation::Remove;
+        self.manage_device_group(operation, api_key, sender_id, name, Some(notification_key), tokens)
+    }
+
+    fn manage_device_group<S>(
+        &self,
+        operation: DeviceGroupOperation,
+        api_key: &str,
+        sender_id: &str,
+        name: &str,
+        notification_key: Option<&str>,
+        tokens: &[S],
+    ) -> Result<DeviceGroupResponse, FcmError>
+    where
+        S: AsRef<str>,
+    {
+        let request = device_group_request(
+            &self.endpoints,
+            operation,
+            api_key,
+            sender_id,
+            name,
+            notification_key,
+            tokens,
+        )?;
+        let response = self.execute(request)?;
+
+        json_response(response.status, &response.headers, &response.body)
+    }
+
+    fn manage_topic<S>(
+        &self,
+        operation: TopicOperation,
+        api_key: &str,
+        topic: &str,
+        tokens: &[S],
+    ) -> Result<TopicManagementResponse, FcmError>
+    where
+        S: AsRef<str>,
+    {
+        let payload = topic_management_payload(topic, tokens)?;
+
+        let request = HttpRequest::post_json(
+            self.endpoints.topic_management(operation),
+            &format!("key={}", api_key),
+            payload,
+        )?;
+        let response = self.execute(request)?;
+
+        topic_management_response(response.status, &response.headers, &response.body)
+    }
+
+    fn execute(&self, request: HttpRequest) -> Result<HttpResponse, FcmError> {
+        let request = self
+            .http_client
+            .request(request.method, &request.url)
+            .headers(request.headers)
+            .body(request.body)
+            .build()?;
+        let response = self.http_client.execute(request)?;
+
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.bytes()?.to_vec();
+
+        Ok(HttpResponse { status, headers, body })
+    }
+}
@@ -0,0 +1,120 @@
+//! Sending many messages with a bounded number of requests in flight.
+//!
+//! Enable the `bulk` feature to use it.
+
+use crate::client::Client;
+use crate::message::Message;
+use crate::{FcmError, FcmResponse};
+use futures::stream::{self, AbortHandle, Stream, StreamExt};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+type Results<'a, I> = Pin<Box<dyn Stream<Item = (I, Result<FcmResponse, FcmError>)> + Send + 'a>>;
+
+/// The results of [Client::send_all](struct.Client.html#method.send_all), in
+/// the order the requests complete.
+///
+/// Dropping the stream cancels the requests in flight. To stop sending
+/// without losing their results, call [cancel](#method.cancel) and keep
+/// polling the stream until it ends.
+#[must_use = "streams do nothing unless polled"]
+pub struct BulkSend<'a, I> {
+    results: Results<'a, I>,
+    abort: AbortHandle,
+}
+
+impl<'a, I> BulkSend<'a, I> {
+    /// Stop taking messages from the producer. The requests already in
+    /// flight complete and are yielded before the stream ends.
+    pub fn cancel(&self) {
+        self.abort.abort();
+    }
+
+    /// A handle to [cancel](struct.BulkCancel.html#method.cancel) the bulk
+    /// send from elsewhere, e.g. another task.
+    pub fn canceller(&self) -> BulkCancel {
+        BulkCancel {
+            abort: self.abort.clone(),
+        }
+    }
+}
+
+impl<'a, I> Stream for BulkSend<'a, I> {
+    type Item = (I, Result<FcmResponse, FcmError>);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.results.as_mut().poll_next(cx)
+    }
+}
+
+/// Cancels a [BulkSend](struct.BulkSend.html), see
+/// [BulkSend::cancel](struct.BulkSend.html#method.cancel).
+#[derive(Debug, Clone)]
+pub struct BulkCancel {
+    abort: AbortHandle,
+}
+
+impl BulkCancel {
+    /// Stop taking messages from the producer of the bulk send.
+    pub fn cancel(&self) {
+        self.abort.abort();
+    }
+}
+
+impl Client {
+    /// Send the messages of a stream, each with a correlation id, keeping at
+    /// most `concurrency` requests in flight. The producer is only polled
+    /// when a request slot is free, so a slow send slows it down.
+    ///
+    /// Yields each correlation id with the result of its message, in the
+    /// order the requests complete. A `concurrency` of zero is treated as
+    /// one.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use fcm::{Client, MessageBuilder};
+    /// use futures::stream::{self, StreamExt};
+    ///
+    /// # async fn run() {
+    /// let client = Client::new();
+    /// let tokens = vec!["token1".to_string(), "token2".to_string()];
+    ///
+    /// let messages = stream::iter(tokens.iter().enumerate())
+    ///     .map(|(i, token)| (i, MessageBuilder::new("<FCM API Key>", token).finalize()));
+    ///
+    /// let mut results = client.send_all(messages, 100);
+    ///
+    /// while let Some((i, result)) = results.next().await {
+    ///     println!("{}: {:?}", tokens[i], result);
+    /// }
+    /// # }
+    /// ```
+    pub fn send_all<'a, I, S>(&'a self, messages: S, concurrency: usize) -> BulkSend<'a, I>
+    where
+        S: Stream<Item = (I, Message<'a>)> + Send + 'a,
+        I: Send + 'a,
+    {
+        let (messages, abort) = stream::abortable(messages);
+
+        let results = messages
+            .map(move |(id, message)| async move { (id, self.send(message).await) })
+            .buffer_unordered(concurrency.max(1));
+
+        BulkSend {
+            results: Box::pin(results),
+            abort,
+        }
+    }
+
+    /// Like [send_all](#method.send_all), but takes the messages from an
+    /// iterator.
+    pub fn send_all_iter<'a, I, M>(&'a self, messages: M, concurrency: usize) -> BulkSend<'a, I>
+    where
+        M: IntoIterator<Item = (I, Message<'a>)>,
+        M::IntoIter: Send + 'a,
+        I: Send + 'a,
+    {
+        self.send_all(stream::iter(messages), concurrency)
+    }
+}
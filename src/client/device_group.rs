@@ -0,0 +1,139 @@
+use crate::client::transport::HttpRequest;
+use crate::client::{json_response, Client, Endpoint, Endpoints};
+use crate::{DeviceGroupResponse, FcmError};
+use http::header::{HeaderName, HeaderValue};
+use serde_json::json;
+
+impl Client {
+    /// Create a device group called `name` with the given registration
+    /// tokens, and get its notification key. Device groups are managed for
+    /// the sender id of the project, given as `sender_id`.
+    pub async fn create_device_group<S>(
+        &self,
+        api_key: &str,
+        sender_id: &str,
+        name: &str,
+        tokens: &[S],
+    ) -> Result<DeviceGroupResponse, FcmError>
+    where
+        S: AsRef<str>,
+    {
+        let operation = DeviceGroupOperation::Create;
+        self.manage_device_group(operation, api_key, sender_id, name, None, tokens)
+            .await
+    }
+
+    /// Add registration tokens to the device group with `notification_key`.
+    pub async fn add_to_device_group<S>(
+        &self,
+        api_key: &str,
+        sender_id: &str,
+        name: &str,
+        notification_key: &str,
+        tokens: &[S],
+    ) -> Result<DeviceGroupResponse, FcmError>
+    where
+        S: AsRef<str>,
+    {
+        let operation = DeviceGroupOperation::Add;
+        self.manage_device_group(operation, api_key, sender_id, name, Some(notification_key), tokens)
+            .await
+    }
+
+    /// Remove registration tokens from the device group with
+    /// `notification_key`. FCM deletes the group when its last token is
+    /// removed.
+    pub async fn remove_from_device_group<S>(
+        &self,
+        api_key: &str,
+        sender_id: &str,
+        name: &str,
+        notification_key: &str,
+        tokens: &[S],
+    ) -> Result<DeviceGroupResponse, FcmError>
+    where
+        S: AsRef<str>,
+    {
+        let operation = DeviceGroupOperation::Remove;
+        self.manage_device_group(operation, api_key, sender_id, name, Some(notification_key), tokens)
+            .await
+    }
+
+    async fn manage_device_group<S>(
+        &self,
+        operation: DeviceGroupOperation,
+        api_key: &str,
+        sender_id: &str,
+        name: &str,
+        notification_key: Option<&str>,
+        tokens: &[S],
+    ) -> Result<DeviceGroupResponse, FcmError>
+    where
+        S: AsRef<str>,
+    {
+        let request = device_group_request(
+            &self.endpoints,
+            operation,
+            api_key,
+            sender_id,
+            name,
+            notification_key,
+            tokens,
+        )?;
+        let response = self.execute(Endpoint::DeviceGroup, request).await?;
+
+        json_response(response.status, &response.headers, &response.body)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum DeviceGroupOperation {
+    Create,
+    Add,
+    Remove,
+}
+
+impl DeviceGroupOperation {
+    fn as_str(self) -> &'static str {
+        match self {
+            DeviceGroupOperation::Create => "create",
+            DeviceGroupOperation::Add => "add",
+            DeviceGroupOperation::Remove => "remove",
+        }
+    }
+}
+
+pub(crate) fn device_group_request<S>(
+    endpoints: &Endpoints,
+    operation: DeviceGroupOperation,
+    api_key: &str,
+    sender_id: &str,
+    name: &str,
+    notification_key: Option<&str>,
+    tokens: &[S],
+) -> Result<HttpRequest, FcmError>
+where
+    S: AsRef<str>,
+{
+    let tokens: Vec<&str> = tokens.iter().map(AsRef::as_ref).collect();
+
+    let mut payload = json!({
+        "operation": operation.as_str(),
+        "notification_key_name": name,
+        "registration_ids": tokens,
+    });
+
+    if let Some(notification_key) = notification_key {
+        payload["notification_key"] = json!(notification_key);
+    }
+
+    let payload = serde_json::to_vec(&payload).map_err(FcmError::Serialization)?;
+    let mut request = HttpRequest::post_json(endpoints.device_group(), &format!("key={}", api_key), payload)?;
+
+    request.headers.insert(
+        HeaderName::from_static("project_id"),
+        HeaderValue::from_str(sender_id).map_err(|e| FcmError::Request(e.into()))?,
+    );
+
+    Ok(request)
+}
@@ -0,0 +1,141 @@
+#[cfg(feature = "tracing")]
+use crate::client::trace;
+use crate::client::transport::HttpRequest;
+use crate::client::{error_response, Client, Endpoint, TargetKind};
+use crate::v1::{self, Credentials, SendResponse, TokenResponse};
+use crate::{FcmError, RawResponse};
+use http::{HeaderMap, StatusCode};
+
+impl Client {
+    /// Try sending a v1 `Message` to FCM, authorized by `credentials`.
+    ///
+    /// Errors reported by the v1 API are returned as `FcmError::V1` with
+    /// their typed details.
+    pub async fn send_v1(&self, credentials: &Credentials, message: &v1::Message) -> Result<SendResponse, FcmError> {
+        instrument!(trace::v1_send(credentials.project_id(), message), async move {
+            let payload = message.request_body().map_err(FcmError::Serialization)?;
+            let token = self.access_token(credentials).await?;
+
+            #[cfg(feature = "rate-limit")]
+            self.acquire_quota(credentials.project_id(), 1).await;
+
+            if let Some(ref observer) = self.observer {
+                observer.on_send(Endpoint::V1Send, TargetKind::from(&message.target));
+            }
+
+            let request = HttpRequest::post_json(
+                self.endpoints.v1_send(credentials.project_id()),
+                &format!("Bearer {}", token),
+                payload,
+            )?;
+            let response = self.execute(Endpoint::V1Send, request).await?;
+            let result = v1_response(response.status, &response.headers, &response.body);
+
+            #[cfg(feature = "rate-limit")]
+            self.observe_quota(credentials.project_id(), response.status, &result);
+
+            #[cfg(feature = "tracing")]
+            trace::v1_outcome(&result);
+
+            result
+        })
+        .await
+    }
+
+    /// Wait until the project quota allows sending `count` messages.
+    #[cfg(feature = "rate-limit")]
+    pub(crate) async fn acquire_quota(&self, project_id: &str, count: usize) {
+        if let Some(ref quota) = self.quota {
+            for _ in 0..count {
+                quota.acquire(project_id).await;
+            }
+        }
+    }
+
+    /// Pause the sends of the project if FCM answered with a 429, for the
+    /// delay it asked for.
+    #[cfg(feature = "rate-limit")]
+    pub(crate) fn observe_quota<T>(&self, project_id: &str, status: StatusCode, result: &Result<T, FcmError>) {
+        if let (Some(quota), Err(e)) = (&self.quota, result) {
+            if status == StatusCode::TOO_MANY_REQUESTS {
+                let delay = e
+                    .retry_after()
+                    .map(|retry_after| retry_after.wait_duration(chrono::Utc::now()));
+                quota.pause(project_id, delay);
+            }
+        }
+    }
+
+    /// Get an access token for `credentials`, fetching a new one from the
+    /// OAuth token endpoint if the cached token expires soon.
+    pub(crate) async fn access_token(&self, credentials: &Credentials) -> Result<String, FcmError> {
+        if let Some(token) = credentials.cached_token() {
+            return Ok(token);
+        }
+
+        #[cfg(feature = "service-account")]
+        let _refresh = {
+            let guard = credentials.lock_refresh().await;
+
+            // Another send may have refreshed the token while this one waited.
+            if let Some(token) = credentials.cached_token() {
+                return Ok(token);
+            }
+
+            guard
+        };
+
+        let body = credentials
+            .token_request_body()?
+            .ok_or_else(|| FcmError::InvalidCredentials("credentials cannot fetch access tokens".into()))?;
+
+        let token = instrument!(trace::token_refresh(credentials.project_id()), async move {
+            let result = self.fetch_access_token(body).await;
+
+            #[cfg(feature = "tracing")]
+            trace::token_refresh_outcome(&result);
+
+            result
+        })
+        .await?;
+
+        credentials.cache_token(&token);
+
+        Ok(token.access_token)
+    }
+
+    async fn fetch_access_token(&self, body: Vec<u8>) -> Result<TokenResponse, FcmError> {
+        let request = HttpRequest::post_form(self.endpoints.oauth_token.clone(), body);
+        let response = self.execute(Endpoint::OAuthToken, request).await?;
+
+        if response.status != StatusCode::OK {
+            return Err(
+                match error_response(response.status, &response.headers, &response.body) {
+                    FcmError::BadRequest(raw) => {
+                        FcmError::InvalidCredentials(format!("token request failed: {}", raw.body).into())
+                    }
+                    error => error,
+                },
+            );
+        }
+
+        serde_json::from_slice(&response.body).map_err(|e| {
+            FcmError::InvalidResponse(
+                RawResponse::new(response.status.as_u16(), &response.headers, &response.body),
+                e,
+            )
+        })
+    }
+}
+
+pub(crate) fn v1_response(status: StatusCode, headers: &HeaderMap, body: &[u8]) -> Result<SendResponse, FcmError> {
+    if status == StatusCode::OK {
+        return serde_json::from_slice(body)
+            .map_err(|e| FcmError::InvalidResponse(RawResponse::new(status.as_u16(), headers, body), e));
+    }
+
+    match v1::Status::from_response_body(body) {
+        Ok(status) => Err(FcmError::V1(status)),
+        Err(_) => Err(error_response(status, headers, body)),
+    }
+}
@@ -1,16 +1,234 @@
 pub mod response;
 
+pub mod transport;
+
+/// Run `future` in the span of `$span` with the `tracing` feature. The
+/// span is not evaluated without it.
+#[cfg(feature = "tracing")]
+macro_rules! instrument {
+    ($span:expr, $future:expr) => {{
+        let span = $span;
+        tracing::Instrument::instrument($future, span)
+    }};
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! instrument {
+    ($span:expr, $future:expr) => {
+        $future
+    };
+}
+
+mod device_group;
+mod http_v1;
+mod observer;
+#[cfg(feature = "tracing")]
+mod trace;
+
+#[cfg(feature = "bulk")]
+mod bulk;
+pub(crate) mod multipart;
+mod send_each;
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+
+#[cfg(test)]
+mod tests;
+
+pub use crate::client::observer::*;
 pub use crate::client::response::*;
 
+#[cfg(feature = "bulk")]
+pub use crate::client::bulk::*;
+
+use crate::client::transport::{HttpRequest, HttpResponse, Transport};
 use crate::message::Message;
-use reqwest::header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, RETRY_AFTER};
-use reqwest::{Body, StatusCode};
+#[cfg(feature = "rate-limit")]
+use crate::rate_limit::{ProjectQuota, QuotaTracker};
+use crate::v1::BatchStrategy;
+use http::header::{HeaderMap, RETRY_AFTER};
+use http::StatusCode;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Where the client sends its requests. Point all of them at a single base
+/// URL with [all](#method.all), e.g. to use a local fake server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoints {
+    /// Base URL of the legacy `/fcm/send` and the v1 `/v1/projects` APIs.
+    pub fcm: String,
+
+    /// Base URL of the Instance ID API used for topic management.
+    pub iid: String,
+
+    /// URL of the OAuth token endpoint used by service account credentials.
+    pub oauth_token: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Endpoints {
+            fcm: "https://fcm.googleapis.com".to_string(),
+            iid: "https://iid.googleapis.com".to_string(),
+            oauth_token: "https://oauth2.googleapis.com/token".to_string(),
+        }
+    }
+}
+
+impl Endpoints {
+    /// Serve all APIs from `base_url`, with the OAuth token endpoint at
+    /// `/token`.
+    pub fn all(base_url: &str) -> Endpoints {
+        let base_url = base_url.trim_end_matches('/');
+
+        Endpoints {
+            fcm: base_url.to_string(),
+            iid: base_url.to_string(),
+            oauth_token: format!("{}/token", base_url),
+        }
+    }
+
+    pub(crate) fn legacy_send(&self) -> String {
+        format!("{}/fcm/send", self.fcm)
+    }
+
+    pub(crate) fn v1_send(&self, project_id: &str) -> String {
+        format!("{}/v1/projects/{}/messages:send", self.fcm, project_id)
+    }
+
+    pub(crate) fn topic_management(&self, operation: TopicOperation) -> String {
+        match operation {
+            TopicOperation::Subscribe => format!("{}/iid/v1:batchAdd", self.iid),
+            TopicOperation::Unsubscribe => format!("{}/iid/v1:batchRemove", self.iid),
+        }
+    }
+
+    pub(crate) fn instance_info(&self, token: &str) -> String {
+        format!("{}/iid/info/{}?details=true", self.iid, token)
+    }
+
+    pub(crate) fn device_group(&self) -> String {
+        format!("{}/fcm/notification", self.fcm)
+    }
+
+    pub(crate) fn batch(&self) -> String {
+        format!("{}/batch", self.fcm)
+    }
+}
+
+/// A builder to get a `Client` with a custom transport or endpoints.
+///
+/// # Examples
+///
+/// ```rust
+/// use fcm::{ClientBuilder, Endpoints};
+///
+/// let mut builder = ClientBuilder::new();
+/// builder.endpoints(Endpoints::all("http://127.0.0.1:8080"));
+/// let client = builder.finalize().unwrap();
+/// ```
+#[derive(Default)]
+pub struct ClientBuilder {
+    transport: Option<Arc<dyn Transport>>,
+    endpoints: Endpoints,
+    observer: Option<Arc<dyn SendObserver>>,
+    #[cfg(feature = "rate-limit")]
+    project_quota: Option<ProjectQuota>,
+    batch_strategy: BatchStrategy,
+}
+
+impl ClientBuilder {
+    /// Get a new builder with the default transport and endpoints.
+    pub fn new() -> ClientBuilder {
+        Self::default()
+    }
+
+    /// Send requests through `transport` instead of the default
+    /// `ReqwestTransport`.
+    pub fn transport<T>(&mut self, transport: T) -> &mut Self
+    where
+        T: Transport,
+    {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// Send requests to `endpoints` instead of the Google APIs.
+    pub fn endpoints(&mut self, endpoints: Endpoints) -> &mut Self {
+        self.endpoints = endpoints;
+        self
+    }
+
+    /// Report sends, responses and their latency to `observer`.
+    pub fn observer<O>(&mut self, observer: O) -> &mut Self
+    where
+        O: SendObserver,
+    {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Pace v1 sends to stay below `quota` for each project. The budget is
+    /// shared by all clones of the client, and a 429 response pauses the
+    /// sends of its project for the `RetryInfo` delay FCM asks for.
+    #[cfg(feature = "rate-limit")]
+    pub fn project_quota(&mut self, quota: ProjectQuota) -> &mut Self {
+        self.project_quota = Some(quota);
+        self
+    }
+
+    /// How [Client::send_each](struct.Client.html#method.send_each) sends
+    /// its messages. Defaults to one request per message.
+    pub fn batch_strategy(&mut self, strategy: BatchStrategy) -> &mut Self {
+        self.batch_strategy = strategy;
+        self
+    }
+
+    /// Complete the build and get a `Client` instance, or an error if the
+    /// default transport cannot be initialized.
+    pub fn finalize(self) -> Result<Client, FcmError> {
+        let transport = match self.transport {
+            Some(transport) => transport,
+            #[cfg(feature = "reqwest")]
+            None => Arc::new(transport::ReqwestTransport::new()?),
+            #[cfg(not(feature = "reqwest"))]
+            None => return Err(FcmError::Request("no transport configured".into())),
+        };
+
+        Ok(Client {
+            transport,
+            endpoints: Arc::new(self.endpoints),
+            observer: self.observer,
+            #[cfg(feature = "rate-limit")]
+            quota: self.project_quota.map(|quota| Arc::new(QuotaTracker::new(quota))),
+            batch_strategy: self.batch_strategy,
+        })
+    }
+}
 
 /// An async client for sending the notification payload.
+///
+/// Cloning the client is cheap, all clones share the same
+/// [Transport](transport/trait.Transport.html).
+///
+/// With the `tracing` feature, sends, topic management and access token
+/// refreshes each run in a span named `fcm.*`, which records the target
+/// kind, token count, HTTP status and the ids FCM assigns. Tokens and keys
+/// are never recorded.
+#[derive(Clone)]
 pub struct Client {
-    http_client: reqwest::Client,
+    transport: Arc<dyn Transport>,
+    endpoints: Arc<Endpoints>,
+    observer: Option<Arc<dyn SendObserver>>,
+    #[cfg(feature = "rate-limit")]
+    quota: Option<Arc<QuotaTracker>>,
+    batch_strategy: BatchStrategy,
 }
 
+#[cfg(feature = "reqwest")]
 impl Default for Client {
     fn default() -> Self {
         Self::new()
@@ -19,51 +237,253 @@ impl Default for Client {
 
 impl Client {
     /// Get a new instance of Client.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the TLS backend cannot be initialized. Use
+    /// [try_new](#method.try_new) to handle that case.
+    #[cfg(feature = "reqwest")]
     pub fn new() -> Client {
-        let http_client = reqwest::ClientBuilder::new()
-            .pool_max_idle_per_host(std::usize::MAX)
-            .build()
-            .unwrap();
+        Self::try_new().expect("failed to initialize the HTTP client")
+    }
 
-        Client { http_client }
+    /// Get a new instance of Client, or an error if the underlying HTTP
+    /// client cannot be initialized.
+    #[cfg(feature = "reqwest")]
+    pub fn try_new() -> Result<Client, FcmError> {
+        ClientBuilder::new().finalize()
     }
 
-    /// Try sending a `Message` to FCM.
+    /// Get a new instance of Client that sends its requests through
+    /// `transport`.
+    pub fn with_transport<T>(transport: T) -> Client
+    where
+        T: Transport,
+    {
+        Client {
+            transport: Arc::new(transport),
+            endpoints: Arc::new(Endpoints::default()),
+            observer: None,
+            #[cfg(feature = "rate-limit")]
+            quota: None,
+            batch_strategy: BatchStrategy::default(),
+        }
+    }
+
+    /// Try sending a `Message` to FCM. A message built with
+    /// [MessageBuilder::new_multi](struct.MessageBuilder.html#method.new_multi)
+    /// is sent to all of its registration ids at once, with one
+    /// [MessageResult](struct.MessageResult.html) per id in the response.
     pub async fn send(&self, message: Message<'_>) -> Result<FcmResponse, FcmError> {
-        let payload = serde_json::to_vec(&message.body).unwrap();
-
-        let request = self
-            .http_client
-            .post("https://fcm.googleapis.com/fcm/send")
-            .header(CONTENT_TYPE, "application/json")
-            .header(CONTENT_LENGTH, format!("{}", payload.len() as u64).as_bytes())
-            .header(AUTHORIZATION, format!("key={}", message.api_key).as_bytes())
-            .body(Body::from(payload))
-            .build()?;
-        let response = self.http_client.execute(request).await?;
-
-        let response_status = response.status();
-
-        let retry_after = response
-            .headers()
-            .get(RETRY_AFTER)
-            .and_then(|ra| ra.to_str().ok())
-            .and_then(|ra| ra.parse::<RetryAfter>().ok());
-
-        match response_status {
-            StatusCode::OK => {
-                let fcm_response: FcmResponse = response.json().await.unwrap();
-
-                match fcm_response.error {
-                    Some(ErrorReason::Unavailable) => Err(response::FcmError::ServerError(retry_after)),
-                    Some(ErrorReason::InternalServerError) => Err(response::FcmError::ServerError(retry_after)),
-                    _ => Ok(fcm_response),
-                }
+        let payload = serde_json::to_vec(&message.body).map_err(FcmError::Serialization)?;
+
+        self.send_payload(message.api_key, payload).await
+    }
+
+    /// Send an already serialized legacy message body.
+    pub(crate) async fn send_payload(&self, api_key: &str, payload: Vec<u8>) -> Result<FcmResponse, FcmError> {
+        #[cfg(feature = "tracing")]
+        let body = serde_json::from_slice(&payload).unwrap_or(serde_json::Value::Null);
+
+        instrument!(trace::legacy_send(&body), async move {
+            if let Some(ref observer) = self.observer {
+                let target = serde_json::from_slice(&payload)
+                    .map(|body| TargetKind::of_legacy(&body))
+                    .unwrap_or(TargetKind::Unknown);
+                observer.on_send(Endpoint::LegacySend, target);
             }
-            StatusCode::UNAUTHORIZED => Err(response::FcmError::Unauthorized),
-            StatusCode::BAD_REQUEST => Err(response::FcmError::InvalidMessage("Bad Request".to_string())),
-            status if status.is_server_error() => Err(response::FcmError::ServerError(retry_after)),
-            _ => Err(response::FcmError::InvalidMessage("Unknown Error".to_string())),
+
+            let request = HttpRequest::post_json(self.endpoints.legacy_send(), &format!("key={}", api_key), payload)?;
+            let response = self.execute(Endpoint::LegacySend, request).await?;
+            let result = send_response(response.status, &response.headers, &response.body);
+
+            #[cfg(feature = "tracing")]
+            trace::legacy_outcome(&result);
+
+            if let (Some(observer), Ok(Some(results))) = (&self.observer, result.as_ref().map(|r| &r.results)) {
+                observer.on_results(results);
+            }
+
+            result
+        })
+        .await
+    }
+
+    /// Execute `request` with the transport, reporting its status and
+    /// latency to the observer.
+    pub(crate) async fn execute(&self, endpoint: Endpoint, request: HttpRequest) -> Result<HttpResponse, FcmError> {
+        let started = Instant::now();
+        let result = self.transport.execute(request).await;
+
+        #[cfg(feature = "tracing")]
+        trace::response(&result);
+
+        if let Some(ref observer) = self.observer {
+            let status = result.as_ref().ok().map(|response| response.status);
+            observer.on_response(endpoint, status, started.elapsed());
         }
+
+        result
+    }
+
+    /// The observer of the client, if one is set.
+    #[cfg_attr(not(feature = "outbox"), allow(dead_code))]
+    pub(crate) fn observer(&self) -> Option<&dyn SendObserver> {
+        self.observer.as_deref()
+    }
+
+    /// Subscribe registration tokens to a topic. The topic can be given with
+    /// or without the `/topics/` prefix. FCM accepts up to 1000 tokens per
+    /// request.
+    pub async fn subscribe_to_topic<S>(
+        &self,
+        api_key: &str,
+        topic: &str,
+        tokens: &[S],
+    ) -> Result<TopicManagementResponse, FcmError>
+    where
+        S: AsRef<str>,
+    {
+        self.manage_topic(TopicOperation::Subscribe, api_key, topic, tokens)
+            .await
+    }
+
+    /// Unsubscribe registration tokens from a topic. The topic can be given
+    /// with or without the `/topics/` prefix. FCM accepts up to 1000 tokens
+    /// per request.
+    pub async fn unsubscribe_from_topic<S>(
+        &self,
+        api_key: &str,
+        topic: &str,
+        tokens: &[S],
+    ) -> Result<TopicManagementResponse, FcmError>
+    where
+        S: AsRef<str>,
+    {
+        self.manage_topic(TopicOperation::Unsubscribe, api_key, topic, tokens)
+            .await
+    }
+
+    /// Get the Instance ID details of a registration token, including the
+    /// topics it is subscribed to.
+    pub async fn token_info(&self, api_key: &str, token: &str) -> Result<TokenInfo, FcmError> {
+        let request = HttpRequest::get(self.endpoints.instance_info(token), &format!("key={}", api_key))?;
+        let response = self.execute(Endpoint::InstanceInfo, request).await?;
+
+        json_response(response.status, &response.headers, &response.body)
+    }
+
+    async fn manage_topic<S>(
+        &self,
+        operation: TopicOperation,
+        api_key: &str,
+        topic: &str,
+        tokens: &[S],
+    ) -> Result<TopicManagementResponse, FcmError>
+    where
+        S: AsRef<str>,
+    {
+        instrument!(trace::topic_management(operation, topic, tokens.len()), async move {
+            let payload = topic_management_payload(topic, tokens)?;
+
+            let request = HttpRequest::post_json(
+                self.endpoints.topic_management(operation),
+                &format!("key={}", api_key),
+                payload,
+            )?;
+            let response = self.execute(Endpoint::TopicManagement, request).await?;
+            let result = topic_management_response(response.status, &response.headers, &response.body);
+
+            #[cfg(feature = "tracing")]
+            trace::topic_management_outcome(&result);
+
+            result
+        })
+        .await
     }
 }
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum TopicOperation {
+    Subscribe,
+    Unsubscribe,
+}
+
+pub(crate) fn topic_management_payload<S>(topic: &str, tokens: &[S]) -> Result<Vec<u8>, FcmError>
+where
+    S: AsRef<str>,
+{
+    let topic = topic.strip_prefix("/topics/").unwrap_or(topic);
+    let tokens: Vec<&str> = tokens.iter().map(AsRef::as_ref).collect();
+
+    let payload = json!({
+        "to": format!("/topics/{}", topic),
+        "registration_tokens": tokens,
+    });
+
+    serde_json::to_vec(&payload).map_err(FcmError::Serialization)
+}
+
+fn retry_after(headers: &HeaderMap) -> Option<RetryAfter> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?;
+
+    match value.parse::<RetryAfter>() {
+        Ok(retry_after) => Some(retry_after),
+        Err(e) => {
+            log::warn!("ignoring {}", e);
+            None
+        }
+    }
+}
+
+/// Turn a response that is not a success into the matching error.
+pub(crate) fn error_response(status: StatusCode, headers: &HeaderMap, body: &[u8]) -> FcmError {
+    match status {
+        StatusCode::UNAUTHORIZED => FcmError::Unauthorized,
+        status if status.is_server_error() => FcmError::ServerError(retry_after(headers)),
+        StatusCode::BAD_REQUEST => FcmError::BadRequest(RawResponse::new(status.as_u16(), headers, body)),
+        StatusCode::FORBIDDEN => FcmError::Forbidden(RawResponse::new(status.as_u16(), headers, body)),
+        StatusCode::TOO_MANY_REQUESTS => {
+            FcmError::TooManyRequests(retry_after(headers), RawResponse::new(status.as_u16(), headers, body))
+        }
+        _ => FcmError::UnexpectedStatus(RawResponse::new(status.as_u16(), headers, body)),
+    }
+}
+
+pub(crate) fn send_response(status: StatusCode, headers: &HeaderMap, body: &[u8]) -> Result<FcmResponse, FcmError> {
+    if status != StatusCode::OK {
+        return Err(error_response(status, headers, body));
+    }
+
+    let fcm_response: FcmResponse = serde_json::from_slice(body)
+        .map_err(|e| FcmError::InvalidResponse(RawResponse::new(status.as_u16(), headers, body), e))?;
+
+    fcm_response.log_unknown();
+
+    match fcm_response.error {
+        Some(ErrorReason::Unavailable) => Err(response::FcmError::ServerError(retry_after(headers))),
+        Some(ErrorReason::InternalServerError) => Err(response::FcmError::ServerError(retry_after(headers))),
+        _ => Ok(fcm_response),
+    }
+}
+
+pub(crate) fn topic_management_response(
+    status: StatusCode,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<TopicManagementResponse, FcmError> {
+    json_response(status, headers, body)
+}
+
+/// Parse the JSON body of a successful response.
+pub(crate) fn json_response<T>(status: StatusCode, headers: &HeaderMap, body: &[u8]) -> Result<T, FcmError>
+where
+    T: DeserializeOwned,
+{
+    if status != StatusCode::OK {
+        return Err(error_response(status, headers, body));
+    }
+
+    serde_json::from_slice(body)
+        .map_err(|e| FcmError::InvalidResponse(RawResponse::new(status.as_u16(), headers, body), e))
+}
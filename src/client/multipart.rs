@@ -0,0 +1,304 @@
+//! The `multipart/mixed` bodies of the v1 batch endpoint.
+//!
+//! A batch request wraps one `messages:send` request per message in a part
+//! of type `application/http`, and the response wraps the HTTP response to
+//! each of them in the same way.
+
+use crate::client::http_v1::v1_response;
+#[cfg(feature = "tracing")]
+use crate::client::trace;
+use crate::client::transport::HttpRequest;
+use crate::client::{error_response, Client, Endpoint, Endpoints, TargetKind};
+use crate::v1::{self, Credentials, SendResponse};
+use crate::{FcmError, RawResponse};
+use http::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE};
+use http::{Method, StatusCode};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static BOUNDARY_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+impl Client {
+    /// Send all messages in a single request to the v1 batch endpoint.
+    pub(crate) async fn send_batch(
+        &self,
+        credentials: &Credentials,
+        messages: &[v1::Message],
+    ) -> Result<Vec<Result<SendResponse, FcmError>>, FcmError> {
+        instrument!(trace::batch(credentials.project_id(), messages.len()), async move {
+            let token = self.access_token(credentials).await?;
+
+            #[cfg(feature = "rate-limit")]
+            self.acquire_quota(credentials.project_id(), messages.len()).await;
+
+            let request = batch_request(&self.endpoints, credentials.project_id(), &token, messages)?;
+
+            if let Some(ref observer) = self.observer {
+                for message in messages {
+                    observer.on_send(Endpoint::Batch, TargetKind::from(&message.target));
+                }
+            }
+
+            let response = self.execute(Endpoint::Batch, request).await?;
+            let result = batch_response(response.status, &response.headers, &response.body, messages.len());
+
+            #[cfg(feature = "rate-limit")]
+            {
+                self.observe_quota(credentials.project_id(), response.status, &result);
+
+                if let Ok(ref responses) = result {
+                    for response in responses {
+                        if let Err(FcmError::V1(ref status)) = response {
+                            let status = StatusCode::from_u16(status.code).unwrap_or(StatusCode::OK);
+                            self.observe_quota(credentials.project_id(), status, response);
+                        }
+                    }
+                }
+            }
+
+            #[cfg(feature = "tracing")]
+            trace::batch_outcome(&result);
+
+            result
+        })
+        .await
+    }
+}
+
+/// A boundary that is unique for each batch of the process.
+fn boundary() -> String {
+    let count = BOUNDARY_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let nanos = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default();
+
+    format!("fcm_batch_{:x}_{:x}", nanos, count)
+}
+
+/// A batch request with one part per message, authorized with `token`.
+pub(crate) fn batch_request(
+    endpoints: &Endpoints,
+    project_id: &str,
+    token: &str,
+    messages: &[v1::Message],
+) -> Result<HttpRequest, FcmError> {
+    let boundary = boundary();
+    let path = format!("/v1/projects/{}/messages:send", project_id);
+    let mut body = Vec::new();
+
+    for (i, message) in messages.iter().enumerate() {
+        let payload = message.request_body().map_err(FcmError::Serialization)?;
+
+        body.extend_from_slice(
+            format!(
+                "--{}\r\n\
+                 Content-Type: application/http\r\n\
+                 Content-Transfer-Encoding: binary\r\n\
+                 Content-ID: {}\r\n\
+                 \r\n\
+                 POST {} HTTP/1.1\r\n\
+                 Content-Type: application/json\r\n\
+                 Content-Length: {}\r\n\
+                 \r\n",
+                boundary,
+                i + 1,
+                path,
+                payload.len()
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(&payload);
+        body.extend_from_slice(b"\r\n");
+    }
+
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+    let content_type = format!("multipart/mixed; boundary={}", boundary);
+    let mut headers = HeaderMap::new();
+
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_str(&content_type).map_err(|e| FcmError::Request(e.into()))?,
+    );
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(body.len() as u64));
+    headers.insert(
+        AUTHORIZATION,
+        HeaderValue::from_str(&format!("Bearer {}", token)).map_err(|e| FcmError::Request(e.into()))?,
+    );
+
+    Ok(HttpRequest {
+        method: Method::POST,
+        url: endpoints.batch(),
+        headers,
+        body,
+    })
+}
+
+/// Parse a batch response into one result per message, in the order of the
+/// messages. Parts that cannot be parsed, and messages without a part,
+/// become `FcmError::InvalidResponse`.
+pub(crate) fn batch_response(
+    status: StatusCode,
+    headers: &HeaderMap,
+    body: &[u8],
+    count: usize,
+) -> Result<Vec<Result<SendResponse, FcmError>>, FcmError> {
+    if status != StatusCode::OK {
+        return Err(match v1::Status::from_response_body(body) {
+            Ok(status) => FcmError::V1(status),
+            Err(_) => error_response(status, headers, body),
+        });
+    }
+
+    let invalid = |reason: &str| {
+        FcmError::InvalidResponse(
+            RawResponse::new(status.as_u16(), headers, body),
+            serde::de::Error::custom(reason),
+        )
+    };
+
+    let boundary = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(multipart_boundary)
+        .ok_or_else(|| invalid("not a multipart/mixed response"))?;
+
+    let mut results: Vec<Option<Result<SendResponse, FcmError>>> = (0..count).map(|_| None).collect();
+    let text = String::from_utf8_lossy(body);
+
+    for (position, part) in multipart_parts(&text, &boundary).into_iter().enumerate() {
+        let (index, result) = match parse_part(part) {
+            Ok((content_id, status, headers, body)) => {
+                let index = content_id.and_then(content_index).unwrap_or(position);
+                (index, v1_response(status, &headers, body.as_bytes()))
+            }
+            Err(reason) => (position, Err(invalid(reason))),
+        };
+
+        match results.get_mut(index) {
+            Some(slot @ None) => *slot = Some(result),
+            _ => log::warn!("ignoring unexpected part {} of a batch response", index + 1),
+        }
+    }
+
+    Ok(results
+        .into_iter()
+        .map(|result| result.unwrap_or_else(|| Err(invalid("part missing from the batch response"))))
+        .collect())
+}
+
+/// The bodies of the requests wrapped in a batch request, in the order of
+/// its parts. `None` if the request is not `multipart/mixed`.
+#[cfg_attr(not(feature = "rate-limit"), allow(dead_code))]
+pub(crate) fn batch_request_bodies(headers: &HeaderMap, body: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let boundary = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(multipart_boundary)?;
+    let text = String::from_utf8_lossy(body);
+
+    let bodies = multipart_parts(&text, &boundary)
+        .into_iter()
+        .map(|part| {
+            let http = split_head(part).map(|(_, http)| http).unwrap_or_default();
+            let body = split_head(http).map(|(_, body)| body).unwrap_or_default();
+            body.as_bytes().to_vec()
+        })
+        .collect();
+
+    Some(bodies)
+}
+
+/// The boundary parameter of a `multipart/mixed` content type.
+fn multipart_boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+
+    if !params.next()?.trim().eq_ignore_ascii_case("multipart/mixed") {
+        return None;
+    }
+
+    params
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"').to_string())
+}
+
+/// The parts between the delimiters of a multipart body.
+fn multipart_parts<'a>(body: &'a str, boundary: &str) -> Vec<&'a str> {
+    let delimiter = format!("--{}", boundary);
+
+    body.split(delimiter.as_str())
+        .skip(1)
+        .take_while(|part| !part.starts_with("--"))
+        .map(|part| {
+            let part = part
+                .strip_prefix("\r\n")
+                .or_else(|| part.strip_prefix('\n'))
+                .unwrap_or(part);
+            part.strip_suffix("\r\n")
+                .or_else(|| part.strip_suffix('\n'))
+                .unwrap_or(part)
+        })
+        .collect()
+}
+
+/// Split a head of header lines from the content after the first empty
+/// line.
+fn split_head(text: &str) -> Option<(&str, &str)> {
+    match (text.find("\r\n\r\n"), text.find("\n\n")) {
+        (Some(crlf), Some(lf)) if lf < crlf => Some((&text[..lf], &text[lf + 2..])),
+        (Some(crlf), _) => Some((&text[..crlf], &text[crlf + 4..])),
+        (None, Some(lf)) => Some((&text[..lf], &text[lf + 2..])),
+        (None, None) => None,
+    }
+}
+
+fn parse_headers<'a>(lines: impl Iterator<Item = &'a str>) -> Result<HeaderMap, &'static str> {
+    let mut headers = HeaderMap::new();
+
+    for line in lines.map(str::trim_end).filter(|line| !line.is_empty()) {
+        let (name, value) = line.split_once(':').ok_or("invalid header line in a batch part")?;
+        let name = HeaderName::from_bytes(name.trim().as_bytes()).map_err(|_| "invalid header name in a batch part")?;
+        let value = HeaderValue::from_str(value.trim()).map_err(|_| "invalid header value in a batch part")?;
+
+        headers.append(name, value);
+    }
+
+    Ok(headers)
+}
+
+/// The Content-ID, status, headers and body of a response part.
+fn parse_part(part: &str) -> Result<(Option<String>, StatusCode, HeaderMap, &str), &'static str> {
+    let (part_head, http) = split_head(part).ok_or("batch part without content")?;
+    let part_headers = parse_headers(part_head.lines())?;
+
+    let content_id = part_headers
+        .get("content-id")
+        .and_then(|value| value.to_str().ok())
+        .map(String::from);
+
+    let (head, body) = split_head(http).unwrap_or((http, ""));
+    let mut lines = head.lines();
+
+    let status = lines
+        .next()
+        .and_then(|status_line| {
+            let mut fields = status_line.split_whitespace();
+
+            match fields.next() {
+                Some(version) if version.starts_with("HTTP/") => fields.next(),
+                _ => None,
+            }
+        })
+        .and_then(|code| code.parse::<u16>().ok())
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .ok_or("batch part without an HTTP status line")?;
+
+    Ok((content_id, status, parse_headers(lines)?, body))
+}
+
+/// The message index of a response Content-ID, e.g. `<response-3>` for the
+/// third message.
+fn content_index(content_id: String) -> Option<usize> {
+    let id = content_id.trim_start_matches('<').trim_end_matches('>');
+    let number = id.strip_prefix("response-").unwrap_or(id);
+
+    number.trim().parse::<usize>().ok()?.checked_sub(1)
+}
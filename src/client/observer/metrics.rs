@@ -0,0 +1,67 @@
+use crate::client::observer::{Endpoint, SendObserver, TargetKind};
+use crate::client::response::MessageResult;
+use http::StatusCode;
+use std::time::Duration;
+
+/// A [SendObserver](trait.SendObserver.html) that reports to the
+/// [metrics](https://docs.rs/metrics) crate. Enable the `metrics` feature to
+/// use it.
+///
+/// The following metrics are recorded, in the installed recorder:
+///
+/// - `fcm_messages_sent_total`, a counter labeled by `endpoint` and `target`.
+/// - `fcm_responses_total`, a counter labeled by `endpoint` and `status`,
+///   which is `none` if no response was received.
+/// - `fcm_request_duration_seconds`, a histogram labeled by `endpoint`.
+/// - `fcm_message_results_total`, a counter of legacy per-token results.
+/// - `fcm_message_errors_total`, a counter of legacy per-token errors
+///   labeled by `reason`.
+/// - `fcm_canonical_ids_total`, a counter of legacy per-token results with
+///   a canonical id. Divided by `fcm_message_results_total` it is the
+///   canonical id rate.
+/// - `fcm_retries_total`, a counter labeled by `endpoint`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MetricsObserver;
+
+impl MetricsObserver {
+    /// Get a new observer.
+    pub fn new() -> MetricsObserver {
+        MetricsObserver
+    }
+}
+
+impl SendObserver for MetricsObserver {
+    fn on_send(&self, endpoint: Endpoint, target: TargetKind) {
+        ::metrics::counter!(
+            "fcm_messages_sent_total",
+            "endpoint" => endpoint.as_str(),
+            "target" => target.as_str()
+        )
+        .increment(1);
+    }
+
+    fn on_response(&self, endpoint: Endpoint, status: Option<StatusCode>, latency: Duration) {
+        let status = status.map_or_else(|| "none".to_string(), |status| status.as_u16().to_string());
+
+        ::metrics::counter!("fcm_responses_total", "endpoint" => endpoint.as_str(), "status" => status).increment(1);
+        ::metrics::histogram!("fcm_request_duration_seconds", "endpoint" => endpoint.as_str()).record(latency);
+    }
+
+    fn on_results(&self, results: &[MessageResult]) {
+        ::metrics::counter!("fcm_message_results_total").increment(results.len() as u64);
+
+        for result in results {
+            if let Some(ref error) = result.error {
+                ::metrics::counter!("fcm_message_errors_total", "reason" => error.to_string()).increment(1);
+            }
+
+            if result.registration_id.is_some() {
+                ::metrics::counter!("fcm_canonical_ids_total").increment(1);
+            }
+        }
+    }
+
+    fn on_retry(&self, endpoint: Endpoint, _attempt: u32) {
+        ::metrics::counter!("fcm_retries_total", "endpoint" => endpoint.as_str()).increment(1);
+    }
+}
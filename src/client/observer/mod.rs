@@ -0,0 +1,168 @@
+//! Hooks to observe the requests of a [Client](../struct.Client.html).
+
+use crate::client::response::MessageResult;
+use crate::v1::Target;
+use http::StatusCode;
+use serde_json::Value;
+use std::fmt;
+use std::time::Duration;
+
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(feature = "metrics")]
+pub use self::metrics::*;
+
+/// The API a request is sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+    /// The legacy `/fcm/send` API.
+    LegacySend,
+
+    /// The v1 `messages:send` API.
+    V1Send,
+
+    /// The v1 batch API, sending several messages in one request.
+    Batch,
+
+    /// The Instance ID API to subscribe to and unsubscribe from topics.
+    TopicManagement,
+
+    /// The Instance ID API to get the details of a registration token.
+    InstanceInfo,
+
+    /// The legacy device group API.
+    DeviceGroup,
+
+    /// The OAuth endpoint issuing access tokens for the v1 API.
+    OAuthToken,
+}
+
+impl Endpoint {
+    /// A short name of the endpoint, e.g. to label metrics.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Endpoint::LegacySend => "legacy_send",
+            Endpoint::V1Send => "v1_send",
+            Endpoint::Batch => "batch",
+            Endpoint::TopicManagement => "topic_management",
+            Endpoint::InstanceInfo => "instance_info",
+            Endpoint::DeviceGroup => "device_group",
+            Endpoint::OAuthToken => "oauth_token",
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Who a sent message is addressed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TargetKind {
+    /// A single registration token, or a device group notification key.
+    Token,
+
+    /// A list of registration tokens, sent with `registration_ids`.
+    Multicast,
+
+    /// The subscribers of a topic.
+    Topic,
+
+    /// The devices matching a topic condition.
+    Condition,
+
+    /// The message has no target FCM would accept.
+    Unknown,
+}
+
+impl TargetKind {
+    /// A short name of the target kind, e.g. to label metrics.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TargetKind::Token => "token",
+            TargetKind::Multicast => "multicast",
+            TargetKind::Topic => "topic",
+            TargetKind::Condition => "condition",
+            TargetKind::Unknown => "unknown",
+        }
+    }
+
+    /// The target kind of a legacy message body.
+    pub(crate) fn of_legacy(body: &Value) -> TargetKind {
+        if body.get("registration_ids").is_some() {
+            TargetKind::Multicast
+        } else if body.get("condition").is_some() {
+            TargetKind::Condition
+        } else {
+            match body.get("to").and_then(Value::as_str) {
+                Some(to) if to.starts_with("/topics/") => TargetKind::Topic,
+                Some(_) => TargetKind::Token,
+                None => TargetKind::Unknown,
+            }
+        }
+    }
+}
+
+impl fmt::Display for TargetKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<&Target> for TargetKind {
+    fn from(target: &Target) -> TargetKind {
+        match target {
+            Target::Token(_) => TargetKind::Token,
+            Target::Topic(_) => TargetKind::Topic,
+            Target::Condition(_) => TargetKind::Condition,
+        }
+    }
+}
+
+/// Observes what a [Client](../struct.Client.html) does, e.g. to collect
+/// metrics. Set it with
+/// [ClientBuilder::observer](../struct.ClientBuilder.html#method.observer).
+///
+/// All methods do nothing by default, so an observer only implements the
+/// ones it is interested in. They are called on the task sending the
+/// request and should return quickly.
+///
+/// # Examples
+///
+/// ```rust
+/// use fcm::{ClientBuilder, Endpoint, SendObserver, TargetKind};
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+///
+/// #[derive(Default)]
+/// struct TopicSends(AtomicUsize);
+///
+/// impl SendObserver for TopicSends {
+///     fn on_send(&self, _endpoint: Endpoint, target: TargetKind) {
+///         if target == TargetKind::Topic {
+///             self.0.fetch_add(1, Ordering::Relaxed);
+///         }
+///     }
+/// }
+///
+/// let mut builder = ClientBuilder::new();
+/// builder.observer(TopicSends::default());
+/// ```
+pub trait SendObserver: Send + Sync + 'static {
+    /// A message to `target` is about to be sent to `endpoint`. A batch
+    /// request reports each of its messages.
+    fn on_send(&self, _endpoint: Endpoint, _target: TargetKind) {}
+
+    /// A request to `endpoint` completed after `latency`, with the HTTP
+    /// `status` of the response, or `None` if no response was received.
+    fn on_response(&self, _endpoint: Endpoint, _status: Option<StatusCode>, _latency: Duration) {}
+
+    /// FCM answered a legacy send with one result per registration token,
+    /// reporting errors and canonical ids.
+    fn on_results(&self, _results: &[MessageResult]) {}
+
+    /// A failed send to `endpoint` is retried. `attempt` is 1 for the first
+    /// retry.
+    fn on_retry(&self, _endpoint: Endpoint, _attempt: u32) {}
+}
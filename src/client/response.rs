@@ -1,10 +1,15 @@
-pub use chrono::{DateTime, Duration, FixedOffset};
-use serde::Deserialize;
-use std::{error::Error, fmt, str::FromStr};
+pub use chrono::{DateTime, Duration, FixedOffset, Utc};
+use chrono::{NaiveDateTime, TimeZone};
+use http::header::HeaderMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::{collections::BTreeMap, error::Error, fmt, str::FromStr};
+
+use crate::v1;
 
 /// A description of what went wrong with the push notification.
 /// Referred from [Firebase documentation](https://firebase.google.com/docs/cloud-messaging/http-server-ref#table9)
-#[derive(Deserialize, Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum ErrorReason {
     /// Check that the request contains a registration token (in the `to` or
     /// `registration_ids` field).
@@ -89,29 +94,319 @@ pub enum ErrorReason {
     /// required APNs authentication key was not uploaded or has expired. Check
     /// the validity of your development and production credentials.
     InvalidApnsCredential,
+
+    /// An error this crate does not know about, as sent by FCM.
+    Unknown(String),
+}
+
+impl ErrorReason {
+    fn from_name(name: &str) -> ErrorReason {
+        match name {
+            "MissingRegistration" => ErrorReason::MissingRegistration,
+            "InvalidRegistration" => ErrorReason::InvalidRegistration,
+            "NotRegistered" => ErrorReason::NotRegistered,
+            "InvalidPackageName" => ErrorReason::InvalidPackageName,
+            "MismatchSenderId" => ErrorReason::MismatchSenderId,
+            "InvalidParameters" => ErrorReason::InvalidParameters,
+            "MessageTooBig" => ErrorReason::MessageTooBig,
+            "InvalidDataKey" => ErrorReason::InvalidDataKey,
+            "InvalidTtl" => ErrorReason::InvalidTtl,
+            "Unavailable" => ErrorReason::Unavailable,
+            "InternalServerError" => ErrorReason::InternalServerError,
+            "DeviceMessageRateExceeded" => ErrorReason::DeviceMessageRateExceeded,
+            "TopicsMessageRateExceeded" => ErrorReason::TopicsMessageRateExceeded,
+            "InvalidApnsCredential" => ErrorReason::InvalidApnsCredential,
+            other => ErrorReason::Unknown(other.to_string()),
+        }
+    }
+
+    /// The error string as sent by FCM.
+    pub fn as_str(&self) -> &str {
+        match self {
+            ErrorReason::MissingRegistration => "MissingRegistration",
+            ErrorReason::InvalidRegistration => "InvalidRegistration",
+            ErrorReason::NotRegistered => "NotRegistered",
+            ErrorReason::InvalidPackageName => "InvalidPackageName",
+            ErrorReason::MismatchSenderId => "MismatchSenderId",
+            ErrorReason::InvalidParameters => "InvalidParameters",
+            ErrorReason::MessageTooBig => "MessageTooBig",
+            ErrorReason::InvalidDataKey => "InvalidDataKey",
+            ErrorReason::InvalidTtl => "InvalidTtl",
+            ErrorReason::Unavailable => "Unavailable",
+            ErrorReason::InternalServerError => "InternalServerError",
+            ErrorReason::DeviceMessageRateExceeded => "DeviceMessageRateExceeded",
+            ErrorReason::TopicsMessageRateExceeded => "TopicsMessageRateExceeded",
+            ErrorReason::InvalidApnsCredential => "InvalidApnsCredential",
+            ErrorReason::Unknown(name) => name,
+        }
+    }
 }
 
-#[derive(Deserialize, Debug)]
+impl<'de> Deserialize<'de> for ErrorReason {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let name = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        Ok(ErrorReason::from_name(&name))
+    }
+}
+
+impl Serialize for ErrorReason {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl fmt::Display for ErrorReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl ErrorReason {
+    /// `true` if the same message may succeed when retried later, using
+    /// exponential backoff.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ErrorReason::Unavailable
+                | ErrorReason::InternalServerError
+                | ErrorReason::DeviceMessageRateExceeded
+                | ErrorReason::TopicsMessageRateExceeded
+        )
+    }
+
+    /// `true` if the registration token is not valid anymore and should be
+    /// removed from the app server.
+    pub fn should_remove_token(&self) -> bool {
+        matches!(self, ErrorReason::NotRegistered | ErrorReason::InvalidRegistration)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct FcmResponse {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub message_id: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<ErrorReason>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub multicast_id: Option<i64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub success: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub failure: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub canonical_ids: Option<u64>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub results: Option<Vec<MessageResult>>,
+
+    /// Fields of the response this crate does not know about.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct MessageResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub message_id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub registration_id: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<ErrorReason>,
+
+    /// Fields of the result this crate does not know about.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// The response to a topic subscription change, with one result per
+/// registration token in the order of the request.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct TopicManagementResponse {
+    #[serde(default)]
+    pub results: Vec<TopicManagementResult>,
+}
+
+impl TopicManagementResponse {
+    /// Number of tokens whose subscription was changed.
+    pub fn success_count(&self) -> usize {
+        self.results.iter().filter(|result| result.error.is_none()).count()
+    }
+
+    /// Number of tokens whose subscription could not be changed.
+    pub fn failure_count(&self) -> usize {
+        self.results.len() - self.success_count()
+    }
+}
+
+/// The outcome of a topic subscription change for a single registration
+/// token. The error is e.g. `NOT_FOUND` for a token that is not valid anymore,
+/// `INVALID_ARGUMENT` or `TOO_MANY_TOPICS`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct TopicManagementResult {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The Instance ID details of a registration token.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenInfo {
+    /// The package name or bundle id of the app the token belongs to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub application: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub application_version: Option<String>,
+
+    /// The sender id the token is authorized for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authorized_entity: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_signer: Option<String>,
+
+    /// `ANDROID`, `IOS` or `CHROME`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+
+    #[serde(default)]
+    pub rel: TokenRelations,
+
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl TokenInfo {
+    /// The topics the token is subscribed to.
+    pub fn topics(&self) -> Vec<&str> {
+        self.rel.topics.keys().map(String::as_str).collect()
+    }
+}
+
+/// The relations of a registration token.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct TokenRelations {
+    #[serde(default)]
+    pub topics: BTreeMap<String, TopicSubscription>,
+}
+
+/// A topic subscription of a registration token.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TopicSubscription {
+    /// The day the token was subscribed, e.g. `2019-03-28`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub add_date: Option<String>,
+}
+
+/// The response to a device group change.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DeviceGroupResponse {
+    /// The key to send messages to the group with.
+    pub notification_key: String,
+}
+
+impl FcmResponse {
+    /// Log unknown errors and fields, which hint at FCM behavior this crate
+    /// does not handle yet.
+    pub(crate) fn log_unknown(&self) {
+        if !self.extra.is_empty() {
+            log::warn!("unknown fields in FCM response: {:?}", self.extra);
+        }
+
+        if let Some(ErrorReason::Unknown(ref name)) = self.error {
+            log::warn!("unknown error in FCM response: {}", name);
+        }
+
+        for result in self.results.iter().flatten() {
+            if !result.extra.is_empty() {
+                log::warn!("unknown fields in FCM message result: {:?}", result.extra);
+            }
+
+            if let Some(ErrorReason::Unknown(ref name)) = result.error {
+                log::warn!("unknown error in FCM message result: {}", name);
+            }
+        }
+    }
+}
+
+/// The status, relevant headers and body of an HTTP response, kept for
+/// diagnostics. The body is truncated to
+/// [RawResponse::MAX_BODY_LEN](#associatedconstant.MAX_BODY_LEN) bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawResponse {
+    /// The HTTP status code.
+    pub status: u16,
+
+    /// The values of the [KEPT_HEADERS](#associatedconstant.KEPT_HEADERS)
+    /// present in the response, in that order.
+    pub headers: Vec<(String, String)>,
+
+    /// The response body, lossily decoded as UTF-8 and truncated.
+    pub body: String,
+
+    /// The complete response body parsed as JSON, if it was valid JSON.
+    pub json: Option<Value>,
+}
+
+impl RawResponse {
+    /// Maximum number of bytes of the body that are kept.
+    pub const MAX_BODY_LEN: usize = 1024;
+
+    /// Headers that are kept from the response.
+    pub const KEPT_HEADERS: &'static [&'static str] = &["content-type", "retry-after", "www-authenticate"];
+
+    pub(crate) fn new(status: u16, headers: &HeaderMap, body: &[u8]) -> Self {
+        let headers = Self::KEPT_HEADERS
+            .iter()
+            .filter_map(|name| {
+                let value = headers.get(*name)?.to_str().ok()?;
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect();
+
+        let json = serde_json::from_slice(body).ok();
+
+        let mut text = String::from_utf8_lossy(&body[..body.len().min(Self::MAX_BODY_LEN)]).into_owned();
+
+        // Cutting off the body may have split a multi-byte character.
+        if text.ends_with(char::REPLACEMENT_CHARACTER) && body.len() > Self::MAX_BODY_LEN {
+            text.pop();
+        }
+
+        RawResponse {
+            status,
+            headers,
+            body: text,
+            json,
+        }
+    }
+
+    /// The value of a kept header.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
 }
 
 /// Fatal errors. Referred from [Firebase
 /// documentation](https://firebase.google.com/docs/cloud-messaging/http-server-ref#table9)
-#[derive(PartialEq, Debug)]
+#[derive(Debug)]
 pub enum FcmError {
     /// The sender account used to send a message couldn't be authenticated. Possible causes are:
     ///
@@ -144,9 +439,137 @@ pub enum FcmError {
     ///
     /// Senders that cause problems risk being blacklisted.
     ServerError(Option<RetryAfter>),
+
+    /// An error returned by the FCM HTTP v1 API, with its typed details.
+    V1(v1::Status),
+
+    /// The connection to FCM could not be established, e.g. because DNS
+    /// resolution, the TLS handshake or connecting the socket failed. The
+    /// request never reached FCM, so it is safe to retry.
+    Connect(Box<dyn Error + Send + Sync>),
+
+    /// The request timed out. FCM might have received and processed it, so a
+    /// retry might deliver the message twice.
+    Timeout(Box<dyn Error + Send + Sync>),
+
+    /// The connection failed while the request was in flight, e.g. because it
+    /// was reset. FCM might have received and processed the request, so a
+    /// retry might deliver the message twice.
+    Transport(Box<dyn Error + Send + Sync>),
+
+    /// The HTTP request could not be built. Retrying will not help.
+    Request(Box<dyn Error + Send + Sync>),
+
+    /// The credentials could not be used to authorize the request, e.g.
+    /// because the service account key is malformed.
+    InvalidCredentials(Box<dyn Error + Send + Sync>),
+
+    /// The message could not be serialized to JSON.
+    Serialization(serde_json::Error),
+
+    /// The server answered with a body that could not be decoded, e.g. an
+    /// HTML page from a proxy or a truncated JSON document.
+    InvalidResponse(RawResponse, serde_json::Error),
+
+    /// The server rejected the request as malformed (HTTP 400). The response
+    /// body describes what is wrong with it.
+    BadRequest(RawResponse),
+
+    /// The sender is not allowed to send the request (HTTP 403), e.g. because
+    /// the API is disabled for the project.
+    Forbidden(RawResponse),
+
+    /// The sender exceeded a quota (HTTP 429). Retry after the
+    /// [RetryAfter](enum.RetryAfter.html) value if included, using
+    /// exponential back-off otherwise.
+    TooManyRequests(Option<RetryAfter>, RawResponse),
+
+    /// The server answered with a status this crate does not expect.
+    UnexpectedStatus(RawResponse),
+
+    /// A client-side rate limit rejected the message before it was sent.
+    /// Retry after the [RetryAfter](enum.RetryAfter.html) value.
+    RateLimited(RetryAfter),
 }
 
-impl Error for FcmError {}
+impl FcmError {
+    /// `true` if the same request may succeed when retried later. Honor the
+    /// [RetryAfter](enum.RetryAfter.html) value if there is one.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            FcmError::ServerError(_)
+            | FcmError::Connect(_)
+            | FcmError::Timeout(_)
+            | FcmError::Transport(_)
+            | FcmError::TooManyRequests(..)
+            | FcmError::RateLimited(_) => true,
+            FcmError::V1(status) => status.is_retryable(),
+            FcmError::Unauthorized
+            | FcmError::InvalidMessage(_)
+            | FcmError::Request(_)
+            | FcmError::InvalidCredentials(_)
+            | FcmError::Serialization(_)
+            | FcmError::InvalidResponse(..)
+            | FcmError::BadRequest(_)
+            | FcmError::Forbidden(_)
+            | FcmError::UnexpectedStatus(_) => false,
+        }
+    }
+
+    /// `true` if the request is retryable and is known not to have been
+    /// processed by FCM, so a retry cannot deliver the message twice. Timeouts
+    /// and connections failing mid-request are retryable, but not safe.
+    pub fn is_retry_safe(&self) -> bool {
+        match self {
+            FcmError::Timeout(_) | FcmError::Transport(_) => false,
+            _ => self.is_retryable(),
+        }
+    }
+
+    /// `true` if the registration token the request was sent to should be
+    /// removed from the app server. Legacy multicast requests report this per
+    /// token, see
+    /// [ErrorReason::should_remove_token](enum.ErrorReason.html#method.should_remove_token).
+    pub fn should_remove_token(&self) -> bool {
+        match self {
+            FcmError::V1(status) => status.should_remove_token(),
+            _ => false,
+        }
+    }
+
+    /// The typed details of an error returned by the v1 API.
+    pub fn v1_status(&self) -> Option<&v1::Status> {
+        match self {
+            FcmError::V1(status) => Some(status),
+            _ => None,
+        }
+    }
+
+    /// The time to wait before retrying, if the server told us.
+    pub fn retry_after(&self) -> Option<RetryAfter> {
+        match self {
+            FcmError::ServerError(retry_after) | FcmError::TooManyRequests(retry_after, _) => retry_after.clone(),
+            FcmError::V1(status) => status.retry_after(),
+            FcmError::RateLimited(retry_after) => Some(retry_after.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl Error for FcmError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FcmError::V1(status) => Some(status),
+            FcmError::Connect(e)
+            | FcmError::Timeout(e)
+            | FcmError::Transport(e)
+            | FcmError::Request(e)
+            | FcmError::InvalidCredentials(e) => Some(e.as_ref()),
+            FcmError::Serialization(e) | FcmError::InvalidResponse(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
 impl fmt::Display for FcmError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -154,17 +577,49 @@ impl fmt::Display for FcmError {
             FcmError::Unauthorized => write!(f, "authorization header missing or with invalid syntax in HTTP request"),
             FcmError::InvalidMessage(ref s) => write!(f, "invalid message {}", s),
             FcmError::ServerError(_) => write!(f, "the server couldn't process the request"),
+            FcmError::V1(status) => write!(f, "{}", status),
+            FcmError::Connect(_) => write!(f, "could not connect to the server"),
+            FcmError::Timeout(_) => write!(f, "the request timed out"),
+            FcmError::Transport(_) => write!(f, "the connection failed during the request"),
+            FcmError::Request(_) => write!(f, "the request could not be built"),
+            FcmError::InvalidCredentials(e) => write!(f, "invalid credentials: {}", e),
+            FcmError::Serialization(_) => write!(f, "the message could not be serialized"),
+            FcmError::InvalidResponse(response, _) => {
+                write!(f, "invalid response with status {}: {}", response.status, response.body)
+            }
+            FcmError::BadRequest(response) => write!(f, "bad request: {}", response.body),
+            FcmError::Forbidden(response) => write!(f, "forbidden: {}", response.body),
+            FcmError::TooManyRequests(_, response) => write!(f, "too many requests: {}", response.body),
+            FcmError::UnexpectedStatus(response) => {
+                write!(f, "unexpected status {}: {}", response.status, response.body)
+            }
+            FcmError::RateLimited(_) => write!(f, "the message exceeds the client-side rate limit"),
         }
     }
 }
 
+impl From<v1::Status> for FcmError {
+    fn from(status: v1::Status) -> Self {
+        Self::V1(status)
+    }
+}
+
+#[cfg(feature = "reqwest")]
 impl From<reqwest::Error> for FcmError {
-    fn from(_: reqwest::Error) -> Self {
-        Self::ServerError(None)
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Self::Timeout(e.into())
+        } else if e.is_connect() {
+            Self::Connect(e.into())
+        } else if e.is_builder() {
+            Self::Request(e.into())
+        } else {
+            Self::Transport(e.into())
+        }
     }
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum RetryAfter {
     /// Amount of time to wait until retrying the message is allowed.
     Delay(Duration),
@@ -173,15 +628,81 @@ pub enum RetryAfter {
     DateTime(DateTime<FixedOffset>),
 }
 
+impl RetryAfter {
+    /// The longest wait [wait_duration](#method.wait_duration) returns.
+    pub const DEFAULT_MAX_WAIT: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+    /// How long to wait from `now` until retrying is allowed, clamped to
+    /// [DEFAULT_MAX_WAIT](#associatedconstant.DEFAULT_MAX_WAIT). Dates in the
+    /// past result in a zero duration.
+    pub fn wait_duration(&self, now: DateTime<Utc>) -> std::time::Duration {
+        self.wait_duration_with_max(now, Self::DEFAULT_MAX_WAIT)
+    }
+
+    /// How long to wait from `now` until retrying is allowed, clamped to
+    /// `max`. Dates in the past result in a zero duration.
+    pub fn wait_duration_with_max(&self, now: DateTime<Utc>, max: std::time::Duration) -> std::time::Duration {
+        let wait = match self {
+            RetryAfter::Delay(delay) => *delay,
+            RetryAfter::DateTime(date) => date.with_timezone(&Utc) - now,
+        };
+
+        wait.to_std().unwrap_or_default().min(max)
+    }
+}
+
+/// The value of a `Retry-After` header could not be parsed.
+#[derive(PartialEq, Debug, Clone)]
+pub struct RetryAfterParseError {
+    value: String,
+}
+
+impl RetryAfterParseError {
+    /// The value that failed to parse.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+impl Error for RetryAfterParseError {}
+
+impl fmt::Display for RetryAfterParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid Retry-After value {:?}", self.value)
+    }
+}
+
+/// Formats of an HTTP-date according to [RFC 7231, section
+/// 7.1.1.1](https://tools.ietf.org/html/rfc7231#section-7.1.1.1): the
+/// IMF-fixdate, the obsolete RFC 850 format and the asctime format.
+const HTTP_DATE_FORMATS: &[&str] = &[
+    "%a, %d %b %Y %H:%M:%S GMT",
+    "%A, %d-%b-%y %H:%M:%S GMT",
+    "%a %b %e %H:%M:%S %Y",
+];
+
 impl FromStr for RetryAfter {
-    type Err = crate::Error;
+    type Err = RetryAfterParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        s.parse::<i64>()
-            .map(Duration::seconds)
-            .map(RetryAfter::Delay)
-            .or_else(|_| DateTime::parse_from_rfc2822(s).map(RetryAfter::DateTime))
-            .map_err(|e| crate::Error::InvalidMessage(format!("{}", e)))
+        let s = s.trim();
+
+        if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+            return s
+                .parse::<i64>()
+                .ok()
+                .and_then(Duration::try_seconds)
+                .map(RetryAfter::Delay)
+                .ok_or_else(|| RetryAfterParseError { value: s.to_string() });
+        }
+
+        HTTP_DATE_FORMATS
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok())
+            .map(|date| Utc.from_utc_datetime(&date).into())
+            .or_else(|| DateTime::parse_from_rfc2822(s).ok())
+            .map(RetryAfter::DateTime)
+            .ok_or_else(|| RetryAfterParseError { value: s.to_string() })
     }
 }
 
@@ -227,6 +748,146 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_unknown_error_and_fields() {
+        let response_data = json!({
+            "multicast_id": 1,
+            "error": "SomethingNew",
+            "new_field": [1, 2],
+            "results": [
+                {"error": "AnotherNewThing", "hint": "wait"},
+                {"message_id": "0:1", "error": "NotRegistered"}
+            ]
+        });
+
+        let fcm_response: FcmResponse = serde_json::from_value(response_data).unwrap();
+
+        assert_eq!(
+            Some(ErrorReason::Unknown("SomethingNew".to_string())),
+            fcm_response.error
+        );
+        assert_eq!(Some(&json!([1, 2])), fcm_response.extra.get("new_field"));
+
+        let results = fcm_response.results.unwrap();
+
+        assert_eq!(
+            Some(ErrorReason::Unknown("AnotherNewThing".to_string())),
+            results[0].error
+        );
+        assert_eq!(Some(&json!("wait")), results[0].extra.get("hint"));
+        assert_eq!(Some(ErrorReason::NotRegistered), results[1].error);
+        assert!(results[1].extra.is_empty());
+        assert!(!ErrorReason::Unknown("SomethingNew".to_string()).is_retryable());
+    }
+
+    #[test]
+    fn test_topic_management_response() {
+        let response: TopicManagementResponse = serde_json::from_value(json!({
+            "results": [{}, {"error": "NOT_FOUND"}, {}]
+        }))
+        .unwrap();
+
+        assert_eq!(2, response.success_count());
+        assert_eq!(1, response.failure_count());
+        assert_eq!(Some("NOT_FOUND"), response.results[1].error.as_deref());
+    }
+
+    #[test]
+    fn test_legacy_error_decisions() {
+        assert!(ErrorReason::Unavailable.is_retryable());
+        assert!(ErrorReason::DeviceMessageRateExceeded.is_retryable());
+        assert!(!ErrorReason::NotRegistered.is_retryable());
+
+        assert!(ErrorReason::NotRegistered.should_remove_token());
+        assert!(ErrorReason::InvalidRegistration.should_remove_token());
+        assert!(!ErrorReason::MessageTooBig.should_remove_token());
+
+        assert!(FcmError::ServerError(None).is_retryable());
+        assert!(!FcmError::Unauthorized.is_retryable());
+    }
+
+    #[test]
+    fn test_v1_status_into_error() {
+        let body = json!({
+            "error": {
+                "code": 503,
+                "message": "The service is currently unavailable.",
+                "status": "UNAVAILABLE",
+                "details": [
+                    {
+                        "@type": "type.googleapis.com/google.firebase.fcm.v1.FcmError",
+                        "errorCode": "UNAVAILABLE"
+                    },
+                    {
+                        "@type": "type.googleapis.com/google.rpc.RetryInfo",
+                        "retryDelay": "10s"
+                    }
+                ]
+            }
+        });
+
+        let status = v1::Status::from_response_body(body.to_string().as_bytes()).unwrap();
+        let error = FcmError::from(status);
+
+        assert!(error.is_retryable());
+        assert!(!error.should_remove_token());
+        assert_eq!(Some(RetryAfter::Delay(Duration::seconds(10))), error.retry_after());
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_connect_error_is_kept_as_source() {
+        let client = reqwest::Client::new();
+        let error: FcmError = client.get("http://127.0.0.1:1/").send().await.unwrap_err().into();
+
+        assert!(matches!(error, FcmError::Connect(_)));
+        assert!(error.is_retryable());
+        assert!(error.is_retry_safe());
+        assert!(error.source().unwrap().downcast_ref::<reqwest::Error>().is_some());
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[test]
+    fn test_builder_error_is_not_retryable() {
+        let client = reqwest::Client::new();
+        let error: FcmError = client.get("not a url").build().unwrap_err().into();
+
+        assert!(matches!(error, FcmError::Request(_)));
+        assert!(!error.is_retryable());
+    }
+
+    #[test]
+    fn test_raw_response_is_truncated() {
+        let body = format!("a{}", "ä".repeat(RawResponse::MAX_BODY_LEN));
+        let response = RawResponse::new(200, &HeaderMap::new(), body.as_bytes());
+
+        assert_eq!(200, response.status);
+        assert_eq!(None, response.json);
+        assert_eq!(RawResponse::MAX_BODY_LEN - 1, response.body.len());
+        assert!(response.body[1..].chars().all(|c| c == 'ä'));
+    }
+
+    #[test]
+    fn test_raw_response_keeps_headers_and_json() {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", "application/json".parse().unwrap());
+        headers.insert("retry-after", "30".parse().unwrap());
+        headers.insert("set-cookie", "secret".parse().unwrap());
+
+        let body = json!({"error": "InvalidParameters"}).to_string();
+        let response = RawResponse::new(429, &headers, body.as_bytes());
+
+        assert_eq!(
+            vec![
+                ("content-type".to_string(), "application/json".to_string()),
+                ("retry-after".to_string(), "30".to_string()),
+            ],
+            response.headers
+        );
+        assert_eq!(Some("30"), response.header("Retry-After"));
+        assert_eq!(Some(json!({"error": "InvalidParameters"})), response.json);
+    }
+
     #[test]
     fn test_retry_after_from_seconds() {
         assert_eq!(RetryAfter::Delay(Duration::seconds(420)), "420".parse().unwrap());
@@ -242,4 +903,56 @@ mod tests {
             retry_after,
         );
     }
+
+    #[test]
+    fn test_retry_after_from_http_dates() {
+        let expected = RetryAfter::DateTime(DateTime::parse_from_rfc2822("Sun, 06 Nov 1994 08:49:37 GMT").unwrap());
+
+        for date in &[
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+            "Sun, 06 Nov 1994 09:49:37 +0100",
+        ] {
+            assert_eq!(expected, date.parse().unwrap(), "{}", date);
+        }
+    }
+
+    #[test]
+    fn test_retry_after_parse_errors() {
+        for value in &[
+            "",
+            "-5",
+            "1.5",
+            "tomorrow",
+            "Sun, 06 Nov 1994",
+            "9223372036854775807",
+            "99999999999999999999",
+        ] {
+            let error = value.parse::<RetryAfter>().unwrap_err();
+            assert_eq!(*value, error.value());
+        }
+    }
+
+    #[test]
+    fn test_retry_after_wait_duration() {
+        let now = Utc.with_ymd_and_hms(1994, 11, 6, 8, 49, 37).unwrap();
+        let secs = std::time::Duration::from_secs;
+
+        assert_eq!(secs(420), RetryAfter::Delay(Duration::seconds(420)).wait_duration(now));
+        assert_eq!(
+            RetryAfter::DEFAULT_MAX_WAIT,
+            RetryAfter::Delay(Duration::days(2)).wait_duration(now)
+        );
+        assert_eq!(
+            secs(60),
+            RetryAfter::Delay(Duration::seconds(420)).wait_duration_with_max(now, secs(60))
+        );
+
+        let later: RetryAfter = "Sun, 06 Nov 1994 08:50:07 GMT".parse().unwrap();
+        assert_eq!(secs(30), later.wait_duration(now));
+
+        let earlier: RetryAfter = "Sun, 06 Nov 1994 08:00:00 GMT".parse().unwrap();
+        assert_eq!(secs(0), earlier.wait_duration(now));
+    }
 }
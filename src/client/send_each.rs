@@ -0,0 +1,73 @@
+//! Sending batches of v1 messages like the Firebase Admin SDKs do.
+
+use crate::client::Client;
+use crate::v1::{self, BatchResponse, BatchStrategy, Credentials, MulticastMessage, MAX_BATCH_SIZE};
+use crate::FcmError;
+use futures::stream::{self, StreamExt};
+
+/// How many requests [Client::send_each](struct.Client.html#method.send_each)
+/// keeps in flight.
+const SEND_EACH_CONCURRENCY: usize = 100;
+
+impl Client {
+    /// Send each v1 message in its own request, authorized by `credentials`,
+    /// like `sendEach` of the Firebase Admin SDKs. Accepts up to
+    /// [MAX_BATCH_SIZE](v1/constant.MAX_BATCH_SIZE.html) messages.
+    ///
+    /// Fails only if the batch is empty or too large, if no access token can
+    /// be obtained or, with the
+    /// [Multipart](v1/enum.BatchStrategy.html#variant.Multipart) strategy, if
+    /// the batch request fails as a whole. Errors of single messages are
+    /// reported in the [BatchResponse](v1/struct.BatchResponse.html).
+    pub async fn send_each(
+        &self,
+        credentials: &Credentials,
+        messages: &[v1::Message],
+    ) -> Result<BatchResponse, FcmError> {
+        check_batch_size(messages.len())?;
+
+        if self.batch_strategy == BatchStrategy::Multipart {
+            return self.send_batch(credentials, messages).await.map(BatchResponse::new);
+        }
+
+        // Fetch the access token once, instead of once per request in flight.
+        self.access_token(credentials).await?;
+
+        let responses = stream::iter(messages)
+            .map(|message| self.send_v1(credentials, message))
+            .buffered(SEND_EACH_CONCURRENCY)
+            .collect()
+            .await;
+
+        Ok(BatchResponse::new(responses))
+    }
+
+    /// Send a [MulticastMessage](v1/struct.MulticastMessage.html) to each of
+    /// its tokens, like `sendEachForMulticast` of the Firebase Admin SDKs.
+    /// The responses are in the order of the tokens.
+    pub async fn send_each_for_multicast(
+        &self,
+        credentials: &Credentials,
+        message: &MulticastMessage,
+    ) -> Result<BatchResponse, FcmError> {
+        check_batch_size(message.tokens.len())?;
+
+        self.send_each(credentials, &message.messages()).await
+    }
+}
+
+/// Check the size of a batch before sending it.
+fn check_batch_size(size: usize) -> Result<(), FcmError> {
+    if size == 0 {
+        return Err(FcmError::InvalidMessage("a batch must not be empty".to_string()));
+    }
+
+    if size > MAX_BATCH_SIZE {
+        return Err(FcmError::InvalidMessage(format!(
+            "a batch must not contain more than {} messages, got {}",
+            MAX_BATCH_SIZE, size
+        )));
+    }
+
+    Ok(())
+}
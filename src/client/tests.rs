@@ -0,0 +1,1048 @@
+use crate::client::transport::{BoxFuture, HttpRequest, HttpResponse, Transport};
+use crate::client::{send_response, topic_management_payload, topic_management_response};
+use crate::{Client, ErrorReason, FcmError, MessageBuilder, RetryAfter};
+use chrono::Duration;
+use http::header::HeaderMap;
+use http::StatusCode;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+
+/// Records requests and answers each with the same status and body.
+#[derive(Clone)]
+struct FakeTransport {
+    status: StatusCode,
+    body: Value,
+    requests: Arc<Mutex<Vec<HttpRequest>>>,
+}
+
+impl FakeTransport {
+    fn new(status: StatusCode, body: Value) -> Self {
+        FakeTransport {
+            status,
+            body,
+            requests: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+}
+
+impl Transport for FakeTransport {
+    fn execute(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, FcmError>> {
+        self.requests.lock().unwrap().push(request);
+
+        let response = HttpResponse {
+            status: self.status,
+            headers: HeaderMap::new(),
+            body: self.body.to_string().into_bytes(),
+        };
+
+        Box::pin(async move { Ok(response) })
+    }
+}
+
+fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+
+    for (name, value) in pairs {
+        headers.insert(*name, value.parse().unwrap());
+    }
+
+    headers
+}
+
+#[test]
+fn should_parse_a_successful_send_response() {
+    let body = json!({
+        "multicast_id": 108,
+        "success": 1,
+        "failure": 1,
+        "canonical_ids": 0,
+        "results": [
+            {"message_id": "1:08"},
+            {"error": "NotRegistered"}
+        ]
+    })
+    .to_string();
+
+    let response = send_response(StatusCode::OK, &HeaderMap::new(), body.as_bytes()).unwrap();
+
+    assert_eq!(Some(108), response.multicast_id);
+    assert_eq!(Some(ErrorReason::NotRegistered), response.results.unwrap()[1].error);
+}
+
+#[test]
+fn should_turn_unavailable_into_a_server_error() {
+    let body = json!({"error": "Unavailable"}).to_string();
+    let error = send_response(StatusCode::OK, &headers(&[("retry-after", "30")]), body.as_bytes()).unwrap_err();
+
+    assert!(matches!(
+        error,
+        FcmError::ServerError(Some(RetryAfter::Delay(delay))) if delay == Duration::seconds(30)
+    ));
+}
+
+#[test]
+fn should_keep_the_body_of_an_invalid_response() {
+    let error = send_response(StatusCode::OK, &HeaderMap::new(), b"<html>proxy error</html>").unwrap_err();
+
+    match error {
+        FcmError::InvalidResponse(response, _) => {
+            assert_eq!(200, response.status);
+            assert_eq!("<html>proxy error</html>", response.body);
+        }
+        error => panic!("unexpected error {:?}", error),
+    }
+}
+
+#[test]
+fn should_map_error_statuses() {
+    let body = br#"{"error": "InvalidTtl"}"#;
+
+    assert!(matches!(
+        send_response(StatusCode::UNAUTHORIZED, &HeaderMap::new(), body),
+        Err(FcmError::Unauthorized)
+    ));
+    assert!(matches!(
+        send_response(StatusCode::BAD_GATEWAY, &HeaderMap::new(), body),
+        Err(FcmError::ServerError(None))
+    ));
+    assert!(matches!(
+        send_response(StatusCode::FORBIDDEN, &HeaderMap::new(), body),
+        Err(FcmError::Forbidden(_))
+    ));
+    assert!(matches!(
+        send_response(StatusCode::NOT_FOUND, &HeaderMap::new(), body),
+        Err(FcmError::UnexpectedStatus(_))
+    ));
+
+    match send_response(StatusCode::BAD_REQUEST, &HeaderMap::new(), body) {
+        Err(FcmError::BadRequest(response)) => {
+            assert_eq!(400, response.status);
+            assert_eq!(Some(json!({"error": "InvalidTtl"})), response.json);
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    match send_response(StatusCode::TOO_MANY_REQUESTS, &headers(&[("retry-after", "5")]), body) {
+        Err(FcmError::TooManyRequests(Some(RetryAfter::Delay(delay)), response)) => {
+            assert_eq!(Duration::seconds(5), delay);
+            assert_eq!(Some("5"), response.header("retry-after"));
+        }
+        result => panic!("unexpected result {:?}", result),
+    }
+}
+
+#[test]
+fn should_build_topic_management_payloads() {
+    let expected = json!({
+        "to": "/topics/news",
+        "registration_tokens": ["a", "b"],
+    });
+
+    for topic in &["news", "/topics/news"] {
+        let payload = topic_management_payload(topic, &["a", "b"]).unwrap();
+        assert_eq!(expected, serde_json::from_slice::<serde_json::Value>(&payload).unwrap());
+    }
+}
+
+#[test]
+fn should_parse_topic_management_responses() {
+    let body = json!({"results": [{}, {"error": "NOT_FOUND"}]}).to_string();
+    let response = topic_management_response(StatusCode::OK, &HeaderMap::new(), body.as_bytes()).unwrap();
+
+    assert_eq!(1, response.failure_count());
+
+    assert!(matches!(
+        topic_management_response(StatusCode::BAD_REQUEST, &HeaderMap::new(), b"{}"),
+        Err(FcmError::BadRequest(_))
+    ));
+}
+
+#[tokio::test]
+async fn should_send_messages_through_the_transport() {
+    let transport = FakeTransport::new(StatusCode::OK, json!({"message_id": 42}));
+    let client = Client::with_transport(transport.clone());
+
+    let response = client
+        .send(MessageBuilder::new("api_key", "token").finalize())
+        .await
+        .unwrap();
+
+    assert_eq!(Some(42), response.message_id);
+
+    let requests = transport.requests.lock().unwrap();
+
+    assert_eq!("https://fcm.googleapis.com/fcm/send", requests[0].url);
+    assert_eq!("key=api_key", requests[0].headers["authorization"]);
+    assert_eq!("application/json", requests[0].headers["content-type"]);
+    assert_eq!(
+        json!({"to": "token"}),
+        serde_json::from_slice::<Value>(&requests[0].body).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn should_manage_topics_through_the_transport() {
+    let transport = FakeTransport::new(StatusCode::OK, json!({"results": [{}]}));
+    let client = Client::with_transport(transport.clone());
+
+    client.subscribe_to_topic("api_key", "news", &["token"]).await.unwrap();
+    client
+        .unsubscribe_from_topic("api_key", "news", &["token"])
+        .await
+        .unwrap();
+
+    let requests = transport.requests.lock().unwrap();
+
+    assert_eq!("https://iid.googleapis.com/iid/v1:batchAdd", requests[0].url);
+    assert_eq!("https://iid.googleapis.com/iid/v1:batchRemove", requests[1].url);
+}
+
+#[tokio::test]
+async fn should_reject_api_keys_that_are_no_header_values() {
+    let transport = FakeTransport::new(StatusCode::OK, json!({}));
+    let client = Client::with_transport(transport.clone());
+
+    let result = client.send(MessageBuilder::new("api\nkey", "token").finalize()).await;
+
+    assert!(matches!(result, Err(FcmError::Request(_))));
+    assert!(transport.requests.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn should_get_token_info() {
+    let transport = FakeTransport::new(
+        StatusCode::OK,
+        json!({
+            "application": "com.example.app",
+            "authorizedEntity": "123",
+            "platform": "ANDROID",
+            "rel": { "topics": { "news": { "addDate": "2019-03-28" } } },
+        }),
+    );
+    let client = Client::with_transport(transport.clone());
+
+    let info = client.token_info("api_key", "token").await.unwrap();
+
+    assert_eq!(Some("com.example.app"), info.application.as_deref());
+    assert_eq!(vec!["news"], info.topics());
+
+    let requests = transport.requests.lock().unwrap();
+    assert_eq!(http::Method::GET, requests[0].method);
+    assert_eq!(
+        "https://iid.googleapis.com/iid/info/token?details=true",
+        requests[0].url
+    );
+}
+
+#[tokio::test]
+async fn should_manage_device_groups_through_the_transport() {
+    let transport = FakeTransport::new(StatusCode::OK, json!({ "notification_key": "group-key" }));
+    let client = Client::with_transport(transport.clone());
+
+    let response = client
+        .add_to_device_group("api_key", "sender", "family", "group-key", &["a"])
+        .await
+        .unwrap();
+
+    assert_eq!("group-key", response.notification_key);
+
+    let requests = transport.requests.lock().unwrap();
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+
+    assert_eq!("https://fcm.googleapis.com/fcm/notification", requests[0].url);
+    assert_eq!("sender", requests[0].headers["project_id"]);
+    assert_eq!(
+        json!({
+            "operation": "add",
+            "notification_key_name": "family",
+            "notification_key": "group-key",
+            "registration_ids": ["a"],
+        }),
+        body
+    );
+}
+
+mod observer {
+    use super::FakeTransport;
+    use crate::client::transport::{BoxFuture, HttpRequest, HttpResponse, Transport};
+    use crate::v1::{self, Credentials, Target};
+    use crate::{ClientBuilder, Endpoint, FcmError, MessageBuilder, MessageResult, SendObserver, TargetKind};
+    use http::StatusCode;
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    /// Records what it observes as strings.
+    #[derive(Clone, Default)]
+    struct RecordingObserver {
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl RecordingObserver {
+        fn events(&self) -> Vec<String> {
+            self.events.lock().unwrap().clone()
+        }
+    }
+
+    impl SendObserver for RecordingObserver {
+        fn on_send(&self, endpoint: Endpoint, target: TargetKind) {
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("send {} {}", endpoint, target));
+        }
+
+        fn on_response(&self, endpoint: Endpoint, status: Option<StatusCode>, _latency: Duration) {
+            let status = status.map_or("none".to_string(), |status| status.as_u16().to_string());
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("response {} {}", endpoint, status));
+        }
+
+        fn on_results(&self, results: &[MessageResult]) {
+            for result in results {
+                let outcome = match (&result.error, &result.registration_id) {
+                    (Some(error), _) => error.to_string(),
+                    (None, Some(_)) => "canonical".to_string(),
+                    (None, None) => "ok".to_string(),
+                };
+                self.events.lock().unwrap().push(format!("result {}", outcome));
+            }
+        }
+    }
+
+    struct FailingTransport;
+
+    impl Transport for FailingTransport {
+        fn execute(&self, _request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, FcmError>> {
+            Box::pin(async { Err(FcmError::Transport("connection reset".into())) })
+        }
+    }
+
+    fn client<T: Transport>(transport: T, observer: &RecordingObserver) -> crate::Client {
+        let mut builder = ClientBuilder::new();
+        builder.transport(transport).observer(observer.clone());
+        builder.finalize().unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_observe_legacy_sends_and_results() {
+        let transport = FakeTransport::new(
+            StatusCode::OK,
+            json!({
+                "multicast_id": 1,
+                "success": 2,
+                "failure": 1,
+                "canonical_ids": 1,
+                "results": [
+                    { "message_id": "1" },
+                    { "error": "NotRegistered" },
+                    { "message_id": "2", "registration_id": "new" },
+                ],
+            }),
+        );
+        let observer = RecordingObserver::default();
+        let client = client(transport, &observer);
+
+        client
+            .send(MessageBuilder::new_multi("key", &["a", "b", "c"]).finalize())
+            .await
+            .unwrap();
+        client
+            .send(MessageBuilder::new("key", "/topics/news").finalize())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            vec![
+                "send legacy_send multicast",
+                "response legacy_send 200",
+                "result ok",
+                "result NotRegistered",
+                "result canonical",
+                "send legacy_send topic",
+                "response legacy_send 200",
+                "result ok",
+                "result NotRegistered",
+                "result canonical",
+            ],
+            observer.events()
+        );
+    }
+
+    #[tokio::test]
+    async fn should_observe_v1_sends_and_errors() {
+        let transport = FakeTransport::new(StatusCode::SERVICE_UNAVAILABLE, json!({}));
+        let observer = RecordingObserver::default();
+        let client = client(transport, &observer);
+
+        let message = v1::MessageBuilder::new(Target::Condition("'news' in topics".to_string())).finalize();
+        let result = client.send_v1(&Credentials::access_token("p", "token"), &message).await;
+
+        assert!(result.is_err());
+        assert_eq!(
+            vec!["send v1_send condition", "response v1_send 503"],
+            observer.events()
+        );
+    }
+
+    #[tokio::test]
+    async fn should_observe_requests_without_response() {
+        let observer = RecordingObserver::default();
+        let client = client(FailingTransport, &observer);
+
+        let result = client.token_info("key", "token").await;
+
+        assert!(matches!(result, Err(FcmError::Transport(_))));
+        assert_eq!(vec!["response instance_info none"], observer.events());
+    }
+}
+
+#[cfg(feature = "tracing")]
+mod traces {
+    use super::FakeTransport;
+    use crate::v1::{self, Credentials, Target};
+    use crate::{Client, MessageBuilder};
+    use http::StatusCode;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::fmt;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+    use tracing_core::span::Current;
+
+    /// Records span names, events and all field values as strings, and
+    /// tracks the entered spans.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        records: Arc<Mutex<Vec<String>>>,
+        spans: Arc<Mutex<HashMap<u64, &'static Metadata<'static>>>>,
+        entered: Arc<Mutex<Vec<Id>>>,
+        next_id: Arc<AtomicU64>,
+    }
+
+    impl Recorder {
+        fn records(&self) -> Vec<String> {
+            self.records.lock().unwrap().clone()
+        }
+
+        fn visit(&self, record: impl FnOnce(&mut dyn Visit)) {
+            let mut records = self.records.lock().unwrap();
+            record(&mut FieldVisitor(&mut records));
+        }
+    }
+
+    struct FieldVisitor<'a>(&'a mut Vec<String>);
+
+    impl Visit for FieldVisitor<'_> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.push(format!("{}={}", field.name(), value));
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.push(format!("{}={:?}", field.name(), value));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            self.records
+                .lock()
+                .unwrap()
+                .push(format!("span {}", span.metadata().name()));
+            self.visit(|visitor| span.record(visitor));
+
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+            self.spans.lock().unwrap().insert(id, span.metadata());
+
+            Id::from_u64(id)
+        }
+
+        fn record(&self, _span: &Id, values: &Record<'_>) {
+            self.visit(|visitor| values.record(visitor));
+        }
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            self.records
+                .lock()
+                .unwrap()
+                .push(format!("event {}", event.metadata().level()));
+            self.visit(|visitor| event.record(visitor));
+        }
+
+        fn enter(&self, span: &Id) {
+            self.entered.lock().unwrap().push(span.clone());
+        }
+
+        fn exit(&self, _span: &Id) {
+            self.entered.lock().unwrap().pop();
+        }
+
+        fn current_span(&self) -> Current {
+            match self.entered.lock().unwrap().last() {
+                Some(id) => Current::new(id.clone(), self.spans.lock().unwrap()[&id.into_u64()]),
+                None => Current::none(),
+            }
+        }
+    }
+
+    fn assert_recorded(records: &[String], expected: &[&str]) {
+        for expected in expected {
+            assert!(
+                records.iter().any(|record| record == expected),
+                "{} not in {:?}",
+                expected,
+                records
+            );
+        }
+    }
+
+    fn assert_no_secrets(records: &[String]) {
+        for secret in &["secret_key", "token-one", "token-two", "access-token"] {
+            assert!(
+                records.iter().all(|record| !record.contains(secret)),
+                "{} recorded in {:?}",
+                secret,
+                records
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn should_trace_legacy_sends_without_tokens() {
+        let transport = FakeTransport::new(
+            StatusCode::OK,
+            json!({
+                "multicast_id": 42,
+                "success": 1,
+                "failure": 1,
+                "results": [{ "message_id": "1" }, { "error": "NotRegistered" }],
+            }),
+        );
+        let client = Client::with_transport(transport);
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        let mut builder = MessageBuilder::new_multi("secret_key", &["token-one", "token-two"]);
+        builder.dry_run(true);
+        client.send(builder.finalize()).await.unwrap();
+
+        let records = recorder.records();
+        assert_recorded(
+            &records,
+            &[
+                "span fcm.send",
+                "target=multicast",
+                "token_count=2",
+                "dry_run=true",
+                "status=200",
+                "multicast_id=42",
+                "failure=1",
+                "event WARN",
+                "index=1",
+                "error=NotRegistered",
+            ],
+        );
+        assert_no_secrets(&records);
+    }
+
+    #[tokio::test]
+    async fn should_trace_v1_errors_with_retry_after() {
+        let transport = FakeTransport::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            json!({
+                "error": {
+                    "code": 429,
+                    "message": "Quota exceeded for token-one",
+                    "status": "RESOURCE_EXHAUSTED",
+                    "details": [
+                        {
+                            "@type": "type.googleapis.com/google.firebase.fcm.v1.FcmError",
+                            "errorCode": "QUOTA_EXCEEDED"
+                        },
+                        { "@type": "type.googleapis.com/google.rpc.RetryInfo", "retryDelay": "30s" }
+                    ]
+                }
+            }),
+        );
+        let client = Client::with_transport(transport);
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        let message = v1::MessageBuilder::new(Target::Token("token-one".to_string())).finalize();
+        let result = client
+            .send_v1(&Credentials::access_token("project", "access-token"), &message)
+            .await;
+
+        assert!(result.is_err());
+
+        let records = recorder.records();
+        assert_recorded(
+            &records,
+            &[
+                "span fcm.send_v1",
+                "project_id=project",
+                "target=token",
+                "status=429",
+                "error=v1",
+                "error_code=QuotaExceeded",
+                "retry_after=30.0",
+                "event WARN",
+            ],
+        );
+        assert_no_secrets(&records);
+    }
+
+    #[tokio::test]
+    async fn should_trace_topic_management() {
+        let transport = FakeTransport::new(StatusCode::OK, json!({ "results": [{}, { "error": "NOT_FOUND" }] }));
+        let client = Client::with_transport(transport);
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        client
+            .subscribe_to_topic("secret_key", "/topics/news", &["token-one", "token-two"])
+            .await
+            .unwrap();
+
+        let records = recorder.records();
+        assert_recorded(
+            &records,
+            &[
+                "span fcm.topic_management",
+                "operation=subscribe",
+                "topic=news",
+                "token_count=2",
+                "success=1",
+                "failure=1",
+                "error=NOT_FOUND",
+            ],
+        );
+        assert_no_secrets(&records);
+    }
+}
+
+#[cfg(feature = "bulk")]
+mod bulk {
+    use super::*;
+    use futures::stream::{self, StreamExt};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Answers every send after a delay that depends on the target, and
+    /// tracks how many requests are in flight.
+    #[derive(Clone, Default)]
+    struct SlowTransport {
+        in_flight: Arc<AtomicUsize>,
+        max_in_flight: Arc<AtomicUsize>,
+        requests: Arc<AtomicUsize>,
+    }
+
+    impl Transport for SlowTransport {
+        fn execute(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, FcmError>> {
+            Box::pin(async move {
+                let body: Value = serde_json::from_slice(&request.body).unwrap();
+                let delay = if body["to"] == "slow" { 100 } else { 10 };
+
+                self.requests.fetch_add(1, Ordering::SeqCst);
+                let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+
+                tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
+                self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                Ok(HttpResponse {
+                    status: StatusCode::OK,
+                    headers: HeaderMap::new(),
+                    body: json!({"message_id": 1}).to_string().into_bytes(),
+                })
+            })
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_keep_at_most_n_requests_in_flight() {
+        let transport = SlowTransport::default();
+        let client = Client::with_transport(transport.clone());
+
+        let messages = (0..20).map(|i| (i, MessageBuilder::new("api_key", "token").finalize()));
+        let mut ids: Vec<i32> = client
+            .send_all_iter(messages, 3)
+            .map(|(id, result)| {
+                assert!(result.is_ok());
+                id
+            })
+            .collect()
+            .await;
+
+        ids.sort_unstable();
+
+        assert_eq!((0..20).collect::<Vec<_>>(), ids);
+        assert_eq!(3, transport.max_in_flight.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_yield_results_in_completion_order() {
+        let client = Client::with_transport(SlowTransport::default());
+
+        let messages = stream::iter(vec![
+            ("slow", MessageBuilder::new("api_key", "slow").finalize()),
+            ("fast", MessageBuilder::new("api_key", "fast").finalize()),
+        ]);
+        let ids: Vec<&str> = client.send_all(messages, 2).map(|(id, _)| id).collect().await;
+
+        assert_eq!(vec!["fast", "slow"], ids);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn should_drain_requests_in_flight_when_cancelled() {
+        let transport = SlowTransport::default();
+        let client = Client::with_transport(transport.clone());
+
+        let messages = (0..10).map(|i| (i, MessageBuilder::new("api_key", "token").finalize()));
+        let mut results = client.send_all_iter(messages, 2);
+
+        assert_eq!(0, results.next().await.unwrap().0);
+        results.canceller().cancel();
+
+        let rest: Vec<i32> = results.map(|(id, _)| id).collect().await;
+
+        assert_eq!(vec![1], rest);
+        assert_eq!(2, transport.requests.load(Ordering::SeqCst));
+        assert_eq!(0, transport.in_flight.load(Ordering::SeqCst));
+    }
+}
+
+mod send_each {
+    use super::*;
+    use crate::v1::{self, Credentials, MulticastMessage, Notification, Target};
+
+    /// Answers v1 sends to the token `bad` with an `UNREGISTERED` error and
+    /// all others with success.
+    #[derive(Clone, Default)]
+    struct V1Transport {
+        requests: Arc<Mutex<Vec<Value>>>,
+    }
+
+    impl Transport for V1Transport {
+        fn execute(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, FcmError>> {
+            let body: Value = serde_json::from_slice(&request.body).unwrap();
+            let token = body["message"]["token"].as_str().unwrap_or_default().to_string();
+            self.requests.lock().unwrap().push(body);
+
+            let (status, body) = if token == "bad" {
+                (
+                    StatusCode::NOT_FOUND,
+                    json!({
+                        "error": {
+                            "code": 404,
+                            "message": "Requested entity was not found.",
+                            "status": "NOT_FOUND",
+                            "details": [{
+                                "@type": "type.googleapis.com/google.firebase.fcm.v1.FcmError",
+                                "errorCode": "UNREGISTERED"
+                            }]
+                        }
+                    }),
+                )
+            } else {
+                (
+                    StatusCode::OK,
+                    json!({ "name": format!("projects/p/messages/{}", token) }),
+                )
+            };
+
+            Box::pin(async move {
+                Ok(HttpResponse {
+                    status,
+                    headers: HeaderMap::new(),
+                    body: body.to_string().into_bytes(),
+                })
+            })
+        }
+    }
+
+    fn token_message(token: &str) -> v1::Message {
+        v1::MessageBuilder::new(Target::Token(token.to_string())).finalize()
+    }
+
+    #[tokio::test]
+    async fn should_send_each_message_in_order() {
+        let client = Client::with_transport(V1Transport::default());
+        let credentials = Credentials::access_token("p", "token");
+
+        let messages = vec![token_message("a"), token_message("bad"), token_message("c")];
+        let response = client.send_each(&credentials, &messages).await.unwrap();
+
+        assert_eq!(2, response.success_count);
+        assert_eq!(1, response.failure_count);
+        assert_eq!("projects/p/messages/a", response.responses[0].as_ref().unwrap().name);
+        assert_eq!("projects/p/messages/c", response.responses[2].as_ref().unwrap().name);
+
+        let errors: Vec<_> = response.errors().collect();
+        assert_eq!(1, errors.len());
+        assert_eq!(1, errors[0].0);
+        assert_eq!(
+            Some(v1::ErrorCode::Unregistered),
+            errors[0].1.v1_status().and_then(|status| status.error_code())
+        );
+    }
+
+    #[tokio::test]
+    async fn should_send_a_multicast_message_to_each_token() {
+        let transport = V1Transport::default();
+        let client = Client::with_transport(transport.clone());
+        let credentials = Credentials::access_token("p", "token");
+
+        let message = MulticastMessage {
+            tokens: vec!["a".to_string(), "b".to_string()],
+            notification: Some(Notification {
+                title: Some("Hey!".to_string()),
+                ..Notification::default()
+            }),
+            validate_only: true,
+            ..MulticastMessage::default()
+        };
+
+        let response = client.send_each_for_multicast(&credentials, &message).await.unwrap();
+
+        assert_eq!(2, response.success_count);
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(
+            json!({
+                "message": {"token": "b", "notification": {"title": "Hey!"}},
+                "validate_only": true
+            }),
+            requests[1]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_reject_empty_and_oversized_batches() {
+        let transport = V1Transport::default();
+        let client = Client::with_transport(transport.clone());
+        let credentials = Credentials::access_token("p", "token");
+
+        let error = client.send_each(&credentials, &[]).await.unwrap_err();
+        assert!(matches!(error, FcmError::InvalidMessage(_)));
+
+        let message = MulticastMessage {
+            tokens: vec!["a".to_string(); v1::MAX_BATCH_SIZE + 1],
+            ..MulticastMessage::default()
+        };
+        let error = client
+            .send_each_for_multicast(&credentials, &message)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, FcmError::InvalidMessage(_)));
+
+        assert!(transport.requests.lock().unwrap().is_empty());
+    }
+}
+
+mod multipart {
+    use super::*;
+    use crate::client::multipart::{batch_request, batch_response};
+    use crate::v1::{self, BatchStrategy, Credentials, Target};
+    use crate::{ClientBuilder, Endpoints};
+
+    const RESPONSE: &str = "--batch_abc\r\n\
+        Content-Type: application/http\r\n\
+        Content-ID: response-2\r\n\
+        \r\n\
+        HTTP/1.1 404 Not Found\r\n\
+        Content-Type: application/json; charset=UTF-8\r\n\
+        \r\n\
+        {\"error\": {\"code\": 404, \"message\": \"not found\", \"status\": \"NOT_FOUND\", \"details\": [\
+        {\"@type\": \"type.googleapis.com/google.firebase.fcm.v1.FcmError\", \"errorCode\": \"UNREGISTERED\"}]}}\r\n\
+        --batch_abc\r\n\
+        Content-Type: application/http\r\n\
+        Content-ID: response-1\r\n\
+        \r\n\
+        HTTP/1.1 200 OK\r\n\
+        Content-Type: application/json; charset=UTF-8\r\n\
+        \r\n\
+        {\"name\": \"projects/p/messages/1\"}\r\n\
+        --batch_abc\r\n\
+        Content-Type: application/http\r\n\
+        Content-ID: response-3\r\n\
+        \r\n\
+        garbage\r\n\
+        --batch_abc--\r\n";
+
+    fn multipart_headers() -> HeaderMap {
+        headers(&[("content-type", "multipart/mixed; boundary=batch_abc")])
+    }
+
+    fn token_message(token: &str) -> v1::Message {
+        v1::MessageBuilder::new(Target::Token(token.to_string())).finalize()
+    }
+
+    #[test]
+    fn should_build_a_multipart_batch_request() {
+        let messages = vec![token_message("a"), token_message("b")];
+        let request = batch_request(&Endpoints::default(), "p", "token", &messages).unwrap();
+
+        let content_type = request.headers["content-type"].to_str().unwrap();
+        let boundary = content_type.strip_prefix("multipart/mixed; boundary=").unwrap();
+        let body = String::from_utf8(request.body).unwrap();
+
+        assert_eq!("https://fcm.googleapis.com/batch", request.url);
+        assert_eq!("Bearer token", request.headers["authorization"]);
+        assert_eq!(3, body.matches(&format!("--{}", boundary)).count());
+        assert!(body.ends_with(&format!("--{}--\r\n", boundary)));
+        assert!(body.contains("Content-ID: 2\r\n\r\nPOST /v1/projects/p/messages:send HTTP/1.1\r\n"));
+        assert!(body.contains("\r\n\r\n{\"message\":{\"token\":\"b\"}}\r\n"));
+    }
+
+    #[test]
+    fn should_parse_the_parts_of_a_batch_response() {
+        let results = batch_response(StatusCode::OK, &multipart_headers(), RESPONSE.as_bytes(), 4).unwrap();
+
+        assert_eq!(4, results.len());
+        assert_eq!("projects/p/messages/1", results[0].as_ref().unwrap().name);
+        assert_eq!(
+            Some(v1::ErrorCode::Unregistered),
+            results[1].as_ref().unwrap_err().v1_status().unwrap().error_code()
+        );
+        assert!(matches!(results[2], Err(FcmError::InvalidResponse(..))));
+        assert!(matches!(results[3], Err(FcmError::InvalidResponse(..))));
+    }
+
+    #[test]
+    fn should_fail_a_batch_response_that_is_not_multipart() {
+        let error = batch_response(StatusCode::OK, &HeaderMap::new(), b"{}", 1).unwrap_err();
+        assert!(matches!(error, FcmError::InvalidResponse(..)));
+
+        let error = batch_response(StatusCode::UNAUTHORIZED, &HeaderMap::new(), b"", 1).unwrap_err();
+        assert!(matches!(error, FcmError::Unauthorized));
+    }
+
+    #[derive(Clone, Default)]
+    struct BatchTransport {
+        requests: Arc<Mutex<Vec<HttpRequest>>>,
+    }
+
+    impl Transport for BatchTransport {
+        fn execute(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, FcmError>> {
+            self.requests.lock().unwrap().push(request);
+
+            Box::pin(async move {
+                Ok(HttpResponse {
+                    status: StatusCode::OK,
+                    headers: multipart_headers(),
+                    body: RESPONSE.as_bytes().to_vec(),
+                })
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn should_send_each_in_a_single_batch_request() {
+        let transport = BatchTransport::default();
+        let mut builder = ClientBuilder::new();
+        builder
+            .transport(transport.clone())
+            .batch_strategy(BatchStrategy::Multipart);
+        let client = builder.finalize().unwrap();
+
+        let messages = vec![token_message("a"), token_message("b"), token_message("c")];
+        let response = client
+            .send_each(&Credentials::access_token("p", "token"), &messages)
+            .await
+            .unwrap();
+
+        assert_eq!(1, response.success_count);
+        assert_eq!(2, response.failure_count);
+        assert_eq!(1, transport.requests.lock().unwrap().len());
+    }
+}
+
+#[cfg(all(feature = "blocking", feature = "fake-server"))]
+mod blocking {
+    use crate::client::blocking::Client;
+    use crate::fake_server::FakeFcm;
+    use crate::{ErrorReason, FcmError, MessageBuilder, RetryAfter};
+    use http::StatusCode;
+    use std::time::Duration;
+
+    /// Run `test` with a blocking client pointed at a fake server. The
+    /// server runs on its own runtime, since the blocking client must not be
+    /// used from within one.
+    fn with_server(test: impl FnOnce(&FakeFcm, Client)) {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let server = runtime.block_on(FakeFcm::start()).unwrap();
+
+        test(&server, Client::new().with_endpoints(server.endpoints()));
+
+        runtime.block_on(async move { drop(server) });
+    }
+
+    #[test]
+    fn should_send_messages() {
+        with_server(|server, client| {
+            server
+                .token_error("gone", ErrorReason::NotRegistered)
+                .canonical_id("moved", "new");
+
+            let response = client
+                .send(MessageBuilder::new_multi("api_key", &["ok", "gone", "moved"]).finalize())
+                .unwrap();
+            let results = response.results.unwrap();
+
+            assert_eq!(Some(2), response.success);
+            assert_eq!(Some(ErrorReason::NotRegistered), results[1].error);
+            assert_eq!(Some("new"), results[2].registration_id.as_deref());
+            assert_eq!(1, server.inbox("ok").len());
+        });
+    }
+
+    #[test]
+    fn should_manage_topics() {
+        with_server(|server, client| {
+            let response = client.subscribe_to_topic("api_key", "news", &["a", "b"]).unwrap();
+
+            assert_eq!(2, response.success_count());
+            assert_eq!(vec!["news"], server.topics("a"));
+
+            client
+                .unsubscribe_from_topic("api_key", "/topics/news", &["a"])
+                .unwrap();
+
+            assert!(server.topics("a").is_empty());
+            assert_eq!(vec!["news"], client.token_info("api_key", "b").unwrap().topics());
+        });
+    }
+
+    #[test]
+    fn should_map_errors() {
+        with_server(|server, client| {
+            server.server_key("right");
+
+            let result = client.send(MessageBuilder::new("wrong", "token").finalize());
+            assert!(matches!(result, Err(FcmError::Unauthorized)));
+
+            server.fail_next(1, StatusCode::SERVICE_UNAVAILABLE, Some(Duration::from_secs(30)));
+
+            let result = client.send(MessageBuilder::new("right", "token").finalize());
+            match result {
+                Err(FcmError::ServerError(Some(RetryAfter::Delay(delay)))) => {
+                    assert_eq!(chrono::Duration::seconds(30), delay)
+                }
+                other => panic!("unexpected result {:?}", other),
+            }
+
+            assert!(client.send(MessageBuilder::new("right", "token").finalize()).is_ok());
+        });
+    }
+}
@@ -0,0 +1,281 @@
+//! Spans and events of the `tracing` feature.
+//!
+//! Only counts, kinds and ids FCM assigns are recorded. Registration tokens,
+//! API keys, access tokens and error bodies, which may echo the request,
+//! never are.
+
+use crate::client::observer::TargetKind;
+use crate::client::transport::HttpResponse;
+use crate::client::TopicOperation;
+use crate::v1::{self, SendResponse, TokenResponse};
+use crate::{FcmError, FcmResponse, RetryAfter, TopicManagementResponse};
+use serde_json::Value;
+use tracing::field::Empty;
+use tracing::Span;
+
+/// The span of a legacy send with the JSON `body`.
+pub(crate) fn legacy_send(body: &Value) -> Span {
+    let target = TargetKind::of_legacy(body);
+    let token_count = match (target, body.get("registration_ids").and_then(Value::as_array)) {
+        (_, Some(ids)) => ids.len(),
+        (TargetKind::Token, None) => 1,
+        _ => 0,
+    };
+    let dry_run = body.get("dry_run").and_then(Value::as_bool).unwrap_or(false);
+
+    tracing::info_span!(
+        "fcm.send",
+        target = %target,
+        token_count,
+        dry_run,
+        status = Empty,
+        message_id = Empty,
+        multicast_id = Empty,
+        success = Empty,
+        failure = Empty,
+        canonical_ids = Empty,
+        error = Empty,
+        retry_after = Empty,
+    )
+}
+
+/// The span of a v1 send of `message` to `project_id`.
+pub(crate) fn v1_send(project_id: &str, message: &v1::Message) -> Span {
+    let target = TargetKind::from(&message.target);
+
+    tracing::info_span!(
+        "fcm.send_v1",
+        project_id,
+        target = %target,
+        token_count = if target == TargetKind::Token { 1 } else { 0 },
+        dry_run = message.validate_only,
+        status = Empty,
+        message_id = Empty,
+        error = Empty,
+        error_code = Empty,
+        retry_after = Empty,
+    )
+}
+
+/// The span of a v1 batch request sending `message_count` messages.
+pub(crate) fn batch(project_id: &str, message_count: usize) -> Span {
+    tracing::info_span!(
+        "fcm.send_batch",
+        project_id,
+        message_count,
+        status = Empty,
+        success = Empty,
+        failure = Empty,
+        error = Empty,
+        retry_after = Empty,
+    )
+}
+
+/// The span of a topic subscription change for `token_count` tokens.
+pub(crate) fn topic_management(operation: TopicOperation, topic: &str, token_count: usize) -> Span {
+    let operation = match operation {
+        TopicOperation::Subscribe => "subscribe",
+        TopicOperation::Unsubscribe => "unsubscribe",
+    };
+
+    tracing::info_span!(
+        "fcm.topic_management",
+        operation,
+        topic = topic.strip_prefix("/topics/").unwrap_or(topic),
+        token_count,
+        status = Empty,
+        success = Empty,
+        failure = Empty,
+        error = Empty,
+        retry_after = Empty,
+    )
+}
+
+/// The span of fetching a new access token for `project_id`.
+pub(crate) fn token_refresh(project_id: &str) -> Span {
+    tracing::info_span!(
+        "fcm.token_refresh",
+        project_id,
+        status = Empty,
+        expires_in = Empty,
+        error = Empty,
+    )
+}
+
+/// Record the HTTP status of a response, or the error if none was received.
+pub(crate) fn response(result: &Result<HttpResponse, FcmError>) {
+    match result {
+        Ok(response) => {
+            Span::current().record("status", response.status.as_u16());
+        }
+        Err(e) => error(e),
+    }
+}
+
+/// Record the outcome of a legacy send, warning about each registration
+/// token the message failed for.
+pub(crate) fn legacy_outcome(result: &Result<FcmResponse, FcmError>) {
+    let response = match result {
+        Ok(response) => response,
+        Err(e) => return error(e),
+    };
+
+    let span = Span::current();
+
+    if let Some(message_id) = response.message_id {
+        span.record("message_id", message_id);
+    }
+    if let Some(multicast_id) = response.multicast_id {
+        span.record("multicast_id", multicast_id);
+    }
+    if let Some(success) = response.success {
+        span.record("success", success);
+    }
+    if let Some(failure) = response.failure {
+        span.record("failure", failure);
+    }
+    if let Some(canonical_ids) = response.canonical_ids {
+        span.record("canonical_ids", canonical_ids);
+    }
+    if let Some(ref reason) = response.error {
+        span.record("error", tracing::field::display(reason));
+        tracing::warn!(error = %reason, "FCM rejected the message");
+    }
+
+    for (index, result) in response.results.iter().flatten().enumerate() {
+        if let Some(ref reason) = result.error {
+            tracing::warn!(
+                index,
+                error = %reason,
+                remove_token = reason.should_remove_token(),
+                "FCM rejected the message for a registration token"
+            );
+        }
+    }
+}
+
+/// Record the outcome of a v1 send.
+pub(crate) fn v1_outcome(result: &Result<SendResponse, FcmError>) {
+    match result {
+        Ok(response) => {
+            let message_id = response.name.rsplit('/').next().unwrap_or_default();
+            Span::current().record("message_id", message_id);
+        }
+        Err(e) => {
+            error(e);
+            if let FcmError::V1(ref status) = e {
+                tracing::warn!(
+                    grpc_status = %status.status,
+                    error_code = status.error_code().map(tracing::field::debug),
+                    remove_token = status.should_remove_token(),
+                    "FCM rejected the message"
+                );
+            }
+        }
+    }
+}
+
+/// Record the outcome of a v1 batch request, warning about each message
+/// that failed.
+pub(crate) fn batch_outcome(result: &Result<Vec<Result<SendResponse, FcmError>>, FcmError>) {
+    let responses = match result {
+        Ok(responses) => responses,
+        Err(e) => return error(e),
+    };
+
+    let failure = responses.iter().filter(|response| response.is_err()).count();
+    let span = Span::current();
+    span.record("success", responses.len() - failure);
+    span.record("failure", failure);
+
+    for (index, response) in responses.iter().enumerate() {
+        if let Err(ref e) = response {
+            let (grpc_status, error_code) = match e {
+                FcmError::V1(status) => (Some(status.status.as_str()), status.error_code()),
+                _ => (None, None),
+            };
+
+            tracing::warn!(
+                index,
+                error = error_kind(e),
+                grpc_status,
+                error_code = error_code.map(tracing::field::debug),
+                "FCM rejected a message of the batch"
+            );
+        }
+    }
+}
+
+/// Record the outcome of a topic subscription change, warning about each
+/// registration token that could not be changed.
+pub(crate) fn topic_management_outcome(result: &Result<TopicManagementResponse, FcmError>) {
+    let response = match result {
+        Ok(response) => response,
+        Err(e) => return error(e),
+    };
+
+    let span = Span::current();
+    span.record("success", response.success_count());
+    span.record("failure", response.failure_count());
+
+    for (index, result) in response.results.iter().enumerate() {
+        if let Some(ref error) = result.error {
+            tracing::warn!(index, error = %error, "the subscription of a registration token was not changed");
+        }
+    }
+}
+
+/// Record the outcome of an access token request.
+pub(crate) fn token_refresh_outcome(result: &Result<TokenResponse, FcmError>) {
+    match result {
+        Ok(token) => {
+            Span::current().record("expires_in", token.expires_in);
+        }
+        Err(e) => {
+            error(e);
+            tracing::warn!(error = error_kind(e), "fetching an access token failed");
+        }
+    }
+}
+
+/// Record an error with the Retry-After delay it asks for.
+fn error(error: &FcmError) {
+    let span = Span::current();
+    span.record("error", error_kind(error));
+
+    if let Some(retry_after) = error.retry_after() {
+        span.record("retry_after", retry_after_secs(&retry_after));
+    }
+
+    if let FcmError::V1(ref status) = error {
+        if let Some(code) = status.error_code() {
+            span.record("error_code", tracing::field::debug(code));
+        }
+    }
+}
+
+fn retry_after_secs(retry_after: &RetryAfter) -> f64 {
+    retry_after.wait_duration(chrono::Utc::now()).as_secs_f64()
+}
+
+/// The kind of an error, without the response body it may carry.
+fn error_kind(error: &FcmError) -> &'static str {
+    match error {
+        FcmError::Unauthorized => "unauthorized",
+        FcmError::InvalidMessage(_) => "invalid_message",
+        FcmError::ServerError(_) => "server_error",
+        FcmError::V1(_) => "v1",
+        FcmError::Connect(_) => "connect",
+        FcmError::Timeout(_) => "timeout",
+        FcmError::Transport(_) => "transport",
+        FcmError::Request(_) => "request",
+        FcmError::InvalidCredentials(_) => "invalid_credentials",
+        FcmError::Serialization(_) => "serialization",
+        FcmError::InvalidResponse(..) => "invalid_response",
+        FcmError::BadRequest(_) => "bad_request",
+        FcmError::Forbidden(_) => "forbidden",
+        FcmError::TooManyRequests(..) => "too_many_requests",
+        FcmError::UnexpectedStatus(_) => "unexpected_status",
+        FcmError::RateLimited(_) => "rate_limited",
+    }
+}
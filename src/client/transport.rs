@@ -0,0 +1,182 @@
+//! The HTTP layer used by [Client](../struct.Client.html).
+//!
+//! A [Transport](trait.Transport.html) takes an
+//! [HttpRequest](struct.HttpRequest.html) and returns the status, headers and
+//! body of the response. The default implementation,
+//! [ReqwestTransport](struct.ReqwestTransport.html), is available with any of
+//! the TLS features. Implement the trait to route requests through another
+//! HTTP stack, or to answer them in-process in tests:
+//!
+//! ```rust
+//! use fcm::transport::{BoxFuture, HttpRequest, HttpResponse, Transport};
+//! use fcm::FcmError;
+//! use http::{HeaderMap, StatusCode};
+//!
+//! struct AlwaysUnavailable;
+//!
+//! impl Transport for AlwaysUnavailable {
+//!     fn execute(&self, _: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, FcmError>> {
+//!         Box::pin(async {
+//!             Ok(HttpResponse {
+//!                 status: StatusCode::SERVICE_UNAVAILABLE,
+//!                 headers: HeaderMap::new(),
+//!                 body: Vec::new(),
+//!             })
+//!         })
+//!     }
+//! }
+//!
+//! let client = fcm::Client::with_transport(AlwaysUnavailable);
+//! ```
+
+use crate::FcmError;
+use http::header::{HeaderValue, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE};
+use http::{HeaderMap, Method, StatusCode};
+use std::future::Future;
+use std::pin::Pin;
+
+// The tests of each feature use a different part of it.
+#[cfg(all(
+    test,
+    any(
+        feature = "rate-limit",
+        feature = "outbox",
+        feature = "scheduler",
+        feature = "idempotency"
+    )
+))]
+#[allow(dead_code)]
+pub(crate) mod scripted;
+
+/// A boxed future, as returned by [Transport](trait.Transport.html).
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// An HTTP request the client wants to send.
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    /// A `POST` request with a JSON body, authorized with `authorization`.
+    pub(crate) fn post_json(url: String, authorization: &str, body: Vec<u8>) -> Result<HttpRequest, FcmError> {
+        let mut headers = HeaderMap::new();
+
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(body.len() as u64));
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(authorization).map_err(|e| FcmError::Request(e.into()))?,
+        );
+
+        Ok(HttpRequest {
+            method: Method::POST,
+            url,
+            headers,
+            body,
+        })
+    }
+
+    /// A `GET` request, authorized with `authorization`.
+    pub(crate) fn get(url: String, authorization: &str) -> Result<HttpRequest, FcmError> {
+        let mut headers = HeaderMap::new();
+
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(authorization).map_err(|e| FcmError::Request(e.into()))?,
+        );
+
+        Ok(HttpRequest {
+            method: Method::GET,
+            url,
+            headers,
+            body: Vec::new(),
+        })
+    }
+
+    /// A `POST` request with a form encoded body.
+    pub(crate) fn post_form(url: String, body: Vec<u8>) -> HttpRequest {
+        let mut headers = HeaderMap::new();
+
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(body.len() as u64));
+
+        HttpRequest {
+            method: Method::POST,
+            url,
+            headers,
+            body,
+        }
+    }
+}
+
+/// The response to an [HttpRequest](struct.HttpRequest.html).
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+/// Sends HTTP requests for the [Client](../struct.Client.html).
+///
+/// Errors should be reported with the transport variants of
+/// [FcmError](../enum.FcmError.html), e.g. `FcmError::Connect` when no
+/// connection could be established, so callers can tell whether a retry is
+/// safe. Responses with error statuses are not errors of the transport.
+pub trait Transport: Send + Sync + 'static {
+    /// Send the request and read the complete response.
+    fn execute(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, FcmError>>;
+}
+
+/// The default [Transport](trait.Transport.html), built on `reqwest`.
+#[cfg(feature = "reqwest")]
+#[derive(Debug, Clone)]
+pub struct ReqwestTransport {
+    http_client: reqwest::Client,
+}
+
+#[cfg(feature = "reqwest")]
+impl ReqwestTransport {
+    /// Get a new transport, or an error if the underlying HTTP client cannot
+    /// be initialized.
+    pub fn new() -> Result<ReqwestTransport, FcmError> {
+        let http_client = reqwest::ClientBuilder::new()
+            .pool_max_idle_per_host(usize::MAX)
+            .build()?;
+
+        Ok(ReqwestTransport { http_client })
+    }
+
+    /// Use an existing, preconfigured `reqwest` client.
+    pub fn from_client(http_client: reqwest::Client) -> ReqwestTransport {
+        ReqwestTransport { http_client }
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl Transport for ReqwestTransport {
+    fn execute(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, FcmError>> {
+        Box::pin(async move {
+            let request = self
+                .http_client
+                .request(request.method, &request.url)
+                .headers(request.headers)
+                .body(request.body)
+                .build()?;
+            let response = self.http_client.execute(request).await?;
+
+            let status = response.status();
+            let headers = response.headers().clone();
+            let body = response.bytes().await?.to_vec();
+
+            Ok(HttpResponse { status, headers, body })
+        })
+    }
+}
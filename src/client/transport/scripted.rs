@@ -0,0 +1,87 @@
+//! A transport for the tests of the modules that wrap a `Client`.
+
+use crate::client::transport::{BoxFuture, HttpRequest, HttpResponse, Transport};
+use crate::FcmError;
+use http::{HeaderMap, StatusCode};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Records requests and answers each with the next queued reply, or with the
+/// fallback reply once they run out. Clones share the recordings and replies.
+#[derive(Clone)]
+pub(crate) struct ScriptedTransport {
+    fallback: (StatusCode, Value),
+    replies: Arc<Mutex<VecDeque<(StatusCode, Value)>>>,
+    requests: Arc<Mutex<Vec<HttpRequest>>>,
+}
+
+impl Default for ScriptedTransport {
+    /// Answers with a success to both legacy and v1 sends.
+    fn default() -> Self {
+        let success = json!({
+            "multicast_id": 1,
+            "success": 1,
+            "failure": 0,
+            "canonical_ids": 0,
+            "results": [{"message_id": "1"}],
+            "name": "projects/p/messages/1"
+        });
+
+        ScriptedTransport::new(StatusCode::OK, success)
+    }
+}
+
+impl ScriptedTransport {
+    /// Answer every request without a queued reply with `status` and `body`.
+    pub(crate) fn new(status: StatusCode, body: Value) -> Self {
+        ScriptedTransport {
+            fallback: (status, body),
+            replies: Arc::new(Mutex::new(VecDeque::new())),
+            requests: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Answer the next request with `status` and `body`, after the replies
+    /// queued earlier.
+    pub(crate) fn reply(&self, status: StatusCode, body: Value) -> &Self {
+        self.replies.lock().unwrap().push_back((status, body));
+        self
+    }
+
+    /// How many requests were sent.
+    pub(crate) fn requests(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+
+    /// The JSON bodies of all requests, in order.
+    pub(crate) fn bodies(&self) -> Vec<Value> {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|request| serde_json::from_slice(&request.body).unwrap())
+            .collect()
+    }
+}
+
+impl Transport for ScriptedTransport {
+    fn execute(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, FcmError>> {
+        self.requests.lock().unwrap().push(request);
+
+        let (status, body) = self
+            .replies
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or_else(|| self.fallback.clone());
+
+        let response = HttpResponse {
+            status,
+            headers: HeaderMap::new(),
+            body: body.to_string().into_bytes(),
+        };
+
+        Box::pin(async move { Ok(response) })
+    }
+}
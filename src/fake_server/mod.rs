@@ -0,0 +1,940 @@
+//! An in-process fake of the FCM HTTP APIs for integration tests.
+//!
+//! Enable the `fake-server` feature to use this module. A
+//! [FakeFcm](struct.FakeFcm.html) server listens on a local port and answers
+//! the legacy `/fcm/send` and device group endpoints, the v1 `messages:send`
+//! endpoint, the OAuth token endpoint and the Instance ID endpoints. It checks authorization and
+//! payload limits the way FCM does, keeps the accepted messages in an inbox
+//! per target and can be told to fail:
+//!
+//! ```rust
+//! use fcm::fake_server::FakeFcm;
+//! use fcm::{ClientBuilder, ErrorReason, MessageBuilder};
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let server = FakeFcm::start().await?;
+//! server.token_error("stale-token", ErrorReason::NotRegistered);
+//!
+//! let mut builder = ClientBuilder::new();
+//! builder.endpoints(server.endpoints());
+//! let client = builder.finalize()?;
+//!
+//! let mut builder = MessageBuilder::new_multi("<FCM API Key>", &["fresh-token", "stale-token"]);
+//! builder.data(&serde_json::json!({ "message": "Howdy!" }))?;
+//!
+//! let response = client.send(builder.finalize()).await?;
+//! assert_eq!(Some(1), response.failure);
+//! assert_eq!(1, server.inbox("fresh-token").len());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::client::Endpoints;
+use crate::v1::MESSAGING_SCOPE;
+use crate::{ErrorReason, FcmResponse, MessageResult};
+use hyper::header::{HeaderValue, AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::convert::Infallible;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+#[cfg(test)]
+mod tests;
+
+/// The largest payload, in bytes, FCM accepts for messages to tokens.
+pub const MAX_PAYLOAD_SIZE: usize = 4096;
+
+/// The largest payload, in bytes, the legacy API accepts for messages to
+/// topics and conditions.
+pub const MAX_TOPIC_PAYLOAD_SIZE: usize = 2048;
+
+/// The most registration tokens a single legacy message can be sent to.
+pub const MAX_REGISTRATION_IDS: usize = 1000;
+
+/// Lifetime of the access tokens issued by the fake token endpoint.
+const ACCESS_TOKEN_LIFETIME: u64 = 3600;
+
+/// Which API a message was sent with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Api {
+    Legacy,
+    V1,
+}
+
+/// A message accepted by the fake server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReceivedMessage {
+    /// The API the message was sent with.
+    pub api: Api,
+
+    /// The legacy message body, or the `message` object of a v1 request.
+    pub body: Value,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Failure {
+    remaining: usize,
+    status: StatusCode,
+    retry_after: Option<Duration>,
+}
+
+struct DeviceGroup {
+    notification_key: String,
+    tokens: BTreeSet<String>,
+}
+
+#[derive(Default)]
+struct State {
+    server_keys: HashSet<String>,
+    access_tokens: HashSet<String>,
+    inbox: HashMap<String, Vec<ReceivedMessage>>,
+    failure: Option<Failure>,
+    token_errors: HashMap<String, ErrorReason>,
+    canonical_ids: HashMap<String, String>,
+    subscriptions: HashMap<String, BTreeMap<String, String>>,
+    groups: HashMap<String, DeviceGroup>,
+    latency: Duration,
+    next_id: u64,
+}
+
+impl State {
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn deliver(&mut self, target: String, message: ReceivedMessage) {
+        self.inbox.entry(target).or_default().push(message);
+    }
+
+    /// The status and `Retry-After` of the next request, if it should fail.
+    fn take_failure(&mut self) -> Option<Failure> {
+        let failure = self.failure.as_mut()?;
+        failure.remaining -= 1;
+
+        let result = *failure;
+
+        if failure.remaining == 0 {
+            self.failure = None;
+        }
+
+        Some(result)
+    }
+}
+
+/// A fake FCM server running on `127.0.0.1`.
+///
+/// Point a [Client](../struct.Client.html) at it with
+/// [endpoints](#method.endpoints). By default any non-empty server key is
+/// accepted by the legacy and Instance ID endpoints, and the v1 endpoint
+/// accepts the access tokens issued by the fake token endpoint. The token
+/// endpoint checks the form and claims of the JWT assertion, but not its
+/// signature.
+///
+/// The server shuts down when it is dropped. It must be started and dropped
+/// within a Tokio runtime.
+pub struct FakeFcm {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl FakeFcm {
+    /// Start a server on a free local port.
+    pub async fn start() -> io::Result<FakeFcm> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let service_state = state.clone();
+        let make_service = make_service_fn(move |_| {
+            let state = service_state.clone();
+
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let state = state.clone();
+                    async move { Ok::<_, Infallible>(handle(&state, request).await) }
+                }))
+            }
+        });
+
+        let (shutdown, stopped) = oneshot::channel();
+        let server = Server::from_tcp(listener)
+            .map_err(io::Error::other)?
+            .serve(make_service)
+            .with_graceful_shutdown(async {
+                stopped.await.ok();
+            });
+
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                log::error!("Fake FCM server failed: {}", e);
+            }
+        });
+
+        Ok(FakeFcm {
+            addr,
+            state,
+            shutdown: Some(shutdown),
+        })
+    }
+
+    /// The base URL of the server, e.g. `http://127.0.0.1:35411`.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Endpoints that route all requests of a client to this server.
+    pub fn endpoints(&self) -> Endpoints {
+        Endpoints::all(&self.url())
+    }
+
+    /// Only accept the given server key, and any other key added with this
+    /// method, on the legacy and Instance ID endpoints.
+    pub fn server_key(&self, key: &str) -> &Self {
+        self.state().server_keys.insert(key.to_string());
+        self
+    }
+
+    /// Accept `token` as a bearer token on the v1 endpoint, e.g. for
+    /// [Credentials::access_token](../v1/struct.Credentials.html#method.access_token).
+    pub fn access_token(&self, token: &str) -> &Self {
+        self.state().access_tokens.insert(token.to_string());
+        self
+    }
+
+    /// Answer the next `count` send requests with `status`, and a
+    /// `Retry-After` header if `retry_after` is given. Replaces an earlier
+    /// injected failure.
+    pub fn fail_next(&self, count: usize, status: StatusCode, retry_after: Option<Duration>) -> &Self {
+        self.state().failure = if count > 0 {
+            Some(Failure {
+                remaining: count,
+                status,
+                retry_after,
+            })
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Reject every message to `token` with `reason`. The v1 endpoint answers
+    /// with the matching v1 error, e.g. `UNREGISTERED` for `NotRegistered`.
+    pub fn token_error(&self, token: &str, reason: ErrorReason) -> &Self {
+        self.state().token_errors.insert(token.to_string(), reason);
+        self
+    }
+
+    /// Accept messages to `token`, but report `canonical_id` as its new
+    /// registration id in legacy responses.
+    pub fn canonical_id(&self, token: &str, canonical_id: &str) -> &Self {
+        self.state()
+            .canonical_ids
+            .insert(token.to_string(), canonical_id.to_string());
+        self
+    }
+
+    /// Wait `latency` before answering each request.
+    pub fn latency(&self, latency: Duration) -> &Self {
+        self.state().latency = latency;
+        self
+    }
+
+    /// The messages accepted for `target`, in order. The target is a
+    /// registration token, a topic with the `/topics/` prefix or a
+    /// condition. Dry runs and validate-only messages are not kept.
+    pub fn inbox(&self, target: &str) -> Vec<ReceivedMessage> {
+        self.state().inbox.get(target).cloned().unwrap_or_default()
+    }
+
+    /// The topics `token` is subscribed to, without the `/topics/` prefix.
+    pub fn topics(&self, token: &str) -> Vec<String> {
+        self.state()
+            .subscriptions
+            .get(token)
+            .map(|topics| topics.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// The registration tokens of the device group called `name`, if it
+    /// exists.
+    pub fn device_group(&self, name: &str) -> Option<Vec<String>> {
+        self.state()
+            .groups
+            .get(name)
+            .map(|group| group.tokens.iter().cloned().collect())
+    }
+
+    /// Forget all accepted messages. Subscriptions and injected faults are
+    /// kept.
+    pub fn clear_inbox(&self) {
+        self.state().inbox.clear();
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        lock(&self.state)
+    }
+}
+
+impl Drop for FakeFcm {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            shutdown.send(()).ok();
+        }
+    }
+}
+
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+async fn handle(state: &Mutex<State>, request: Request<Body>) -> Response<Body> {
+    let latency = lock(state).latency;
+
+    if latency > Duration::from_secs(0) {
+        tokio::time::sleep(latency).await;
+    }
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let authorization = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let sender_id = request
+        .headers()
+        .get("project_id")
+        .and_then(|value| value.to_str().ok())
+        .map(String::from);
+
+    let body = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return text(StatusCode::BAD_REQUEST, &e.to_string()),
+    };
+
+    let mut state = lock(state);
+
+    match (method, path.as_str()) {
+        (Method::POST, "/token") => token(&mut state, &body),
+        (Method::POST, "/fcm/send") => legacy_send(&mut state, &authorization, &body),
+        (Method::POST, "/fcm/notification") => device_group(&mut state, &authorization, sender_id, &body),
+        (Method::POST, "/iid/v1:batchAdd") => manage_topic(&mut state, &authorization, &body, true),
+        (Method::POST, "/iid/v1:batchRemove") => manage_topic(&mut state, &authorization, &body, false),
+        (Method::GET, path) if path.starts_with("/iid/info/") => {
+            instance_info(&state, &authorization, &path["/iid/info/".len()..])
+        }
+        (Method::POST, path) if path.starts_with("/v1/projects/") && path.ends_with("/messages:send") => {
+            let project_id = &path["/v1/projects/".len()..path.len() - "/messages:send".len()];
+            v1_send(&mut state, project_id, &authorization, &body)
+        }
+        _ => text(StatusCode::NOT_FOUND, "Not Found"),
+    }
+}
+
+fn legacy_send(state: &mut State, authorization: &str, body: &[u8]) -> Response<Body> {
+    if !is_server_key(state, authorization) {
+        return unauthorized();
+    }
+
+    if let Some(failure) = state.take_failure() {
+        return failed(failure, text(failure.status, "Service Unavailable"));
+    }
+
+    let message: Value = match serde_json::from_slice(body) {
+        Ok(message @ Value::Object(_)) => message,
+        _ => return text(StatusCode::BAD_REQUEST, "JSON_PARSING_ERROR: Unexpected token"),
+    };
+
+    let dry_run = message.get("dry_run").and_then(Value::as_bool).unwrap_or(false);
+    let received = ReceivedMessage {
+        api: Api::Legacy,
+        body: message.clone(),
+    };
+
+    let topic_target = match (message.get("to").and_then(Value::as_str), message.get("condition")) {
+        (Some(to), None) if to.starts_with("/topics/") => Some(to.to_string()),
+        (None, Some(Value::String(condition))) => Some(condition.clone()),
+        _ => None,
+    };
+
+    if let Some(target) = topic_target {
+        if payload_size(&message) > MAX_TOPIC_PAYLOAD_SIZE {
+            return json_response(
+                StatusCode::OK,
+                &FcmResponse {
+                    error: Some(ErrorReason::MessageTooBig),
+                    ..FcmResponse::default()
+                },
+            );
+        }
+
+        if !dry_run {
+            state.deliver(target, received);
+        }
+
+        return json_response(
+            StatusCode::OK,
+            &FcmResponse {
+                message_id: Some(state.next_id()),
+                ..FcmResponse::default()
+            },
+        );
+    }
+
+    let tokens: Vec<String> = match (message.get("to"), message.get("registration_ids")) {
+        (Some(Value::String(to)), None) => vec![to.clone()],
+        (None, Some(Value::Array(ids))) => ids.iter().filter_map(Value::as_str).map(String::from).collect(),
+        _ => return text(StatusCode::BAD_REQUEST, "Missing \"to\" or \"registration_ids\" field"),
+    };
+
+    if tokens.is_empty() || tokens.len() > MAX_REGISTRATION_IDS {
+        return text(
+            StatusCode::BAD_REQUEST,
+            &format!(
+                "Number of messages on bulk ({}) exceeds maximum allowed ({})",
+                tokens.len(),
+                MAX_REGISTRATION_IDS
+            ),
+        );
+    }
+
+    let too_big = payload_size(&message) > MAX_PAYLOAD_SIZE;
+
+    let results: Vec<MessageResult> = tokens
+        .iter()
+        .map(|token| {
+            let error = if too_big {
+                Some(ErrorReason::MessageTooBig)
+            } else {
+                state.token_errors.get(token).cloned()
+            };
+
+            if let Some(error) = error {
+                return MessageResult {
+                    error: Some(error),
+                    ..MessageResult::default()
+                };
+            }
+
+            if !dry_run {
+                state.deliver(token.clone(), received.clone());
+            }
+
+            MessageResult {
+                message_id: Some(format!("0:{}", state.next_id())),
+                registration_id: state.canonical_ids.get(token).cloned(),
+                ..MessageResult::default()
+            }
+        })
+        .collect();
+
+    let failure = results.iter().filter(|result| result.error.is_some()).count() as u64;
+    let canonical_ids = results.iter().filter(|result| result.registration_id.is_some()).count() as u64;
+
+    json_response(
+        StatusCode::OK,
+        &FcmResponse {
+            multicast_id: Some(state.next_id() as i64),
+            success: Some(results.len() as u64 - failure),
+            failure: Some(failure),
+            canonical_ids: Some(canonical_ids),
+            results: Some(results),
+            ..FcmResponse::default()
+        },
+    )
+}
+
+fn v1_send(state: &mut State, project_id: &str, authorization: &str, body: &[u8]) -> Response<Body> {
+    let token = authorization.strip_prefix("Bearer ").unwrap_or_default();
+
+    if !state.access_tokens.contains(token) {
+        return v1_error(
+            StatusCode::UNAUTHORIZED,
+            "UNAUTHENTICATED",
+            "Request had invalid authentication credentials.",
+            None,
+        );
+    }
+
+    if let Some(failure) = state.take_failure() {
+        let response = v1_error(
+            failure.status,
+            "UNAVAILABLE",
+            "The service is currently unavailable.",
+            None,
+        );
+        return failed(failure, response);
+    }
+
+    let request: Value = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(e) => return v1_error(StatusCode::BAD_REQUEST, "INVALID_ARGUMENT", &e.to_string(), None),
+    };
+
+    let message = match request.get("message") {
+        Some(message @ Value::Object(_)) => message.clone(),
+        _ => return invalid_argument("Request contains an invalid argument.", "message", "Missing message"),
+    };
+
+    let validate_only = request.get("validate_only").and_then(Value::as_bool).unwrap_or(false);
+
+    let target = match (
+        message.get("token").and_then(Value::as_str),
+        message.get("topic").and_then(Value::as_str),
+        message.get("condition").and_then(Value::as_str),
+    ) {
+        (Some(token), None, None) => token.to_string(),
+        (None, Some(topic), None) => format!("/topics/{}", topic),
+        (None, None, Some(condition)) => condition.to_string(),
+        _ => {
+            return invalid_argument(
+                "Exactly one of token, topic or condition must be specified.",
+                "message",
+                "Invalid target",
+            )
+        }
+    };
+
+    if let Some(data) = message.get("data").and_then(Value::as_object) {
+        if let Some((key, _)) = data.iter().find(|(_, value)| !value.is_string()) {
+            return invalid_argument(
+                "Invalid value in data, only strings are allowed.",
+                &format!("message.data[{}]", key),
+                "Invalid value",
+            );
+        }
+    }
+
+    if payload_size(&message) > MAX_PAYLOAD_SIZE {
+        return invalid_argument("Message is too big.", "message", "Message is too big");
+    }
+
+    if let Some(reason) = state.token_errors.get(&target) {
+        return v1_token_error(reason);
+    }
+
+    if !validate_only {
+        state.deliver(
+            target,
+            ReceivedMessage {
+                api: Api::V1,
+                body: message,
+            },
+        );
+    }
+
+    json_response(
+        StatusCode::OK,
+        &json!({ "name": format!("projects/{}/messages/{}", project_id, state.next_id()) }),
+    )
+}
+
+/// The v1 error FCM answers with instead of the legacy `reason`.
+fn v1_token_error(reason: &ErrorReason) -> Response<Body> {
+    let (status, status_name, error_code) = match reason {
+        ErrorReason::NotRegistered => (StatusCode::NOT_FOUND, "NOT_FOUND", "UNREGISTERED"),
+        ErrorReason::MismatchSenderId => (StatusCode::FORBIDDEN, "PERMISSION_DENIED", "SENDER_ID_MISMATCH"),
+        ErrorReason::Unavailable => (StatusCode::SERVICE_UNAVAILABLE, "UNAVAILABLE", "UNAVAILABLE"),
+        ErrorReason::InternalServerError => (StatusCode::INTERNAL_SERVER_ERROR, "INTERNAL", "INTERNAL"),
+        ErrorReason::InvalidRegistration | ErrorReason::MissingRegistration => {
+            return invalid_argument(
+                "The registration token is not a valid FCM registration token",
+                "message.token",
+                "Invalid registration token",
+            )
+        }
+        _ => (StatusCode::BAD_REQUEST, "INVALID_ARGUMENT", "INVALID_ARGUMENT"),
+    };
+
+    v1_error(status, status_name, &reason.to_string(), Some(error_code))
+}
+
+fn invalid_argument(message: &str, field: &str, description: &str) -> Response<Body> {
+    let details = json!([
+        {
+            "@type": "type.googleapis.com/google.firebase.fcm.v1.FcmError",
+            "errorCode": "INVALID_ARGUMENT",
+        },
+        {
+            "@type": "type.googleapis.com/google.rpc.BadRequest",
+            "fieldViolations": [{ "field": field, "description": description }],
+        },
+    ]);
+
+    json_response(
+        StatusCode::BAD_REQUEST,
+        &json!({
+            "error": {
+                "code": 400,
+                "message": message,
+                "status": "INVALID_ARGUMENT",
+                "details": details,
+            }
+        }),
+    )
+}
+
+fn v1_error(status: StatusCode, status_name: &str, message: &str, error_code: Option<&str>) -> Response<Body> {
+    let details: Vec<Value> = error_code
+        .map(|code| {
+            json!({
+                "@type": "type.googleapis.com/google.firebase.fcm.v1.FcmError",
+                "errorCode": code,
+            })
+        })
+        .into_iter()
+        .collect();
+
+    json_response(
+        status,
+        &json!({
+            "error": {
+                "code": status.as_u16(),
+                "message": message,
+                "status": status_name,
+                "details": details,
+            }
+        }),
+    )
+}
+
+fn token(state: &mut State, body: &[u8]) -> Response<Body> {
+    let body = String::from_utf8_lossy(body);
+
+    if form_value(&body, "grant_type").as_deref() != Some("urn:ietf:params:oauth:grant-type:jwt-bearer") {
+        return oauth_error("unsupported_grant_type", "Invalid grant_type");
+    }
+
+    let assertion = match form_value(&body, "assertion") {
+        Some(assertion) => assertion,
+        None => return oauth_error("invalid_request", "Missing required parameter: assertion"),
+    };
+
+    if let Err(description) = check_assertion(&assertion) {
+        return oauth_error("invalid_grant", description);
+    }
+
+    let access_token = format!("fake-access-token-{}", state.next_id());
+    state.access_tokens.insert(access_token.clone());
+
+    json_response(
+        StatusCode::OK,
+        &json!({
+            "access_token": access_token,
+            "expires_in": ACCESS_TOKEN_LIFETIME,
+            "token_type": "Bearer",
+        }),
+    )
+}
+
+/// Check the claims of a JWT assertion, without verifying its signature.
+fn check_assertion(assertion: &str) -> Result<(), &'static str> {
+    use base64::Engine;
+
+    let parts: Vec<&str> = assertion.split('.').collect();
+
+    if parts.len() != 3 {
+        return Err("Invalid JWT: Token must consist of three parts");
+    }
+
+    let claims: Value = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(parts[1])
+        .ok()
+        .and_then(|claims| serde_json::from_slice(&claims).ok())
+        .ok_or("Invalid JWT: Claims are not valid JSON")?;
+
+    if claims.get("iss").and_then(Value::as_str).is_none() {
+        return Err("Invalid JWT: Missing issuer");
+    }
+
+    let scopes = claims.get("scope").and_then(Value::as_str).unwrap_or_default();
+
+    if !scopes.split(' ').any(|scope| scope == MESSAGING_SCOPE) {
+        return Err("Invalid JWT: Missing messaging scope");
+    }
+
+    let now = chrono::Utc::now().timestamp();
+
+    match claims.get("exp").and_then(Value::as_i64) {
+        Some(exp) if exp > now => Ok(()),
+        _ => Err("Invalid JWT: Token must be a short-lived token and in a reasonable timeframe"),
+    }
+}
+
+fn oauth_error(error: &str, description: &str) -> Response<Body> {
+    json_response(
+        StatusCode::BAD_REQUEST,
+        &json!({ "error": error, "error_description": description }),
+    )
+}
+
+fn manage_topic(state: &mut State, authorization: &str, body: &[u8], subscribe: bool) -> Response<Body> {
+    if !is_server_key(state, authorization) {
+        return unauthorized();
+    }
+
+    let request: Value = serde_json::from_slice(body).unwrap_or(Value::Null);
+
+    let topic = request
+        .get("to")
+        .and_then(Value::as_str)
+        .and_then(|to| to.strip_prefix("/topics/"))
+        .filter(|topic| !topic.is_empty());
+
+    let tokens = request.get("registration_tokens").and_then(Value::as_array);
+
+    let (topic, tokens) = match (topic, tokens) {
+        (Some(topic), Some(tokens)) if !tokens.is_empty() => (topic, tokens),
+        _ => return json_response(StatusCode::BAD_REQUEST, &json!({ "error": "InvalidParameters" })),
+    };
+
+    let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+
+    let results: Vec<Value> = tokens
+        .iter()
+        .map(|token| {
+            let token = match token.as_str() {
+                Some(token) => token,
+                None => return json!({ "error": "INVALID_ARGUMENT" }),
+            };
+
+            match state.token_errors.get(token) {
+                Some(ErrorReason::NotRegistered) => return json!({ "error": "NOT_FOUND" }),
+                Some(ErrorReason::InvalidRegistration) => return json!({ "error": "INVALID_ARGUMENT" }),
+                _ => {}
+            }
+
+            let topics = state.subscriptions.entry(token.to_string()).or_default();
+
+            if subscribe {
+                topics.entry(topic.to_string()).or_insert_with(|| today.clone());
+            } else {
+                topics.remove(topic);
+            }
+
+            json!({})
+        })
+        .collect();
+
+    json_response(StatusCode::OK, &json!({ "results": results }))
+}
+
+fn instance_info(state: &State, authorization: &str, token: &str) -> Response<Body> {
+    if !is_server_key(state, authorization) {
+        return unauthorized();
+    }
+
+    if let Some(ErrorReason::NotRegistered) | Some(ErrorReason::InvalidRegistration) = state.token_errors.get(token) {
+        return json_response(
+            StatusCode::NOT_FOUND,
+            &json!({ "error": "No information found about this instance id." }),
+        );
+    }
+
+    let topics: serde_json::Map<String, Value> = state
+        .subscriptions
+        .get(token)
+        .into_iter()
+        .flatten()
+        .map(|(topic, date)| (topic.clone(), json!({ "addDate": date })))
+        .collect();
+
+    json_response(
+        StatusCode::OK,
+        &json!({
+            "application": "com.example.fake",
+            "authorizedEntity": "0",
+            "platform": "ANDROID",
+            "rel": { "topics": topics },
+        }),
+    )
+}
+
+fn device_group(state: &mut State, authorization: &str, sender_id: Option<String>, body: &[u8]) -> Response<Body> {
+    if !is_server_key(state, authorization) {
+        return unauthorized();
+    }
+
+    let group_error = |message: &str| json_response(StatusCode::BAD_REQUEST, &json!({ "error": message }));
+
+    if sender_id.is_none() {
+        return group_error("project_id header is missing");
+    }
+
+    let request: Value = serde_json::from_slice(body).unwrap_or(Value::Null);
+
+    let operation = request.get("operation").and_then(Value::as_str).unwrap_or_default();
+    let name = request.get("notification_key_name").and_then(Value::as_str);
+    let key = request.get("notification_key").and_then(Value::as_str);
+    let tokens: Vec<String> = request
+        .get("registration_ids")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(String::from)
+        .collect();
+
+    let name = match name {
+        Some(name) if !tokens.is_empty() => name.to_string(),
+        _ => return group_error("notification_key_name and registration_ids are required"),
+    };
+
+    if operation == "create" {
+        if state.groups.contains_key(&name) {
+            return group_error("notification_key already exists");
+        }
+
+        let notification_key = format!("fake-notification-key-{}", state.next_id());
+
+        state.groups.insert(
+            name,
+            DeviceGroup {
+                notification_key: notification_key.clone(),
+                tokens: tokens.into_iter().collect(),
+            },
+        );
+
+        return json_response(StatusCode::OK, &json!({ "notification_key": notification_key }));
+    }
+
+    let group = match state.groups.get_mut(&name) {
+        Some(group) if Some(group.notification_key.as_str()) == key => group,
+        _ => return group_error("notification_key not found"),
+    };
+
+    let notification_key = group.notification_key.clone();
+
+    match operation {
+        "add" => group.tokens.extend(tokens),
+        "remove" => {
+            for token in &tokens {
+                group.tokens.remove(token);
+            }
+
+            if group.tokens.is_empty() {
+                state.groups.remove(&name);
+            }
+        }
+        _ => return group_error("invalid operation"),
+    }
+
+    json_response(StatusCode::OK, &json!({ "notification_key": notification_key }))
+}
+
+fn is_server_key(state: &State, authorization: &str) -> bool {
+    match authorization.strip_prefix("key=") {
+        Some(key) if !key.is_empty() => state.server_keys.is_empty() || state.server_keys.contains(key),
+        _ => false,
+    }
+}
+
+/// The size of the parts of a message that count against the payload limit.
+fn payload_size(message: &Value) -> usize {
+    ["data", "notification"]
+        .iter()
+        .filter_map(|key| message.get(*key))
+        .map(|value| value.to_string().len())
+        .sum()
+}
+
+/// The decoded value of `key` in a form encoded body.
+fn form_value(body: &str, key: &str) -> Option<String> {
+    body.split('&').find_map(|pair| {
+        let mut parts = pair.splitn(2, '=');
+
+        if parts.next()? == key {
+            Some(percent_decode(parts.next().unwrap_or_default()))
+        } else {
+            None
+        }
+    })
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (escaped, bytes[i]) {
+            (Some(byte), _) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (None, b'+') => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (None, byte) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn failed(failure: Failure, mut response: Response<Body>) -> Response<Body> {
+    *response.status_mut() = failure.status;
+
+    if let Some(retry_after) = failure.retry_after {
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(retry_after.as_secs()));
+    }
+
+    response
+}
+
+fn unauthorized() -> Response<Body> {
+    let mut response = text(
+        StatusCode::UNAUTHORIZED,
+        "<HTML><HEAD><TITLE>The request's Authentication (Server-) Key contained an invalid or \
+         ill-formed FCM-Token (a.k.a. IID-Token).</TITLE></HEAD></HTML>",
+    );
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("text/html; charset=UTF-8"));
+    response
+}
+
+fn text(status: StatusCode, body: &str) -> Response<Body> {
+    let mut response = Response::new(Body::from(body.to_string()));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("text/plain; charset=UTF-8"));
+    response
+}
+
+fn json_response<T: serde::Serialize>(status: StatusCode, body: &T) -> Response<Body> {
+    let mut response = Response::new(Body::from(
+        serde_json::to_vec(body).expect("responses serialize to JSON"),
+    ));
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/json; charset=UTF-8"),
+    );
+    response
+}
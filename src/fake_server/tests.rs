@@ -0,0 +1,357 @@
+use crate::fake_server::{Api, FakeFcm};
+use crate::v1::{self, Credentials, ErrorCode, Target};
+use crate::{Client, ClientBuilder, ErrorReason, FcmError, MessageBuilder, NotificationBuilder};
+use http::StatusCode;
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+fn client(server: &FakeFcm) -> Client {
+    let mut builder = ClientBuilder::new();
+    builder.endpoints(server.endpoints());
+    builder.finalize().unwrap()
+}
+
+#[tokio::test]
+async fn should_deliver_legacy_messages_to_inboxes() {
+    let server = FakeFcm::start().await.unwrap();
+    let client = client(&server);
+
+    let mut notification = NotificationBuilder::new();
+    notification.title("Hey!");
+
+    let mut builder = MessageBuilder::new_multi("api_key", &["one", "two"]);
+    builder.notification(notification.finalize());
+
+    let response = client.send(builder.finalize()).await.unwrap();
+
+    assert_eq!(Some(2), response.success);
+    assert_eq!(Some(0), response.failure);
+    assert_eq!(1, server.inbox("one").len());
+    assert_eq!(Api::Legacy, server.inbox("two")[0].api);
+    assert_eq!(json!("Hey!"), server.inbox("two")[0].body["notification"]["title"]);
+
+    let response = client
+        .send(MessageBuilder::new("api_key", "/topics/news").finalize())
+        .await
+        .unwrap();
+
+    assert!(response.message_id.is_some());
+    assert_eq!(1, server.inbox("/topics/news").len());
+}
+
+#[tokio::test]
+async fn should_not_keep_dry_runs() {
+    let server = FakeFcm::start().await.unwrap();
+
+    let mut builder = MessageBuilder::new("api_key", "token");
+    builder.dry_run(true);
+
+    client(&server).send(builder.finalize()).await.unwrap();
+
+    assert!(server.inbox("token").is_empty());
+}
+
+#[tokio::test]
+async fn should_check_the_server_key() {
+    let server = FakeFcm::start().await.unwrap();
+    server.server_key("right");
+
+    let client = client(&server);
+
+    let result = client.send(MessageBuilder::new("wrong", "token").finalize()).await;
+    assert!(matches!(result, Err(FcmError::Unauthorized)));
+
+    let result = client.send(MessageBuilder::new("right", "token").finalize()).await;
+    assert!(result.is_ok());
+}
+
+#[tokio::test]
+async fn should_enforce_payload_limits() {
+    let server = FakeFcm::start().await.unwrap();
+    let client = client(&server);
+
+    let big = "x".repeat(3000);
+
+    let mut builder = MessageBuilder::new("api_key", "token");
+    builder.data(&json!({ "big": big })).unwrap();
+    let response = client.send(builder.finalize()).await.unwrap();
+    assert_eq!(Some(1), response.success);
+
+    let mut builder = MessageBuilder::new("api_key", "/topics/news");
+    builder.data(&json!({ "big": big })).unwrap();
+    let response = client.send(builder.finalize()).await.unwrap();
+    assert_eq!(Some(ErrorReason::MessageTooBig), response.error);
+
+    let tokens: Vec<String> = (0..1001).map(|i| i.to_string()).collect();
+    let result = client
+        .send(MessageBuilder::new_multi("api_key", &tokens).finalize())
+        .await;
+    assert!(matches!(result, Err(FcmError::BadRequest(_))));
+}
+
+#[tokio::test]
+async fn should_inject_token_errors_and_canonical_ids() {
+    let server = FakeFcm::start().await.unwrap();
+    server
+        .token_error("gone", ErrorReason::NotRegistered)
+        .token_error("busy", ErrorReason::Unavailable)
+        .canonical_id("old", "new");
+
+    let response = client(&server)
+        .send(MessageBuilder::new_multi("api_key", &["gone", "busy", "old"]).finalize())
+        .await
+        .unwrap();
+
+    let results = response.results.unwrap();
+
+    assert_eq!(Some(ErrorReason::NotRegistered), results[0].error);
+    assert_eq!(Some(ErrorReason::Unavailable), results[1].error);
+    assert_eq!(Some("new".to_string()), results[2].registration_id);
+    assert_eq!(Some(1), response.canonical_ids);
+    assert!(server.inbox("gone").is_empty());
+    assert_eq!(1, server.inbox("old").len());
+}
+
+#[tokio::test]
+async fn should_fail_the_next_requests() {
+    let server = FakeFcm::start().await.unwrap();
+    server.fail_next(2, StatusCode::SERVICE_UNAVAILABLE, Some(Duration::from_secs(30)));
+
+    let client = client(&server);
+
+    for _ in 0..2 {
+        match client.send(MessageBuilder::new("api_key", "token").finalize()).await {
+            Err(FcmError::ServerError(retry_after)) => {
+                assert_eq!(
+                    Some(crate::RetryAfter::Delay(chrono::Duration::seconds(30))),
+                    retry_after
+                )
+            }
+            result => panic!("expected a server error, got {:?}", result),
+        }
+    }
+
+    assert!(client
+        .send(MessageBuilder::new("api_key", "token").finalize())
+        .await
+        .is_ok());
+    assert_eq!(1, server.inbox("token").len());
+}
+
+#[tokio::test]
+async fn should_delay_responses() {
+    let server = FakeFcm::start().await.unwrap();
+    server.latency(Duration::from_millis(100));
+
+    let started = Instant::now();
+    client(&server)
+        .send(MessageBuilder::new("api_key", "token").finalize())
+        .await
+        .unwrap();
+
+    assert!(started.elapsed() >= Duration::from_millis(100));
+}
+
+#[tokio::test]
+async fn should_manage_topic_subscriptions() {
+    let server = FakeFcm::start().await.unwrap();
+    server.token_error("gone", ErrorReason::NotRegistered);
+
+    let client = client(&server);
+
+    let response = client
+        .subscribe_to_topic("api_key", "news", &["one", "gone"])
+        .await
+        .unwrap();
+
+    assert_eq!(1, response.success_count());
+    assert_eq!(Some("NOT_FOUND".to_string()), response.results[1].error);
+    assert_eq!(vec!["news".to_string()], server.topics("one"));
+
+    client
+        .unsubscribe_from_topic("api_key", "news", &["one"])
+        .await
+        .unwrap();
+
+    assert!(server.topics("one").is_empty());
+}
+
+#[tokio::test]
+async fn should_send_v1_messages_with_access_tokens() {
+    let server = FakeFcm::start().await.unwrap();
+    server.access_token("secret");
+
+    let client = client(&server);
+    let mut builder = v1::MessageBuilder::new(Target::Topic("news".to_string()));
+    builder.data(vec![("message", "Howdy!")]);
+    let message = builder.finalize();
+
+    let response = client
+        .send_v1(&Credentials::access_token("project", "secret"), &message)
+        .await
+        .unwrap();
+
+    assert!(response.name.starts_with("projects/project/messages/"));
+    assert_eq!(Api::V1, server.inbox("/topics/news")[0].api);
+    assert_eq!(json!("Howdy!"), server.inbox("/topics/news")[0].body["data"]["message"]);
+
+    let result = client
+        .send_v1(&Credentials::access_token("project", "wrong"), &message)
+        .await;
+
+    match result {
+        Err(FcmError::V1(status)) => assert_eq!(401, status.code),
+        result => panic!("expected a v1 error, got {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn should_answer_v1_token_errors() {
+    let server = FakeFcm::start().await.unwrap();
+    server
+        .access_token("secret")
+        .token_error("gone", ErrorReason::NotRegistered)
+        .token_error("invalid", ErrorReason::InvalidRegistration);
+
+    let client = client(&server);
+    let credentials = Credentials::access_token("project", "secret");
+
+    for token in &["gone", "invalid"] {
+        let message = v1::MessageBuilder::new(Target::Token(token.to_string())).finalize();
+
+        match client.send_v1(&credentials, &message).await {
+            Err(error) => assert!(error.should_remove_token(), "{:?}", error),
+            Ok(response) => panic!("expected an error, got {:?}", response),
+        }
+    }
+
+    let mut builder = v1::MessageBuilder::new(Target::Token("token".to_string()));
+    builder.data(vec![("big", "x".repeat(5000))]);
+
+    match client.send_v1(&credentials, &builder.finalize()).await {
+        Err(FcmError::V1(status)) => assert_eq!(Some(ErrorCode::InvalidArgument), status.error_code()),
+        result => panic!("expected a v1 error, got {:?}", result),
+    }
+}
+
+#[cfg(feature = "service-account")]
+#[tokio::test]
+async fn should_exchange_service_account_assertions_for_access_tokens() {
+    let key = v1::ServiceAccountKey::from_json(
+        &json!({
+            "project_id": "project",
+            "private_key_id": "key-id",
+            "private_key": include_str!("../../tests/data/test_key.pem"),
+            "client_email": "sender@project.iam.gserviceaccount.com",
+        })
+        .to_string(),
+    )
+    .unwrap();
+
+    let server = FakeFcm::start().await.unwrap();
+    let client = client(&server);
+    let credentials = Credentials::service_account(key);
+
+    let message = v1::MessageBuilder::new(Target::Token("token".to_string())).finalize();
+
+    client.send_v1(&credentials, &message).await.unwrap();
+    client.send_v1(&credentials, &message).await.unwrap();
+
+    assert_eq!(2, server.inbox("token").len());
+    assert_eq!(Some("fake-access-token-1".to_string()), credentials.cached_token());
+}
+
+#[cfg(feature = "service-account")]
+#[tokio::test]
+async fn should_refresh_the_access_token_once_for_concurrent_sends() {
+    use crate::{Endpoint, SendObserver};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct TokenRequests(Arc<AtomicUsize>);
+
+    impl SendObserver for TokenRequests {
+        fn on_response(&self, endpoint: Endpoint, _status: Option<StatusCode>, _latency: Duration) {
+            if endpoint == Endpoint::OAuthToken {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    let key = v1::ServiceAccountKey::from_json(
+        &json!({
+            "project_id": "project",
+            "private_key": include_str!("../../tests/data/test_key.pem"),
+            "client_email": "sender@project.iam.gserviceaccount.com",
+        })
+        .to_string(),
+    )
+    .unwrap();
+
+    let server = FakeFcm::start().await.unwrap();
+    server.latency(Duration::from_millis(50));
+
+    let token_requests = Arc::new(AtomicUsize::new(0));
+
+    let mut builder = ClientBuilder::new();
+    builder
+        .endpoints(server.endpoints())
+        .observer(TokenRequests(token_requests.clone()));
+    let client = builder.finalize().unwrap();
+
+    let credentials = Credentials::service_account(key);
+    let message = v1::MessageBuilder::new(Target::Token("token".to_string())).finalize();
+
+    let (first, second, third) = tokio::join!(
+        client.send_v1(&credentials, &message),
+        client.send_v1(&credentials, &message),
+        client.send_v1(&credentials, &message),
+    );
+
+    assert!(first.is_ok() && second.is_ok() && third.is_ok());
+    assert_eq!(3, server.inbox("token").len());
+    assert_eq!(1, token_requests.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn should_manage_device_groups() {
+    let server = FakeFcm::start().await.unwrap();
+    let client = client(&server);
+
+    let created = client
+        .create_device_group("api_key", "sender", "family", &["a", "b"])
+        .await
+        .unwrap();
+
+    let duplicate = client.create_device_group("api_key", "sender", "family", &["c"]).await;
+    assert!(matches!(duplicate, Err(FcmError::BadRequest(_))));
+
+    client
+        .add_to_device_group("api_key", "sender", "family", &created.notification_key, &["c"])
+        .await
+        .unwrap();
+    client
+        .remove_from_device_group("api_key", "sender", "family", &created.notification_key, &["a"])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        Some(vec!["b".to_string(), "c".to_string()]),
+        server.device_group("family")
+    );
+}
+
+#[tokio::test]
+async fn should_report_token_info() {
+    let server = FakeFcm::start().await.unwrap();
+    server.token_error("gone", ErrorReason::NotRegistered);
+
+    let client = client(&server);
+    client.subscribe_to_topic("api_key", "news", &["one"]).await.unwrap();
+
+    let info = client.token_info("api_key", "one").await.unwrap();
+    assert_eq!(vec!["news"], info.topics());
+
+    let gone = client.token_info("api_key", "gone").await;
+    assert!(matches!(gone, Err(FcmError::UnexpectedStatus(ref raw)) if raw.status == 404));
+}
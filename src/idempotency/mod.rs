@@ -0,0 +1,270 @@
+//! Idempotent sends that do not deliver a message twice when a send is
+//! retried.
+//!
+//! Enable the `idempotency` feature to use this module. An
+//! [IdempotentClient](struct.IdempotentClient.html) gives every send a key,
+//! either supplied by the caller or derived from the content of the message.
+//! When a send with the same key succeeded within the deduplication window,
+//! the stored response is returned instead of sending the message again.
+//! Only successful responses are stored, so a send that failed, also for
+//! some of its tokens, can be retried.
+//!
+//! The key is also added to the data of the message as
+//! [DEDUP_ID_KEY](constant.DEDUP_ID_KEY.html), so apps can drop duplicates
+//! that reached the device anyway, e.g. when a request timed out after FCM
+//! accepted it.
+//!
+//! ```no_run
+//! use fcm::idempotency::IdempotentClient;
+//! use fcm::{Client, MessageBuilder};
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), fcm::FcmError> {
+//! let client = IdempotentClient::new(Client::new());
+//!
+//! let message = MessageBuilder::new("<FCM API Key>", "<registration id>").finalize();
+//! let response = client.send_with_key("order-1234-shipped", message).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::v1::{self, Credentials, SendResponse};
+use crate::{Client, FcmError, FcmResponse, Message};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+#[cfg(test)]
+mod tests;
+
+/// The data key the deduplication id is sent with.
+pub const DEDUP_ID_KEY: &str = "fcm_dedup_id";
+
+/// Expired entries are dropped once a memory store holds more keys than
+/// this.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// A response stored for an idempotency key.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "api", content = "response", rename_all = "lowercase")]
+pub enum StoredResponse {
+    Legacy(FcmResponse),
+    V1(SendResponse),
+}
+
+/// Remembers the responses to recent sends by their idempotency key.
+///
+/// Share a store between processes, e.g. by implementing it on top of a
+/// database, to deduplicate sends across them.
+pub trait IdempotencyStore: Send + Sync + 'static {
+    /// The response stored for `key`, unless it expired before `now`.
+    fn get(&self, key: &str, now: DateTime<Utc>) -> Option<StoredResponse>;
+
+    /// Store the response for `key` until `expires_at`.
+    fn insert(&self, key: &str, response: StoredResponse, expires_at: DateTime<Utc>);
+}
+
+/// An [IdempotencyStore](trait.IdempotencyStore.html) in the memory of the
+/// process.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    entries: Mutex<HashMap<String, (DateTime<Utc>, StoredResponse)>>,
+}
+
+impl MemoryStore {
+    /// Get a new, empty store.
+    pub fn new() -> MemoryStore {
+        Self::default()
+    }
+
+    fn entries(&self) -> MutexGuard<'_, HashMap<String, (DateTime<Utc>, StoredResponse)>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// How many keys are stored, including expired ones.
+    pub fn len(&self) -> usize {
+        self.entries().len()
+    }
+
+    /// `true` if no keys are stored.
+    pub fn is_empty(&self) -> bool {
+        self.entries().is_empty()
+    }
+}
+
+impl IdempotencyStore for MemoryStore {
+    fn get(&self, key: &str, now: DateTime<Utc>) -> Option<StoredResponse> {
+        match self.entries().get(key) {
+            Some((expires_at, response)) if *expires_at > now => Some(response.clone()),
+            _ => None,
+        }
+    }
+
+    fn insert(&self, key: &str, response: StoredResponse, expires_at: DateTime<Utc>) {
+        let mut entries = self.entries();
+
+        if entries.len() >= PRUNE_THRESHOLD {
+            let now = Utc::now();
+            entries.retain(|_, (expires_at, _)| *expires_at > now);
+        }
+
+        entries.insert(key.to_string(), (expires_at, response));
+    }
+}
+
+/// The 64-bit FNV-1a hash of `bytes`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    bytes
+        .iter()
+        .fold(OFFSET_BASIS, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(PRIME))
+}
+
+/// The key of a value derived from its content. Object keys are sorted, so
+/// the order in which fields were set does not matter.
+fn fingerprint_value(value: &Value) -> String {
+    format!("{:016x}", fnv1a(value.to_string().as_bytes()))
+}
+
+/// The content-derived idempotency key of a legacy message: a hash of its
+/// body, which includes the targets. The server key is not part of it.
+pub fn fingerprint(message: &Message<'_>) -> Result<String, FcmError> {
+    let body = serde_json::to_value(&message.body).map_err(FcmError::Serialization)?;
+
+    Ok(fingerprint_value(&body))
+}
+
+/// The content-derived idempotency key of a v1 message sent to
+/// `project_id`.
+pub fn fingerprint_v1(project_id: &str, message: &v1::Message) -> Result<String, FcmError> {
+    let message = serde_json::to_value(message).map_err(FcmError::Serialization)?;
+
+    Ok(fingerprint_value(&serde_json::json!([project_id, message])))
+}
+
+/// Sends messages at most once per idempotency key within a window, see the
+/// [module documentation](index.html).
+///
+/// Two sends with the same key that run at the same time are not
+/// deduplicated, only a send after one that completed is.
+pub struct IdempotentClient<S = MemoryStore> {
+    client: Client,
+    store: S,
+    window: Duration,
+}
+
+impl IdempotentClient<MemoryStore> {
+    /// Get a new client that sends through `client` and remembers keys in
+    /// memory for one hour.
+    pub fn new(client: Client) -> IdempotentClient<MemoryStore> {
+        Self::with_store(client, MemoryStore::new())
+    }
+}
+
+impl<S: IdempotencyStore> IdempotentClient<S> {
+    /// Get a new client that sends through `client` and remembers keys in
+    /// `store` for one hour.
+    pub fn with_store(client: Client, store: S) -> IdempotentClient<S> {
+        IdempotentClient {
+            client,
+            store,
+            window: Duration::hours(1),
+        }
+    }
+
+    /// How long a successful send is remembered.
+    pub fn window(&mut self, window: Duration) -> &mut Self {
+        self.window = window;
+        self
+    }
+
+    /// The store of the client.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Send a legacy message, deduplicated by its content.
+    pub async fn send(&self, message: Message<'_>) -> Result<FcmResponse, FcmError> {
+        let key = fingerprint(&message)?;
+
+        self.send_with_key(&key, message).await
+    }
+
+    /// Send a legacy message, deduplicated by `key`. Dry runs are not
+    /// remembered, so a real send with the same key is still delivered.
+    pub async fn send_with_key(&self, key: &str, message: Message<'_>) -> Result<FcmResponse, FcmError> {
+        if let Some(StoredResponse::Legacy(response)) = self.stored(key) {
+            return Ok(response);
+        }
+
+        let mut body = serde_json::to_value(&message.body).map_err(FcmError::Serialization)?;
+
+        match body.get_mut("data") {
+            Some(Value::Object(data)) => {
+                data.insert(DEDUP_ID_KEY.to_string(), Value::String(key.to_string()));
+            }
+            _ => body["data"] = serde_json::json!({ DEDUP_ID_KEY: key }),
+        }
+
+        let dry_run = body["dry_run"] == true;
+        let payload = serde_json::to_vec(&body).map_err(FcmError::Serialization)?;
+        let response = self.client.send_payload(message.api_key, payload).await?;
+
+        // FCM answers 200 even when some or all tokens failed, e.g. with
+        // `Unavailable`, and those must be retried.
+        if !dry_run && response.error.is_none() && response.failure.unwrap_or(0) == 0 {
+            self.remember(key, StoredResponse::Legacy(response.clone()));
+        }
+
+        Ok(response)
+    }
+
+    /// Send a v1 message, deduplicated by its content.
+    pub async fn send_v1(&self, credentials: &Credentials, message: &v1::Message) -> Result<SendResponse, FcmError> {
+        let key = fingerprint_v1(credentials.project_id(), message)?;
+
+        self.send_v1_with_key(&key, credentials, message).await
+    }
+
+    /// Send a v1 message, deduplicated by `key`. Messages that are only
+    /// validated are not remembered.
+    pub async fn send_v1_with_key(
+        &self,
+        key: &str,
+        credentials: &Credentials,
+        message: &v1::Message,
+    ) -> Result<SendResponse, FcmError> {
+        if let Some(StoredResponse::V1(response)) = self.stored(key) {
+            return Ok(response);
+        }
+
+        let mut message = message.clone();
+        message.data.insert(DEDUP_ID_KEY.to_string(), key.to_string());
+
+        let response = self.client.send_v1(credentials, &message).await?;
+
+        if !message.validate_only {
+            self.remember(key, StoredResponse::V1(response.clone()));
+        }
+
+        Ok(response)
+    }
+
+    fn stored(&self, key: &str) -> Option<StoredResponse> {
+        let response = self.store.get(key, Utc::now());
+
+        if response.is_some() {
+            log::warn!("skipping duplicate send with idempotency key {}", key);
+        }
+
+        response
+    }
+
+    fn remember(&self, key: &str, response: StoredResponse) {
+        self.store.insert(key, response, Utc::now() + self.window);
+    }
+}
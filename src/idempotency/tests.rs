@@ -0,0 +1,231 @@
+use super::{
+    fingerprint, fingerprint_v1, IdempotencyStore, IdempotentClient, MemoryStore, StoredResponse, DEDUP_ID_KEY,
+};
+use crate::client::transport::scripted::ScriptedTransport;
+use crate::v1::{self, Credentials, Target};
+use crate::{Client, FcmError, FcmResponse, MessageBuilder};
+use chrono::{Duration, Utc};
+use http::StatusCode;
+use serde_json::json;
+
+fn setup() -> (ScriptedTransport, IdempotentClient) {
+    let transport = ScriptedTransport::default();
+    let client = IdempotentClient::new(Client::with_transport(transport.clone()));
+
+    (transport, client)
+}
+
+fn v1_message(token: &str) -> v1::Message {
+    let mut builder = v1::MessageBuilder::new(Target::Token(token.to_string()));
+    builder.data(vec![("foo", "bar")]);
+    builder.finalize()
+}
+
+#[test]
+fn should_fingerprint_by_content() {
+    let mut first = MessageBuilder::new("key_a", "token");
+    first.data(&json!({"a": 1, "b": 2})).unwrap();
+    let mut second = MessageBuilder::new("key_b", "token");
+    second.data(&json!({"b": 2, "a": 1})).unwrap();
+    let other = MessageBuilder::new("key_a", "other");
+
+    let first = fingerprint(&first.finalize()).unwrap();
+
+    assert_eq!(16, first.len());
+    assert_eq!(first, fingerprint(&second.finalize()).unwrap());
+    assert_ne!(first, fingerprint(&other.finalize()).unwrap());
+
+    let message = v1_message("token");
+    assert_eq!(
+        fingerprint_v1("p", &message).unwrap(),
+        fingerprint_v1("p", &message.clone()).unwrap()
+    );
+    assert_ne!(
+        fingerprint_v1("p", &message).unwrap(),
+        fingerprint_v1("q", &message).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn should_send_duplicates_once() {
+    let (transport, client) = setup();
+
+    let first = client
+        .send(MessageBuilder::new("key", "token").finalize())
+        .await
+        .unwrap();
+    let second = client
+        .send(MessageBuilder::new("key", "token").finalize())
+        .await
+        .unwrap();
+    client
+        .send(MessageBuilder::new("key", "other").finalize())
+        .await
+        .unwrap();
+
+    assert_eq!(first.multicast_id, second.multicast_id);
+    assert_eq!(2, transport.requests());
+}
+
+#[tokio::test]
+async fn should_embed_the_dedup_id() {
+    let (transport, client) = setup();
+
+    let mut builder = MessageBuilder::new("key", "token");
+    builder.data(&json!({"foo": "bar"})).unwrap();
+    client.send_with_key("order-1", builder.finalize()).await.unwrap();
+    client
+        .send_with_key("order-2", MessageBuilder::new("key", "token").finalize())
+        .await
+        .unwrap();
+
+    let bodies = transport.bodies();
+    assert_eq!(json!({"foo": "bar", DEDUP_ID_KEY: "order-1"}), bodies[0]["data"]);
+    assert_eq!(json!({ DEDUP_ID_KEY: "order-2" }), bodies[1]["data"]);
+}
+
+#[tokio::test]
+async fn should_dedup_by_caller_key() {
+    let (transport, client) = setup();
+
+    client
+        .send_with_key("order-1", MessageBuilder::new("key", "token").finalize())
+        .await
+        .unwrap();
+    client
+        .send_with_key("order-1", MessageBuilder::new("key", "other").finalize())
+        .await
+        .unwrap();
+
+    assert_eq!(1, transport.requests());
+}
+
+#[tokio::test]
+async fn should_not_remember_dry_runs() {
+    let (transport, client) = setup();
+
+    let mut dry_run = MessageBuilder::new("key", "token");
+    dry_run.dry_run(true);
+
+    client.send_with_key("order-1", dry_run.finalize()).await.unwrap();
+    client
+        .send_with_key("order-1", MessageBuilder::new("key", "token").finalize())
+        .await
+        .unwrap();
+
+    let mut validate_only = v1::MessageBuilder::new(Target::Token("token".to_string()));
+    validate_only.validate_only(true);
+    let credentials = Credentials::access_token("p", "t");
+
+    client
+        .send_v1_with_key("order-2", &credentials, &validate_only.finalize())
+        .await
+        .unwrap();
+    client
+        .send_v1_with_key("order-2", &credentials, &v1_message("token"))
+        .await
+        .unwrap();
+
+    assert_eq!(4, transport.requests());
+}
+
+#[tokio::test]
+async fn should_answer_queued_replies_in_order() {
+    let (transport, client) = setup();
+    transport.reply(StatusCode::SERVICE_UNAVAILABLE, json!({}));
+    transport.reply(StatusCode::BAD_REQUEST, json!({}));
+
+    let first = client.send(MessageBuilder::new("key", "token").finalize()).await;
+    let second = client.send(MessageBuilder::new("key", "token").finalize()).await;
+
+    assert!(matches!(first, Err(FcmError::ServerError(_))));
+    assert!(matches!(second, Err(FcmError::BadRequest(_))));
+}
+
+#[tokio::test]
+async fn should_resend_after_a_failure() {
+    let (transport, client) = setup();
+    transport.reply(StatusCode::SERVICE_UNAVAILABLE, json!({}));
+
+    let first = client.send(MessageBuilder::new("key", "token").finalize()).await;
+    let second = client.send(MessageBuilder::new("key", "token").finalize()).await;
+
+    assert!(matches!(first, Err(FcmError::ServerError(_))));
+    assert!(second.is_ok());
+    assert_eq!(2, transport.requests());
+    assert_eq!(transport.bodies()[0]["data"], transport.bodies()[1]["data"]);
+}
+
+#[tokio::test]
+async fn should_resend_after_token_errors() {
+    let (transport, client) = setup();
+    transport.reply(
+        StatusCode::OK,
+        json!({"multicast_id": 1, "success": 0, "failure": 1, "results": [{"error": "Unavailable"}]}),
+    );
+
+    let first = client
+        .send(MessageBuilder::new("key", "token").finalize())
+        .await
+        .unwrap();
+    let second = client
+        .send(MessageBuilder::new("key", "token").finalize())
+        .await
+        .unwrap();
+
+    assert_eq!(Some(1), first.failure);
+    assert_eq!(Some(1), second.success);
+    assert_eq!(2, transport.requests());
+}
+
+#[tokio::test]
+async fn should_resend_after_the_window() {
+    let (transport, mut client) = setup();
+    client.window(Duration::zero());
+
+    client
+        .send(MessageBuilder::new("key", "token").finalize())
+        .await
+        .unwrap();
+    client
+        .send(MessageBuilder::new("key", "token").finalize())
+        .await
+        .unwrap();
+
+    assert_eq!(2, transport.requests());
+}
+
+#[tokio::test]
+async fn should_send_v1_duplicates_once() {
+    let (transport, client) = setup();
+    let credentials = Credentials::access_token("p", "token");
+    let message = v1_message("token");
+
+    let first = client.send_v1(&credentials, &message).await.unwrap();
+    let second = client.send_v1(&credentials, &message).await.unwrap();
+
+    assert_eq!(first, second);
+
+    let bodies = transport.bodies();
+    let key = fingerprint_v1("p", &message).unwrap();
+    assert_eq!(1, bodies.len());
+    assert_eq!(json!(key), bodies[0]["message"]["data"][DEDUP_ID_KEY]);
+    assert_eq!(json!("bar"), bodies[0]["message"]["data"]["foo"]);
+}
+
+#[test]
+fn should_expire_stored_responses() {
+    let store = MemoryStore::new();
+    let now = Utc::now();
+
+    store.insert(
+        "key",
+        StoredResponse::Legacy(FcmResponse::default()),
+        now + Duration::seconds(10),
+    );
+
+    assert!(store.get("key", now).is_some());
+    assert!(store.get("key", now + Duration::seconds(10)).is_none());
+    assert!(store.get("other", now).is_none());
+    assert_eq!(1, store.len());
+}
@@ -64,5 +64,24 @@ mod notification;
 pub use crate::notification::*;
 mod client;
 pub use crate::client::*;
+pub mod v1;
+
+#[cfg(feature = "testing")]
+pub mod testing;
+
+#[cfg(feature = "fake-server")]
+pub mod fake_server;
+
+#[cfg(feature = "rate-limit")]
+pub mod rate_limit;
+
+#[cfg(feature = "outbox")]
+pub mod outbox;
+
+#[cfg(feature = "scheduler")]
+pub mod scheduler;
+
+#[cfg(feature = "idempotency")]
+pub mod idempotency;
 
 pub use crate::client::response::FcmError as Error;
@@ -0,0 +1,155 @@
+use std::borrow::Cow;
+
+use crate::message::{Message, MessageBuilder, Priority};
+
+/// A builder for iOS background (silent) pushes.
+///
+/// APNs silently drops background pushes that are sent with the wrong
+/// headers. FCM derives the APNs headers from the legacy message fields, so
+/// this builder always sets `content_available` (sent as `content-available:
+/// 1`) and `Priority::Normal`, which FCM translates to `apns-priority: 5` and
+/// `apns-push-type: background`. It has no way to attach a notification, so
+/// no alert, sound or badge can end up in the payload.
+///
+/// # Examples
+///
+/// ```rust
+/// use fcm::BackgroundMessageBuilder;
+/// use std::collections::HashMap;
+///
+/// let mut map = HashMap::new();
+/// map.insert("sync", "inbox");
+///
+/// let mut builder = BackgroundMessageBuilder::new("<FCM API Key>", "<registration id>");
+/// builder.data(&map).unwrap();
+/// let message = builder.finalize();
+///
+/// assert!(message.apns_lints().is_empty());
+/// ```
+#[derive(Debug)]
+pub struct BackgroundMessageBuilder<'a> {
+    inner: MessageBuilder<'a>,
+}
+
+impl<'a> BackgroundMessageBuilder<'a> {
+    /// Get a new instance of a background message. You need to supply to.
+    pub fn new(api_key: &'a str, to: &'a str) -> Self {
+        BackgroundMessageBuilder {
+            inner: MessageBuilder::new(api_key, to),
+        }
+    }
+
+    /// Get a new instance of a background message. You need to supply
+    /// registration ids.
+    pub fn new_multi<S>(api_key: &'a str, ids: &'a [S]) -> Self
+    where
+        S: Into<Cow<'a, str>> + AsRef<str>,
+    {
+        BackgroundMessageBuilder {
+            inner: MessageBuilder::new_multi(api_key, ids),
+        }
+    }
+
+    /// Set this parameter to identify groups of messages that can be collapsed.
+    pub fn collapse_key(&mut self, collapse_key: &'a str) -> &mut Self {
+        self.inner.collapse_key(collapse_key);
+        self
+    }
+
+    /// How long (in seconds) to keep the message on FCM servers in case the device
+    /// is offline. The maximum and default is 4 weeks.
+    pub fn time_to_live(&mut self, time_to_live: i32) -> &mut Self {
+        self.inner.time_to_live(time_to_live);
+        self
+    }
+
+    /// Package name of the application where the registration tokens must match.
+    pub fn restricted_package_name(&mut self, restricted_package_name: &'a str) -> &mut Self {
+        self.inner.restricted_package_name(restricted_package_name);
+        self
+    }
+
+    /// When set to `true`, allows you to test FCM without actually sending the message.
+    pub fn dry_run(&mut self, dry_run: bool) -> &mut Self {
+        self.inner.dry_run(dry_run);
+        self
+    }
+
+    /// Use this to add custom key-value pairs to the message. See
+    /// [MessageBuilder::data](struct.MessageBuilder.html#method.data).
+    pub fn data(&mut self, data: &dyn erased_serde::Serialize) -> Result<&mut Self, serde_json::Error> {
+        self.inner.data(data)?;
+        Ok(self)
+    }
+
+    /// Complete the build and get a `Message` instance
+    pub fn finalize(mut self) -> Message<'a> {
+        self.inner.content_available(true).priority(Priority::Normal);
+        self.inner.finalize()
+    }
+}
+
+/// A problem with how a message combines background push settings, as
+/// reported by [Message::apns_lints](struct.Message.html#method.apns_lints).
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum ApnsLint {
+    /// `content_available` is set together with `Priority::High`. FCM sends
+    /// the push with `apns-priority: 10`, which APNs rejects for background
+    /// pushes.
+    HighPriority,
+
+    /// `content_available` is set together with a notification title or body,
+    /// so the push is delivered as a visible alert instead of in the
+    /// background.
+    Alert,
+
+    /// `content_available` is set together with a notification sound.
+    Sound,
+
+    /// `content_available` is set together with a notification badge.
+    Badge,
+}
+
+impl<'a> Message<'a> {
+    /// Check the message for settings that break iOS background pushes. A
+    /// message without `content_available` never yields any lints.
+    ///
+    /// # Examples:
+    /// ```rust
+    /// use fcm::{ApnsLint, MessageBuilder, Priority};
+    ///
+    /// let mut builder = MessageBuilder::new("<FCM API Key>", "<registration id>");
+    /// builder.content_available(true).priority(Priority::High);
+    /// let message = builder.finalize();
+    ///
+    /// assert_eq!(vec![ApnsLint::HighPriority], message.apns_lints());
+    /// ```
+    pub fn apns_lints(&self) -> Vec<ApnsLint> {
+        let body = &self.body;
+        let mut lints = Vec::new();
+
+        if body.content_available != Some(true) {
+            return lints;
+        }
+
+        if body.priority == Some(Priority::High) {
+            lints.push(ApnsLint::HighPriority);
+        }
+
+        if let Some(ref notification) = body.notification {
+            if notification.has_alert() {
+                lints.push(ApnsLint::Alert);
+            }
+
+            if notification.has_sound() {
+                lints.push(ApnsLint::Sound);
+            }
+
+            if notification.has_badge() {
+                lints.push(ApnsLint::Badge);
+            }
+        }
+
+        lints
+    }
+}
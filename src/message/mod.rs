@@ -5,6 +5,9 @@ use serde_json::Value;
 
 use crate::notification::Notification;
 
+mod background;
+pub use self::background::*;
+
 #[cfg(test)]
 mod tests;
 
@@ -20,6 +23,9 @@ pub struct MessageBody<'a> {
     #[serde(skip_serializing_if = "Option::is_none")]
     collapse_key: Option<&'a str>,
 
+    #[serde(skip_serializing_if = "Option::is_none")]
+    condition: Option<&'a str>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     content_available: Option<bool>,
 
@@ -83,6 +89,7 @@ pub struct Message<'a> {
 pub struct MessageBuilder<'a> {
     api_key: &'a str,
     collapse_key: Option<&'a str>,
+    condition: Option<&'a str>,
     content_available: Option<bool>,
     data: Option<Value>,
     delay_while_idle: Option<bool>,
@@ -102,6 +109,7 @@ impl<'a> MessageBuilder<'a> {
             api_key,
             to: Some(to),
             registration_ids: None,
+            condition: None,
             collapse_key: None,
             priority: None,
             content_available: None,
@@ -125,6 +133,27 @@ impl<'a> MessageBuilder<'a> {
             api_key,
             to: None,
             registration_ids: Some(converted),
+            condition: None,
+            collapse_key: None,
+            priority: None,
+            content_available: None,
+            delay_while_idle: None,
+            time_to_live: None,
+            restricted_package_name: None,
+            dry_run: None,
+            data: None,
+            notification: None,
+        }
+    }
+
+    /// Get a new instance of Message sent to all devices matching a topic
+    /// condition, e.g. `'dogs' in topics || 'cats' in topics`.
+    pub fn new_condition(api_key: &'a str, condition: &'a str) -> Self {
+        MessageBuilder {
+            api_key,
+            to: None,
+            registration_ids: None,
+            condition: Some(condition),
             collapse_key: None,
             priority: None,
             content_available: None,
@@ -246,6 +275,7 @@ impl<'a> MessageBuilder<'a> {
             body: MessageBody {
                 to: self.to,
                 registration_ids: self.registration_ids,
+                condition: self.condition,
                 collapse_key: self.collapse_key,
                 priority: self.priority,
                 content_available: self.content_available,
@@ -1,5 +1,5 @@
 use crate::notification::NotificationBuilder;
-use crate::{MessageBuilder, Priority};
+use crate::{ApnsLint, BackgroundMessageBuilder, MessageBuilder, Priority};
 use serde::Serialize;
 use serde_json::json;
 use std::borrow::Cow;
@@ -100,6 +100,14 @@ fn should_set_registration_ids() {
     assert_eq!(msg.body.registration_ids, Some(vec![Cow::from("id1")]));
 }
 
+#[test]
+fn should_send_to_a_condition() {
+    let msg = MessageBuilder::new_condition("api_key", "'dogs' in topics").finalize();
+    let payload = serde_json::to_value(&msg.body).unwrap();
+
+    assert_eq!(json!({ "condition": "'dogs' in topics" }), payload);
+}
+
 #[test]
 fn should_set_collapse_key() {
     let msg = MessageBuilder::new("api_key", "token").finalize();
@@ -203,5 +211,59 @@ fn should_set_notifications() {
     builder.notification(nm);
     let msg = builder.finalize();
 
-    assert!(msg.body.notification != None);
+    assert!(msg.body.notification.is_some());
+}
+
+#[test]
+fn should_force_background_push_settings() {
+    let mut builder = BackgroundMessageBuilder::new("api_key", "token");
+    builder.collapse_key("sync").time_to_live(60);
+    let msg = builder.finalize();
+
+    let payload = serde_json::to_string(&msg.body).unwrap();
+
+    let expected_payload = json!({
+        "collapse_key": "sync",
+        "content_available": true,
+        "priority": "normal",
+        "time_to_live": 60,
+        "to": "token"
+    })
+    .to_string();
+
+    assert_eq!(expected_payload, payload);
+    assert!(msg.apns_lints().is_empty());
+}
+
+#[test]
+fn should_not_lint_foreground_messages() {
+    let mut nm = NotificationBuilder::new();
+    nm.title("title").sound("pling").badge("1");
+
+    let mut builder = MessageBuilder::new("api_key", "token");
+    builder.priority(Priority::High).notification(nm.finalize());
+
+    assert!(builder.finalize().apns_lints().is_empty());
+}
+
+#[test]
+fn should_lint_misconfigured_background_messages() {
+    let mut nm = NotificationBuilder::new();
+    nm.body("body").sound("pling").badge("1");
+
+    let mut builder = MessageBuilder::new("api_key", "token");
+    builder
+        .content_available(true)
+        .priority(Priority::High)
+        .notification(nm.finalize());
+
+    assert_eq!(
+        vec![
+            ApnsLint::HighPriority,
+            ApnsLint::Alert,
+            ApnsLint::Sound,
+            ApnsLint::Badge
+        ],
+        builder.finalize().apns_lints()
+    );
 }
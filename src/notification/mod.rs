@@ -183,3 +183,20 @@ impl<'a> NotificationBuilder<'a> {
         }
     }
 }
+
+impl<'a> Notification<'a> {
+    /// `true` if the notification would be shown as a visible alert on iOS.
+    pub(crate) fn has_alert(&self) -> bool {
+        self.title.is_some() || self.body.is_some() || self.title_loc_key.is_some() || self.body_loc_key.is_some()
+    }
+
+    /// `true` if the notification plays a sound.
+    pub(crate) fn has_sound(&self) -> bool {
+        self.sound.is_some()
+    }
+
+    /// `true` if the notification sets the application badge.
+    pub(crate) fn has_badge(&self) -> bool {
+        self.badge.is_some()
+    }
+}
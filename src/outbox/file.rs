@@ -0,0 +1,179 @@
+use crate::outbox::{EntryState, OutboxEntry, OutboxMessage, OutboxStore};
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+/// An [OutboxStore](trait.OutboxStore.html) that appends every change of an
+/// entry as a JSON line to a file, and syncs the file before returning.
+///
+/// Opening the file replays it, so the latest state of every entry is known
+/// again after a restart. Finished entries stay in the file until it is
+/// [compacted](#method.compact).
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    file: File,
+    entries: BTreeMap<u64, OutboxEntry>,
+    next_id: u64,
+}
+
+impl FileStore {
+    /// Open the store at `path`, creating the file if it does not exist.
+    /// A truncated last line, as left by a crash during a write, is cut off
+    /// so that new entries start on a line of their own.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FileStore> {
+        let path = path.as_ref().to_path_buf();
+        let mut entries = BTreeMap::new();
+        let mut complete = 0;
+
+        if path.exists() {
+            let mut reader = BufReader::new(File::open(&path)?);
+            let mut line = Vec::new();
+
+            for number in 1.. {
+                line.clear();
+
+                let read = reader.read_until(b'\n', &mut line)?;
+
+                if read == 0 {
+                    break;
+                }
+
+                if line.last() != Some(&b'\n') {
+                    log::warn!("cutting off the truncated line {} of {}", number, path.display());
+                    break;
+                }
+
+                complete += read as u64;
+
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+
+                match serde_json::from_slice::<OutboxEntry>(&line) {
+                    Ok(entry) => {
+                        entries.insert(entry.id, entry);
+                    }
+                    Err(e) => log::warn!("ignoring line {} of {}: {}", number, path.display(), e),
+                }
+            }
+        }
+
+        let next_id = entries.keys().next_back().map(|id| id + 1).unwrap_or(1);
+        entries.retain(|_, entry| entry.state != EntryState::Done);
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        if file.metadata()?.len() > complete {
+            file.set_len(complete)?;
+            file.sync_data()?;
+        }
+
+        Ok(FileStore {
+            path,
+            state: Mutex::new(State { file, entries, next_id }),
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The entries that were dead-lettered.
+    pub fn dead_letters(&self) -> Vec<OutboxEntry> {
+        self.state()
+            .entries
+            .values()
+            .filter(|entry| matches!(entry.state, EntryState::Dead { .. }))
+            .cloned()
+            .collect()
+    }
+
+    /// Rewrite the file with only the latest state of the pending and
+    /// dead-lettered entries. The new file replaces the old one atomically.
+    pub fn compact(&self) -> io::Result<()> {
+        let mut state = self.state();
+        let compacted = self.path.with_extension("compact");
+
+        {
+            let mut file = File::create(&compacted)?;
+
+            for entry in state.entries.values() {
+                write_entry(&mut file, entry)?;
+            }
+
+            file.sync_all()?;
+        }
+
+        fs::rename(&compacted, &self.path)?;
+        state.file = OpenOptions::new().append(true).open(&self.path)?;
+
+        Ok(())
+    }
+}
+
+fn write_entry(file: &mut File, entry: &OutboxEntry) -> io::Result<()> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+
+    file.write_all(&line)
+}
+
+impl State {
+    fn append(&mut self, entry: &OutboxEntry) -> io::Result<()> {
+        write_entry(&mut self.file, entry)?;
+        self.file.sync_data()
+    }
+}
+
+impl OutboxStore for FileStore {
+    fn insert(&self, message: OutboxMessage, next_attempt: DateTime<Utc>) -> io::Result<OutboxEntry> {
+        let mut state = self.state();
+
+        let entry = OutboxEntry {
+            id: state.next_id,
+            message,
+            attempts: 0,
+            next_attempt,
+            state: EntryState::Pending,
+        };
+
+        state.append(&entry)?;
+        state.next_id += 1;
+        state.entries.insert(entry.id, entry.clone());
+
+        Ok(entry)
+    }
+
+    fn update(&self, entry: &OutboxEntry) -> io::Result<()> {
+        let mut state = self.state();
+
+        state.append(entry)?;
+
+        if entry.state == EntryState::Done {
+            state.entries.remove(&entry.id);
+        } else {
+            state.entries.insert(entry.id, entry.clone());
+        }
+
+        Ok(())
+    }
+
+    fn pending(&self) -> io::Result<Vec<OutboxEntry>> {
+        Ok(self
+            .state()
+            .entries
+            .values()
+            .filter(|entry| entry.state == EntryState::Pending)
+            .cloned()
+            .collect())
+    }
+}
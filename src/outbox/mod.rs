@@ -0,0 +1,387 @@
+//! A durable outbox that delivers messages at least once, even if the
+//! process restarts before FCM accepted them.
+//!
+//! Enable the `outbox` feature to use this module. Messages are persisted in
+//! an [OutboxStore](trait.OutboxStore.html) before they are sent, and an
+//! [Outbox](struct.Outbox.html) worker sends them through a
+//! [Client](../struct.Client.html), retrying with exponential backoff until
+//! FCM accepts them or they are dead-lettered. Entries that were pending when
+//! the process stopped are sent when the worker starts again.
+//!
+//! ```no_run
+//! use fcm::outbox::{FileStore, Outbox, OutboxMessage};
+//! use fcm::{Client, MessageBuilder};
+//! use std::sync::Arc;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> std::io::Result<()> {
+//! let store = FileStore::open("outbox.jsonl")?;
+//!
+//! let mut outbox = Outbox::new(Client::new(), store);
+//! outbox.api_key("<FCM API Key>");
+//! let outbox = Arc::new(outbox);
+//!
+//! tokio::spawn({
+//!     let outbox = outbox.clone();
+//!     async move { outbox.run().await }
+//! });
+//!
+//! let message = MessageBuilder::new("<FCM API Key>", "<registration id>").finalize();
+//! outbox.enqueue(OutboxMessage::legacy(&message)?)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The API key and v1 credentials are given to the worker, and never
+//! written to the store.
+
+use crate::v1::{self, Credentials};
+use crate::{Client, Endpoint, FcmError, FcmResponse, Message};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+mod file;
+pub use self::file::*;
+
+#[cfg(test)]
+mod tests;
+
+/// A message as it is persisted in the outbox.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "api", rename_all = "lowercase")]
+pub enum OutboxMessage {
+    /// The serialized body of a legacy [Message](../struct.Message.html).
+    Legacy { body: Value },
+
+    /// A v1 message.
+    V1 { message: Box<v1::Message> },
+}
+
+impl OutboxMessage {
+    /// Persist the body of a legacy message. The API key is not persisted,
+    /// the worker sends with its own, see [Outbox::api_key](struct.Outbox.html#method.api_key).
+    pub fn legacy(message: &Message<'_>) -> io::Result<OutboxMessage> {
+        Ok(OutboxMessage::Legacy {
+            body: serde_json::to_value(&message.body)?,
+        })
+    }
+
+    /// Persist a v1 message.
+    pub fn v1(message: v1::Message) -> OutboxMessage {
+        OutboxMessage::V1 {
+            message: Box::new(message),
+        }
+    }
+}
+
+/// Where an entry is in its delivery.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum EntryState {
+    /// The message still has to be sent.
+    Pending,
+
+    /// FCM accepted the message.
+    Done,
+
+    /// The message will not be sent, e.g. because FCM rejected it or it ran
+    /// out of attempts.
+    Dead { reason: String },
+}
+
+/// A persisted message with its delivery state.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OutboxEntry {
+    /// Identifies the entry within its store.
+    pub id: u64,
+
+    pub message: OutboxMessage,
+
+    /// How many times sending the message was attempted.
+    pub attempts: u32,
+
+    /// When the message is sent next, if it is pending.
+    pub next_attempt: DateTime<Utc>,
+
+    #[serde(flatten)]
+    pub state: EntryState,
+}
+
+/// Persists the entries of an [Outbox](struct.Outbox.html).
+///
+/// An implementation must have written an entry durably before returning
+/// from [insert](#tymethod.insert) or [update](#tymethod.update), as the
+/// outbox relies on them to survive a crash.
+pub trait OutboxStore: Send + Sync + 'static {
+    /// Persist a new pending entry for `message`, to be sent at
+    /// `next_attempt`, and assign its id.
+    fn insert(&self, message: OutboxMessage, next_attempt: DateTime<Utc>) -> io::Result<OutboxEntry>;
+
+    /// Persist the new state of an existing entry.
+    fn update(&self, entry: &OutboxEntry) -> io::Result<()>;
+
+    /// All pending entries, in the order they were inserted.
+    fn pending(&self) -> io::Result<Vec<OutboxEntry>>;
+}
+
+/// How an attempt to send an entry ended.
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Done,
+    Retry(Option<Duration>),
+    Dead(String),
+}
+
+/// Sends the entries of an [OutboxStore](trait.OutboxStore.html) through a
+/// [Client](../struct.Client.html).
+pub struct Outbox<S> {
+    client: Client,
+    store: S,
+    api_key: Option<String>,
+    credentials: Option<Credentials>,
+    max_attempts: u32,
+    base_delay: Duration,
+    max_delay: Duration,
+    idle_interval: Duration,
+    notify: Arc<Notify>,
+}
+
+impl<S: OutboxStore> Outbox<S> {
+    /// Get a new outbox that sends the entries of `store` through `client`.
+    /// Messages are attempted up to 10 times, waiting from one second up to
+    /// one hour between attempts.
+    pub fn new(client: Client, store: S) -> Outbox<S> {
+        Outbox {
+            client,
+            store,
+            api_key: None,
+            credentials: None,
+            max_attempts: 10,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60 * 60),
+            idle_interval: Duration::from_secs(60),
+            notify: Arc::new(Notify::new()),
+        }
+    }
+
+    /// The server key legacy messages are sent with.
+    pub fn api_key(&mut self, api_key: &str) -> &mut Self {
+        self.api_key = Some(api_key.to_string());
+        self
+    }
+
+    /// The credentials v1 messages are sent with.
+    pub fn credentials(&mut self, credentials: Credentials) -> &mut Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Dead-letter messages after this many failed attempts.
+    pub fn max_attempts(&mut self, max_attempts: u32) -> &mut Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Wait `base` after the first failed attempt, doubling the wait after
+    /// every further one up to `max`. A `Retry-After` from FCM is honored if
+    /// it is longer.
+    pub fn retry_delays(&mut self, base: Duration, max: Duration) -> &mut Self {
+        self.base_delay = base;
+        self.max_delay = max;
+        self
+    }
+
+    /// The store of the outbox.
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Persist `message` and wake up the worker to send it. Returns the id
+    /// of its entry.
+    pub fn enqueue(&self, message: OutboxMessage) -> io::Result<u64> {
+        let entry = self.store.insert(message, Utc::now())?;
+        self.notify.notify_one();
+
+        Ok(entry.id)
+    }
+
+    /// Send the pending entries that are due, and return how many were
+    /// attempted.
+    pub async fn run_once(&self) -> io::Result<usize> {
+        let now = Utc::now();
+        let due: Vec<OutboxEntry> = self
+            .store
+            .pending()?
+            .into_iter()
+            .filter(|entry| entry.next_attempt <= now)
+            .collect();
+
+        for entry in &due {
+            let attempt = self.attempt(entry.clone());
+
+            #[cfg(feature = "tracing")]
+            let attempt = tracing::Instrument::instrument(
+                attempt,
+                tracing::info_span!("fcm.outbox_attempt", entry_id = entry.id, attempt = entry.attempts + 1),
+            );
+
+            attempt.await?;
+        }
+
+        Ok(due.len())
+    }
+
+    /// Send entries as they become due, starting with those left pending by
+    /// a previous run. Only returns if the store fails.
+    pub async fn run(&self) -> io::Result<()> {
+        loop {
+            self.run_once().await?;
+
+            let now = Utc::now();
+            let wait = self
+                .store
+                .pending()?
+                .iter()
+                .map(|entry| (entry.next_attempt - now).to_std().unwrap_or_default())
+                .min()
+                .unwrap_or(self.idle_interval)
+                .min(self.idle_interval);
+
+            if wait > Duration::ZERO {
+                let _ = tokio::time::timeout(wait, self.notify.notified()).await;
+            }
+        }
+    }
+
+    async fn attempt(&self, mut entry: OutboxEntry) -> io::Result<()> {
+        entry.attempts += 1;
+
+        if let Some(observer) = self.client.observer().filter(|_| entry.attempts > 1) {
+            let endpoint = match entry.message {
+                OutboxMessage::Legacy { .. } => Endpoint::LegacySend,
+                OutboxMessage::V1 { .. } => Endpoint::V1Send,
+            };
+            observer.on_retry(endpoint, entry.attempts - 1);
+        }
+
+        let outcome = match entry.message {
+            OutboxMessage::Legacy { ref mut body } => match self.api_key {
+                Some(ref api_key) => match serde_json::to_vec(body) {
+                    Ok(payload) => legacy_outcome(body, self.client.send_payload(api_key, payload).await),
+                    Err(e) => Outcome::Dead(format!("the message could not be serialized: {}", e)),
+                },
+                None => Outcome::Dead("no API key configured for legacy messages".to_string()),
+            },
+            OutboxMessage::V1 { ref message } => match self.credentials {
+                Some(ref credentials) => match self.client.send_v1(credentials, message).await {
+                    Ok(_) => Outcome::Done,
+                    Err(e) => error_outcome(&e),
+                },
+                None => Outcome::Dead("no credentials configured for v1 messages".to_string()),
+            },
+        };
+
+        match outcome {
+            Outcome::Done => entry.state = EntryState::Done,
+            Outcome::Dead(reason) => entry.state = EntryState::Dead { reason },
+            Outcome::Retry(_) if entry.attempts >= self.max_attempts => {
+                entry.state = EntryState::Dead {
+                    reason: format!("gave up after {} attempts", entry.attempts),
+                };
+            }
+            Outcome::Retry(retry_after) => {
+                let delay = self.backoff(entry.attempts).max(retry_after.unwrap_or_default());
+
+                #[cfg(feature = "tracing")]
+                tracing::warn!(
+                    retry_after = retry_after.map(|retry_after| retry_after.as_secs_f64()),
+                    delay = delay.as_secs_f64(),
+                    "sending the outbox entry failed, retrying later"
+                );
+
+                let next_attempt = chrono::Duration::from_std(delay)
+                    .ok()
+                    .and_then(|delay| Utc::now().checked_add_signed(delay));
+
+                match next_attempt {
+                    Some(next_attempt) => entry.next_attempt = next_attempt,
+                    None => {
+                        entry.state = EntryState::Dead {
+                            reason: format!("the retry delay of {:?} is too long", delay),
+                        }
+                    }
+                }
+            }
+        }
+
+        if let EntryState::Dead { ref reason } = entry.state {
+            log::error!("dead-lettering outbox entry {}: {}", entry.id, reason);
+        }
+
+        self.store.update(&entry)
+    }
+
+    /// The wait after `attempts` failed attempts.
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+fn error_outcome(error: &FcmError) -> Outcome {
+    if error.is_retryable() {
+        Outcome::Retry(
+            error
+                .retry_after()
+                .map(|retry_after| retry_after.wait_duration(Utc::now())),
+        )
+    } else {
+        Outcome::Dead(error.to_string())
+    }
+}
+
+/// Classify a legacy response. When a multicast message fails for some
+/// tokens with retryable errors, `body` is narrowed to those tokens, so the
+/// others do not get it twice.
+fn legacy_outcome(body: &mut Value, result: Result<FcmResponse, FcmError>) -> Outcome {
+    let response = match result {
+        Ok(response) => response,
+        Err(e) => return error_outcome(&e),
+    };
+
+    if let Some(error) = response.error {
+        return if error.is_retryable() {
+            Outcome::Retry(None)
+        } else {
+            Outcome::Dead(error.to_string())
+        };
+    }
+
+    let results = response.results.unwrap_or_default();
+
+    let retry: Vec<usize> = results
+        .iter()
+        .enumerate()
+        .filter(|(_, result)| result.error.as_ref().map(|error| error.is_retryable()).unwrap_or(false))
+        .map(|(i, _)| i)
+        .collect();
+
+    if retry.is_empty() {
+        return match results.first().and_then(|result| result.error.as_ref()) {
+            Some(error) if results.len() == 1 => Outcome::Dead(error.to_string()),
+            _ => Outcome::Done,
+        };
+    }
+
+    if let Some(Value::Array(ids)) = body.get_mut("registration_ids") {
+        *ids = retry.iter().filter_map(|&i| ids.get(i).cloned()).collect();
+    }
+
+    Outcome::Retry(None)
+}
@@ -0,0 +1,286 @@
+use crate::client::transport::scripted::ScriptedTransport;
+use crate::outbox::{EntryState, FileStore, Outbox, OutboxMessage, OutboxStore};
+use crate::{Client, ClientBuilder, Endpoint, MessageBuilder, SendObserver};
+use chrono::Utc;
+use http::StatusCode;
+use serde_json::json;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+fn store_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("fcm-outbox-{}-{}.jsonl", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn legacy(to: &str) -> OutboxMessage {
+    OutboxMessage::legacy(&MessageBuilder::new("api_key", to).finalize()).unwrap()
+}
+
+fn outbox(transport: &ScriptedTransport, store: FileStore) -> Outbox<FileStore> {
+    let mut outbox = Outbox::new(Client::with_transport(transport.clone()), store);
+    outbox.api_key("api_key").retry_delays(Duration::ZERO, Duration::ZERO);
+    outbox
+}
+
+#[test]
+fn should_replay_the_file_on_open() {
+    let path = store_path("replay");
+
+    {
+        let store = FileStore::open(&path).unwrap();
+        let first = store.insert(legacy("a"), Utc::now()).unwrap();
+        let mut second = store.insert(legacy("b"), Utc::now()).unwrap();
+
+        second.state = EntryState::Done;
+        store.update(&second).unwrap();
+        assert_eq!(vec![first], store.pending().unwrap());
+    }
+
+    // A crash in the middle of a write leaves a truncated line.
+    let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(b"{\"id\": 3, \"mess").unwrap();
+
+    let store = FileStore::open(&path).unwrap();
+    let pending = store.pending().unwrap();
+
+    assert_eq!(1, pending.len());
+    assert_eq!(1, pending[0].id);
+    assert_eq!(legacy("a"), pending[0].message);
+    assert_eq!(3, store.insert(legacy("c"), Utc::now()).unwrap().id);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn should_keep_entries_written_after_a_torn_write() {
+    let path = store_path("torn");
+
+    FileStore::open(&path).unwrap().insert(legacy("a"), Utc::now()).unwrap();
+
+    let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(b"{\"id\": 2, \"mess").unwrap();
+
+    FileStore::open(&path).unwrap().insert(legacy("b"), Utc::now()).unwrap();
+
+    let store = FileStore::open(&path).unwrap();
+    let pending = store.pending().unwrap();
+
+    assert_eq!(2, pending.len());
+    assert_eq!(legacy("b"), pending[1].message);
+    assert_eq!(3, store.insert(legacy("c"), Utc::now()).unwrap().id);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn should_send_pending_entries_and_mark_them_done() {
+    let path = store_path("done");
+    let transport = ScriptedTransport::default();
+    let outbox = outbox(&transport, FileStore::open(&path).unwrap());
+
+    outbox.enqueue(legacy("a")).unwrap();
+
+    assert_eq!(1, outbox.run_once().await.unwrap());
+    assert!(outbox.store().pending().unwrap().is_empty());
+    assert_eq!(json!({"to": "a"}), transport.bodies()[0]);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn should_resume_pending_entries_after_a_restart() {
+    let path = store_path("resume");
+
+    {
+        let transport = ScriptedTransport::default();
+        transport.reply(StatusCode::SERVICE_UNAVAILABLE, json!({}));
+
+        let outbox = outbox(&transport, FileStore::open(&path).unwrap());
+        outbox.enqueue(legacy("a")).unwrap();
+        outbox.enqueue(legacy("b")).unwrap();
+    }
+
+    let transport = ScriptedTransport::default();
+    let outbox = outbox(&transport, FileStore::open(&path).unwrap());
+
+    assert_eq!(2, outbox.run_once().await.unwrap());
+    assert_eq!(vec![json!({"to": "a"}), json!({"to": "b"})], transport.bodies());
+    assert!(outbox.store().pending().unwrap().is_empty());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn should_retry_and_then_dead_letter() {
+    let path = store_path("retry");
+    let transport = ScriptedTransport::default();
+    transport
+        .reply(StatusCode::SERVICE_UNAVAILABLE, json!({}))
+        .reply(StatusCode::OK, json!({"results": [{"error": "Unavailable"}]}))
+        .reply(StatusCode::SERVICE_UNAVAILABLE, json!({}));
+
+    let mut outbox = outbox(&transport, FileStore::open(&path).unwrap());
+    outbox.max_attempts(3);
+
+    outbox.enqueue(legacy("a")).unwrap();
+    outbox.run_once().await.unwrap();
+    outbox.run_once().await.unwrap();
+
+    let pending = outbox.store().pending().unwrap();
+    assert_eq!(2, pending[0].attempts);
+
+    outbox.run_once().await.unwrap();
+
+    assert!(outbox.store().pending().unwrap().is_empty());
+    let dead = outbox.store().dead_letters();
+    assert_eq!(3, dead[0].attempts);
+    assert_eq!(
+        EntryState::Dead {
+            reason: "gave up after 3 attempts".to_string()
+        },
+        dead[0].state
+    );
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// Records the attempt numbers of the observed retries.
+#[derive(Clone, Default)]
+struct RetryObserver {
+    retries: Arc<Mutex<Vec<u32>>>,
+}
+
+impl SendObserver for RetryObserver {
+    fn on_retry(&self, endpoint: Endpoint, attempt: u32) {
+        assert_eq!(Endpoint::LegacySend, endpoint);
+        self.retries.lock().unwrap().push(attempt);
+    }
+}
+
+#[tokio::test]
+async fn should_report_retries_to_the_observer() {
+    let path = store_path("observer");
+    let transport = ScriptedTransport::default();
+    transport
+        .reply(StatusCode::SERVICE_UNAVAILABLE, json!({}))
+        .reply(StatusCode::SERVICE_UNAVAILABLE, json!({}));
+
+    let observer = RetryObserver::default();
+    let mut builder = ClientBuilder::new();
+    builder.transport(transport.clone()).observer(observer.clone());
+
+    let mut outbox = Outbox::new(builder.finalize().unwrap(), FileStore::open(&path).unwrap());
+    outbox.api_key("api_key").retry_delays(Duration::ZERO, Duration::ZERO);
+
+    outbox.enqueue(legacy("a")).unwrap();
+    for _ in 0..3 {
+        outbox.run_once().await.unwrap();
+    }
+
+    assert_eq!(vec![1, 2], *observer.retries.lock().unwrap());
+    assert_eq!(3, transport.requests());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn should_wait_before_retrying() {
+    let path = store_path("backoff");
+    let transport = ScriptedTransport::default();
+    transport.reply(StatusCode::SERVICE_UNAVAILABLE, json!({}));
+
+    let mut outbox = outbox(&transport, FileStore::open(&path).unwrap());
+    outbox.retry_delays(Duration::from_secs(60), Duration::from_secs(600));
+
+    outbox.enqueue(legacy("a")).unwrap();
+    outbox.run_once().await.unwrap();
+
+    assert_eq!(0, outbox.run_once().await.unwrap());
+    assert!(outbox.store().pending().unwrap()[0].next_attempt > Utc::now() + chrono::Duration::seconds(50));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn should_dead_letter_entries_whose_retry_is_out_of_range() {
+    let path = store_path("out-of-range");
+    let transport = ScriptedTransport::default();
+    transport.reply(StatusCode::SERVICE_UNAVAILABLE, json!({}));
+
+    let mut outbox = outbox(&transport, FileStore::open(&path).unwrap());
+    outbox.retry_delays(Duration::MAX, Duration::MAX);
+
+    outbox.enqueue(legacy("a")).unwrap();
+    outbox.run_once().await.unwrap();
+
+    assert!(outbox.store().pending().unwrap().is_empty());
+    assert!(matches!(
+        outbox.store().dead_letters()[0].state,
+        EntryState::Dead { ref reason } if reason.starts_with("the retry delay")
+    ));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn should_dead_letter_rejected_messages() {
+    let path = store_path("rejected");
+    let transport = ScriptedTransport::default();
+    transport.reply(StatusCode::OK, json!({"results": [{"error": "NotRegistered"}]}));
+
+    let outbox = outbox(&transport, FileStore::open(&path).unwrap());
+    outbox.enqueue(legacy("a")).unwrap();
+    outbox
+        .enqueue(OutboxMessage::v1(
+            crate::v1::MessageBuilder::new(crate::v1::Target::Token("b".to_string())).finalize(),
+        ))
+        .unwrap();
+    outbox.run_once().await.unwrap();
+
+    let dead = outbox.store().dead_letters();
+    assert_eq!(
+        EntryState::Dead {
+            reason: "NotRegistered".to_string()
+        },
+        dead[0].state
+    );
+    assert_eq!(
+        EntryState::Dead {
+            reason: "no credentials configured for v1 messages".to_string()
+        },
+        dead[1].state
+    );
+
+    // Dead letters survive compaction, finished entries do not.
+    outbox.store().compact().unwrap();
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(2, contents.lines().count());
+    assert_eq!(2, FileStore::open(&path).unwrap().dead_letters().len());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn should_only_retry_the_failed_tokens_of_a_multicast_message() {
+    let path = store_path("multicast");
+    let transport = ScriptedTransport::default();
+    transport.reply(
+        StatusCode::OK,
+        json!({"results": [{"message_id": "1"}, {"error": "Unavailable"}, {"error": "NotRegistered"}]}),
+    );
+
+    let outbox = outbox(&transport, FileStore::open(&path).unwrap());
+    let message = MessageBuilder::new_multi("api_key", &["a", "b", "c"]).finalize();
+    outbox.enqueue(OutboxMessage::legacy(&message).unwrap()).unwrap();
+
+    outbox.run_once().await.unwrap();
+    outbox.run_once().await.unwrap();
+
+    assert_eq!(json!({"registration_ids": ["b"]}), transport.bodies()[1]);
+    assert!(outbox.store().pending().unwrap().is_empty());
+
+    std::fs::remove_file(&path).unwrap();
+}
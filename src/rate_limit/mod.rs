@@ -0,0 +1,556 @@
+//! Client-side rate limiting per registration token and per topic.
+//!
+//! FCM limits how many messages a single device or topic may receive, and
+//! only tells the sender with a `DeviceMessageRateExceeded` or
+//! `TopicsMessageRateExceeded` error once the limit was hit. A
+//! [RateLimiter](struct.RateLimiter.html) keeps a token bucket per target to
+//! stay below those limits, and a
+//! [RateLimitedTransport](struct.RateLimitedTransport.html) applies it to all
+//! messages a [Client](../struct.Client.html) sends:
+//!
+//! ```rust
+//! # use fcm::transport::{BoxFuture, HttpRequest, HttpResponse, Transport};
+//! # struct MyTransport;
+//! # impl Transport for MyTransport {
+//! #     fn execute(&self, _: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, fcm::FcmError>> { unimplemented!() }
+//! # }
+//! # let transport = MyTransport;
+//! use fcm::rate_limit::{Mode, RateLimit, RateLimitedTransport, RateLimiter};
+//! use fcm::ClientBuilder;
+//! use std::time::Duration;
+//!
+//! let mut limiter = RateLimiter::new();
+//! limiter.per_token(RateLimit::per_minute(60));
+//! limiter.per_topic(RateLimit::new(1, Duration::from_secs(10)));
+//! limiter.mode(Mode::Delay { max_wait: Duration::from_secs(5) });
+//!
+//! let mut builder = ClientBuilder::new();
+//! builder.transport(RateLimitedTransport::new(transport, limiter));
+//! let client = builder.finalize().unwrap();
+//! ```
+//!
+//! When FCM still reports one of the rate errors, the limit of that target
+//! is tightened for a while, see [RateLimiter::tighten](struct.RateLimiter.html#method.tighten).
+//!
+//! The project-wide quota of the v1 API is tracked separately by a
+//! [QuotaTracker](struct.QuotaTracker.html), see
+//! [ClientBuilder::project_quota](../struct.ClientBuilder.html#method.project_quota).
+
+use crate::client::multipart::{batch_request_bodies, batch_response};
+use crate::client::transport::{BoxFuture, HttpRequest, HttpResponse, Transport};
+use crate::v1::{ErrorCode, Status};
+use crate::{ErrorReason, FcmError, FcmResponse, RetryAfter};
+use http::StatusCode;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+mod quota;
+pub use self::quota::*;
+
+#[cfg(test)]
+mod tests;
+
+/// How many times the refill interval of a key is doubled at most.
+const MAX_TIGHTENING: u32 = 6;
+
+/// Idle buckets are dropped once the limiter tracks more keys than this.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// A token bucket: up to `burst` messages at once, then one message every
+/// `refill`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// How many messages can be sent at once.
+    pub burst: u32,
+
+    /// How long it takes to earn one more message.
+    pub refill: Duration,
+}
+
+impl RateLimit {
+    /// Allow `burst` messages at once, then one more every `refill`. A burst
+    /// of zero is treated as one.
+    pub fn new(burst: u32, refill: Duration) -> RateLimit {
+        RateLimit {
+            burst: burst.max(1),
+            refill,
+        }
+    }
+
+    /// Allow `count` messages per minute, all of which can be sent at once.
+    pub fn per_minute(count: u32) -> RateLimit {
+        let count = count.max(1);
+
+        RateLimit::new(count, Duration::from_secs(60) / count)
+    }
+}
+
+/// What to do with a message that would exceed the limit of its target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Fail with `FcmError::RateLimited` right away.
+    Reject,
+
+    /// Wait until the limit allows sending the message, but fail with
+    /// `FcmError::RateLimited` if that takes longer than `max_wait`.
+    Delay { max_wait: Duration },
+}
+
+/// The target a limit applies to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LimitKey {
+    /// A registration token.
+    Token(String),
+
+    /// A topic, without the `/topics/` prefix.
+    Topic(String),
+}
+
+/// How many characters of a registration token are shown by `Display`.
+const SHOWN_TOKEN_CHARS: usize = 8;
+
+/// Registration tokens identify a device, so only their first characters
+/// are shown, e.g. in the warnings logged when a limit is tightened.
+impl fmt::Display for LimitKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitKey::Token(token) if token.chars().count() > SHOWN_TOKEN_CHARS => {
+                let shown: String = token.chars().take(SHOWN_TOKEN_CHARS).collect();
+                write!(f, "token {}…", shown)
+            }
+            LimitKey::Token(token) => write!(f, "token {}", token),
+            LimitKey::Topic(topic) => write!(f, "/topics/{}", topic),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    tightening: u32,
+    tightened_at: Option<Instant>,
+}
+
+impl Bucket {
+    fn full(limit: &RateLimit, now: Instant) -> Bucket {
+        Bucket {
+            tokens: f64::from(limit.burst),
+            updated: now,
+            tightening: 0,
+            tightened_at: None,
+        }
+    }
+
+    fn refill_interval(&self, limit: &RateLimit) -> Duration {
+        limit.refill * (1 << self.tightening)
+    }
+
+    fn refill(&mut self, limit: &RateLimit, cooldown: Duration, now: Instant) {
+        if let Some(tightened_at) = self.tightened_at {
+            if now.saturating_duration_since(tightened_at) >= cooldown {
+                self.tightening = 0;
+                self.tightened_at = None;
+            }
+        }
+
+        let interval = self.refill_interval(limit);
+        let elapsed = now.saturating_duration_since(self.updated);
+
+        self.tokens = if interval.is_zero() {
+            f64::from(limit.burst)
+        } else {
+            (self.tokens + elapsed.as_secs_f64() / interval.as_secs_f64()).min(f64::from(limit.burst))
+        };
+        self.updated = now;
+    }
+
+    /// How long until one message can be sent.
+    fn wait(&self, limit: &RateLimit) -> Duration {
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else {
+            self.refill_interval(limit).mul_f64(1.0 - self.tokens)
+        }
+    }
+
+    fn is_idle(&self, limit: &RateLimit) -> bool {
+        self.tightened_at.is_none() && self.tokens >= f64::from(limit.burst)
+    }
+}
+
+/// Token buckets per registration token and per topic.
+///
+/// Without limits configured, every message is allowed. The limiter can be
+/// shared between clients through an `Arc`.
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_token: Option<RateLimit>,
+    per_topic: Option<RateLimit>,
+    mode: Mode,
+    cooldown: Duration,
+    buckets: Mutex<HashMap<LimitKey, Bucket>>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        RateLimiter {
+            per_token: None,
+            per_topic: None,
+            mode: Mode::Reject,
+            cooldown: Duration::from_secs(60),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl RateLimiter {
+    /// Get a new limiter without limits that rejects messages exceeding
+    /// them.
+    pub fn new() -> RateLimiter {
+        Self::default()
+    }
+
+    /// Limit the messages sent to each registration token.
+    pub fn per_token(&mut self, limit: RateLimit) -> &mut Self {
+        self.per_token = Some(limit);
+        self
+    }
+
+    /// Limit the messages sent to each topic.
+    pub fn per_topic(&mut self, limit: RateLimit) -> &mut Self {
+        self.per_topic = Some(limit);
+        self
+    }
+
+    /// Whether messages exceeding a limit are rejected, the default, or
+    /// delayed.
+    pub fn mode(&mut self, mode: Mode) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    /// How long a tightened limit stays tightened after the last rate error
+    /// FCM reported for the key. Defaults to one minute.
+    pub fn cooldown(&mut self, cooldown: Duration) -> &mut Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    fn limit(&self, key: &LimitKey) -> Option<&RateLimit> {
+        match key {
+            LimitKey::Token(_) => self.per_token.as_ref(),
+            LimitKey::Topic(_) => self.per_topic.as_ref(),
+        }
+    }
+
+    /// Take one message from the budget of every key, or none at all. Returns
+    /// how long to wait if any of the keys is out of budget.
+    pub fn try_acquire(&self, keys: &[LimitKey]) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if buckets.len() > PRUNE_THRESHOLD {
+            buckets.retain(|key, bucket| {
+                self.limit(key)
+                    .map(|limit| {
+                        bucket.refill(limit, self.cooldown, now);
+                        !bucket.is_idle(limit)
+                    })
+                    .unwrap_or(false)
+            });
+        }
+
+        let mut wait = Duration::ZERO;
+
+        for key in keys {
+            if let Some(limit) = self.limit(key) {
+                let bucket = buckets.entry(key.clone()).or_insert_with(|| Bucket::full(limit, now));
+
+                bucket.refill(limit, self.cooldown, now);
+                wait = wait.max(bucket.wait(limit));
+            }
+        }
+
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        for key in keys {
+            if let Some(bucket) = buckets.get_mut(key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Take one message from the budget of every key, waiting or failing
+    /// with `FcmError::RateLimited` as configured with [mode](#method.mode).
+    pub async fn acquire(&self, keys: &[LimitKey]) -> Result<(), FcmError> {
+        let deadline = match self.mode {
+            Mode::Reject => None,
+            Mode::Delay { max_wait } => Some(Instant::now() + max_wait),
+        };
+
+        loop {
+            let wait = match self.try_acquire(keys) {
+                Ok(()) => return Ok(()),
+                Err(wait) => wait,
+            };
+
+            match deadline {
+                Some(deadline) if Instant::now() + wait <= deadline => tokio::time::sleep(wait).await,
+                _ => return Err(rate_limited(wait)),
+            }
+        }
+    }
+
+    /// Tighten the limit of `key` after FCM reported it exceeded its rate:
+    /// the budget is emptied and the refill interval doubled, up to 64 times
+    /// the configured one. The limit is restored once no rate error was
+    /// reported for the [cooldown](#method.cooldown).
+    pub fn tighten(&self, key: &LimitKey) {
+        let limit = match self.limit(key) {
+            Some(limit) => limit,
+            None => return,
+        };
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let bucket = buckets.entry(key.clone()).or_insert_with(|| Bucket::full(limit, now));
+
+        bucket.refill(limit, self.cooldown, now);
+
+        if bucket.tightened_at.is_some() {
+            bucket.tightening = (bucket.tightening + 1).min(MAX_TIGHTENING);
+        } else {
+            bucket.tightening = 1;
+        }
+
+        bucket.tokens = 0.0;
+        bucket.tightened_at = Some(now);
+
+        log::warn!(
+            "FCM rate limit exceeded for {}, refilling every {:?}",
+            key,
+            bucket.refill_interval(limit)
+        );
+    }
+
+    /// The interval currently used to refill the budget of `key`, `None` if
+    /// it is not limited.
+    pub fn refill_interval(&self, key: &LimitKey) -> Option<Duration> {
+        let limit = self.limit(key)?;
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        match buckets.get_mut(key) {
+            Some(bucket) => {
+                bucket.refill(limit, self.cooldown, Instant::now());
+                Some(bucket.refill_interval(limit))
+            }
+            None => Some(limit.refill),
+        }
+    }
+}
+
+fn rate_limited(wait: Duration) -> FcmError {
+    let wait = chrono::Duration::from_std(wait).unwrap_or(chrono::Duration::MAX);
+
+    FcmError::RateLimited(RetryAfter::Delay(wait))
+}
+
+/// A [Transport](../transport/trait.Transport.html) that applies a
+/// [RateLimiter](struct.RateLimiter.html) to the legacy and v1 send
+/// requests, and tightens the limits of targets FCM reports as exceeding
+/// their rate. Other requests pass through unchanged.
+pub struct RateLimitedTransport<T> {
+    inner: T,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<T: Transport> RateLimitedTransport<T> {
+    /// Limit the messages sent through `inner`.
+    pub fn new(inner: T, limiter: RateLimiter) -> RateLimitedTransport<T> {
+        Self::shared(inner, Arc::new(limiter))
+    }
+
+    /// Limit the messages sent through `inner` with a limiter that is shared,
+    /// e.g. with other transports.
+    pub fn shared(inner: T, limiter: Arc<RateLimiter>) -> RateLimitedTransport<T> {
+        RateLimitedTransport { inner, limiter }
+    }
+
+    /// The limiter of the transport.
+    pub fn limiter(&self) -> &Arc<RateLimiter> {
+        &self.limiter
+    }
+}
+
+impl<T: Transport> Transport for RateLimitedTransport<T> {
+    fn execute(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, FcmError>> {
+        Box::pin(async move {
+            let api = SendApi::of(&request);
+            let targets = api.map(|api| api.targets(&request)).unwrap_or_default();
+            let keys: Vec<LimitKey> = targets.iter().flatten().cloned().collect();
+
+            if keys.is_empty() {
+                return self.inner.execute(request).await;
+            }
+
+            self.limiter.acquire(&keys).await?;
+
+            let response = self.inner.execute(request).await?;
+
+            if let Some(api) = api {
+                for key in api.exceeded(&targets, &response) {
+                    self.limiter.tighten(key);
+                }
+            }
+
+            Ok(response)
+        })
+    }
+}
+
+/// The API of a send request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SendApi {
+    Legacy,
+    V1,
+    Batch,
+}
+
+impl SendApi {
+    fn of(request: &HttpRequest) -> Option<SendApi> {
+        let path = request.url.split('?').next().unwrap_or_default();
+
+        if path.ends_with("/fcm/send") {
+            Some(SendApi::Legacy)
+        } else if path.ends_with("/messages:send") {
+            Some(SendApi::V1)
+        } else if path.ends_with("/batch") {
+            Some(SendApi::Batch)
+        } else {
+            None
+        }
+    }
+
+    /// The limited target of each message of a request, in the order of the
+    /// results in the response. Conditions are not limited.
+    fn targets(self, request: &HttpRequest) -> Vec<Option<LimitKey>> {
+        match self {
+            SendApi::Legacy => match serde_json::from_slice::<Value>(&request.body) {
+                Ok(body) => legacy_targets(&body),
+                Err(_) => Vec::new(),
+            },
+            SendApi::V1 => vec![v1_target(&request.body)],
+            SendApi::Batch => batch_request_bodies(&request.headers, &request.body)
+                .unwrap_or_default()
+                .iter()
+                .map(|body| v1_target(body))
+                .collect(),
+        }
+    }
+
+    /// The targets FCM reported as exceeding their rate.
+    fn exceeded<'a>(self, targets: &'a [Option<LimitKey>], response: &HttpResponse) -> Vec<&'a LimitKey> {
+        let flagged: Vec<bool> = match self {
+            SendApi::Legacy if response.status == StatusCode::OK => {
+                let response: FcmResponse = match serde_json::from_slice(&response.body) {
+                    Ok(response) => response,
+                    Err(_) => return Vec::new(),
+                };
+
+                if response.error.as_ref().map(is_rate_error).unwrap_or(false) {
+                    vec![true; targets.len()]
+                } else {
+                    response
+                        .results
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|result| result.error.as_ref().map(is_rate_error).unwrap_or(false))
+                        .collect()
+                }
+            }
+            SendApi::V1 | SendApi::Batch if response.status == StatusCode::TOO_MANY_REQUESTS => {
+                match Status::from_response_body(&response.body) {
+                    Ok(ref status) if is_quota_exceeded(status) => vec![true; targets.len()],
+                    _ => Vec::new(),
+                }
+            }
+            SendApi::Batch if response.status == StatusCode::OK => {
+                match batch_response(response.status, &response.headers, &response.body, targets.len()) {
+                    Ok(results) => results
+                        .iter()
+                        .map(|result| match result {
+                            Err(FcmError::V1(status)) => is_quota_exceeded(status),
+                            _ => false,
+                        })
+                        .collect(),
+                    Err(_) => Vec::new(),
+                }
+            }
+            _ => Vec::new(),
+        };
+
+        targets
+            .iter()
+            .zip(flagged)
+            .filter(|(_, flagged)| *flagged)
+            .filter_map(|(target, _)| target.as_ref())
+            .collect()
+    }
+}
+
+fn legacy_targets(body: &Value) -> Vec<Option<LimitKey>> {
+    if let Some(Value::String(to)) = body.get("to") {
+        return vec![Some(limit_key(to))];
+    }
+
+    body.get("registration_ids")
+        .and_then(Value::as_array)
+        .map(|ids| {
+            ids.iter()
+                .filter_map(Value::as_str)
+                .map(|token| Some(LimitKey::Token(token.to_string())))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The limited target of the body of a `messages:send` request.
+fn v1_target(body: &[u8]) -> Option<LimitKey> {
+    let body: Value = serde_json::from_slice(body).ok()?;
+    let message = &body["message"];
+
+    if let Some(token) = message["token"].as_str() {
+        Some(LimitKey::Token(token.to_string()))
+    } else {
+        message["topic"]
+            .as_str()
+            .map(|topic| LimitKey::Topic(topic.trim_start_matches("/topics/").to_string()))
+    }
+}
+
+fn is_quota_exceeded(status: &Status) -> bool {
+    status.error_code() == Some(ErrorCode::QuotaExceeded)
+}
+
+fn is_rate_error(reason: &ErrorReason) -> bool {
+    matches!(
+        reason,
+        ErrorReason::DeviceMessageRateExceeded | ErrorReason::TopicsMessageRateExceeded
+    )
+}
+
+fn limit_key(to: &str) -> LimitKey {
+    match to.strip_prefix("/topics/") {
+        Some(topic) => LimitKey::Topic(topic.to_string()),
+        None => LimitKey::Token(to.to_string()),
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How many slots the sliding window of a project is split into.
+const SLOTS: u32 = 60;
+
+/// How long sends pause after a 429 response without a `RetryInfo` delay.
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// A ceiling on the v1 messages sent for a project within a sliding window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProjectQuota {
+    /// How many messages can be sent within the window.
+    pub limit: u32,
+
+    /// The length of the window.
+    pub window: Duration,
+}
+
+impl ProjectQuota {
+    /// The default FCM quota of 600,000 messages per minute.
+    pub const FCM_DEFAULT: ProjectQuota = ProjectQuota {
+        limit: 600_000,
+        window: Duration::from_secs(60),
+    };
+
+    /// Allow `limit` messages within any `window`. A limit of zero is
+    /// treated as one.
+    pub fn new(limit: u32, window: Duration) -> ProjectQuota {
+        ProjectQuota {
+            limit: limit.max(1),
+            window,
+        }
+    }
+
+    /// Allow `limit` messages within any minute.
+    pub fn per_minute(limit: u32) -> ProjectQuota {
+        ProjectQuota::new(limit, Duration::from_secs(60))
+    }
+}
+
+/// The messages sent for a project, grouped into fixed slots keyed by their
+/// end. Slots are counted from the first message of the project, and a slot
+/// only expires once its last possible message is older than the window.
+#[derive(Debug, Default)]
+struct Window {
+    origin: Option<Instant>,
+    slots: VecDeque<(Instant, u32)>,
+    count: u32,
+    paused_until: Option<Instant>,
+}
+
+impl Window {
+    fn expire(&mut self, window: Duration, now: Instant) {
+        while let Some(&(end, count)) = self.slots.front() {
+            if end + window > now {
+                break;
+            }
+
+            self.slots.pop_front();
+            self.count -= count;
+        }
+
+        if self.paused_until.map(|until| until <= now).unwrap_or(false) {
+            self.paused_until = None;
+        }
+    }
+
+    fn record(&mut self, slot: Duration, now: Instant) {
+        let origin = *self.origin.get_or_insert(now);
+        let slot = slot.as_nanos().max(1);
+        let elapsed = now.saturating_duration_since(origin).as_nanos();
+        let end = origin + Duration::from_nanos(u64::try_from((elapsed / slot + 1) * slot).unwrap_or(u64::MAX));
+
+        match self.slots.back_mut() {
+            Some((last, count)) if *last == end => *count += 1,
+            _ => self.slots.push_back((end, 1)),
+        }
+
+        self.count += 1;
+    }
+}
+
+/// Tracks the v1 messages sent per project in a sliding window and paces
+/// sends to stay below a [ProjectQuota](struct.ProjectQuota.html).
+///
+/// A [Client](../struct.Client.html) built with
+/// [ClientBuilder::project_quota](../struct.ClientBuilder.html#method.project_quota)
+/// shares one tracker between all of its clones.
+#[derive(Debug)]
+pub struct QuotaTracker {
+    quota: ProjectQuota,
+    projects: Mutex<HashMap<String, Window>>,
+}
+
+impl QuotaTracker {
+    /// Get a new tracker that keeps every project below `quota`.
+    pub fn new(quota: ProjectQuota) -> QuotaTracker {
+        QuotaTracker {
+            quota,
+            projects: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The quota every project is kept below.
+    pub fn quota(&self) -> ProjectQuota {
+        self.quota
+    }
+
+    /// Count one message for `project` if the quota allows it, or return how
+    /// long to wait until it does.
+    pub fn try_acquire(&self, project: &str) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut projects = self.projects.lock().unwrap_or_else(|e| e.into_inner());
+        let window = projects.entry(project.to_string()).or_default();
+
+        window.expire(self.quota.window, now);
+
+        if let Some(until) = window.paused_until {
+            return Err(until - now);
+        }
+
+        if window.count >= self.quota.limit {
+            let oldest = window.slots.front().map(|&(end, _)| end).unwrap_or(now);
+            return Err((oldest + self.quota.window).saturating_duration_since(now));
+        }
+
+        window.record(self.quota.window / SLOTS, now);
+
+        Ok(())
+    }
+
+    /// Count one message for `project`, waiting until the quota allows it.
+    pub async fn acquire(&self, project: &str) {
+        while let Err(wait) = self.try_acquire(project) {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Pause all sends for `project` after FCM answered with a 429, for
+    /// `delay` if it included a `RetryInfo` detail and one second otherwise.
+    pub fn pause(&self, project: &str, delay: Option<Duration>) {
+        let now = Instant::now();
+        let delay = delay.unwrap_or(DEFAULT_BACKOFF);
+        let until = now + delay;
+        let mut projects = self.projects.lock().unwrap_or_else(|e| e.into_inner());
+        let window = projects.entry(project.to_string()).or_default();
+
+        window.expire(self.quota.window, now);
+
+        if window.paused_until.map(|paused| paused < until).unwrap_or(true) {
+            window.paused_until = Some(until);
+        }
+
+        log::warn!(
+            "FCM quota exceeded for project {}, pausing sends for {:?}",
+            project,
+            delay
+        );
+    }
+
+    /// How many messages were counted for `project` within the current
+    /// window.
+    pub fn used(&self, project: &str) -> u32 {
+        let mut projects = self.projects.lock().unwrap_or_else(|e| e.into_inner());
+
+        match projects.get_mut(project) {
+            Some(window) => {
+                window.expire(self.quota.window, Instant::now());
+                window.count
+            }
+            None => 0,
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use super::{LimitKey, Mode, ProjectQuota, QuotaTracker, RateLimit, RateLimitedTransport, RateLimiter};
+use crate::client::transport::scripted::ScriptedTransport;
+use crate::{Client, ClientBuilder, FcmError, MessageBuilder, RetryAfter};
+use http::StatusCode;
+use serde_json::json;
+use std::time::Duration;
+
+fn token(token: &str) -> LimitKey {
+    LimitKey::Token(token.to_string())
+}
+
+#[tokio::test(start_paused = true)]
+async fn should_allow_a_burst_and_then_refill() {
+    let mut limiter = RateLimiter::new();
+    limiter.per_token(RateLimit::new(2, Duration::from_secs(10)));
+
+    assert_eq!(Ok(()), limiter.try_acquire(&[token("a")]));
+    assert_eq!(Ok(()), limiter.try_acquire(&[token("a")]));
+    assert_eq!(Err(Duration::from_secs(10)), limiter.try_acquire(&[token("a")]));
+    assert_eq!(Ok(()), limiter.try_acquire(&[token("b")]));
+
+    tokio::time::advance(Duration::from_secs(4)).await;
+    assert_eq!(Err(Duration::from_secs(6)), limiter.try_acquire(&[token("a")]));
+
+    tokio::time::advance(Duration::from_secs(6)).await;
+    assert_eq!(Ok(()), limiter.try_acquire(&[token("a")]));
+}
+
+#[test]
+fn should_not_display_full_registration_tokens() {
+    assert_eq!("token abcdefgh…", token("abcdefghijklmnop").to_string());
+    assert_eq!("token short", token("short").to_string());
+    assert_eq!("/topics/news", LimitKey::Topic("news".to_string()).to_string());
+}
+
+#[tokio::test(start_paused = true)]
+async fn should_acquire_all_keys_or_none() {
+    let mut limiter = RateLimiter::new();
+    limiter.per_token(RateLimit::new(1, Duration::from_secs(10)));
+
+    assert_eq!(Ok(()), limiter.try_acquire(&[token("a")]));
+    assert!(limiter.try_acquire(&[token("b"), token("a")]).is_err());
+    assert_eq!(Ok(()), limiter.try_acquire(&[token("b")]));
+}
+
+#[tokio::test(start_paused = true)]
+async fn should_not_limit_keys_without_a_limit() {
+    let mut limiter = RateLimiter::new();
+    limiter.per_token(RateLimit::new(1, Duration::from_secs(10)));
+
+    let topics = [LimitKey::Topic("news".to_string())];
+
+    for _ in 0..10 {
+        assert_eq!(Ok(()), limiter.try_acquire(&topics));
+    }
+
+    assert_eq!(None, limiter.refill_interval(&topics[0]));
+}
+
+#[tokio::test(start_paused = true)]
+async fn should_reject_or_delay_depending_on_the_mode() {
+    let mut limiter = RateLimiter::new();
+    limiter.per_token(RateLimit::new(1, Duration::from_secs(10)));
+
+    limiter.acquire(&[token("a")]).await.unwrap();
+
+    match limiter.acquire(&[token("a")]).await {
+        Err(FcmError::RateLimited(RetryAfter::Delay(wait))) => assert_eq!(chrono::Duration::seconds(10), wait),
+        result => panic!("unexpected {:?}", result.map_err(|e| e.to_string())),
+    }
+
+    limiter.mode(Mode::Delay {
+        max_wait: Duration::from_secs(5),
+    });
+    assert!(limiter.acquire(&[token("a")]).await.is_err());
+
+    limiter.mode(Mode::Delay {
+        max_wait: Duration::from_secs(10),
+    });
+    let start = tokio::time::Instant::now();
+    limiter.acquire(&[token("a")]).await.unwrap();
+    assert_eq!(Duration::from_secs(10), start.elapsed());
+}
+
+#[tokio::test(start_paused = true)]
+async fn should_tighten_until_the_cooldown_passes() {
+    let mut limiter = RateLimiter::new();
+    limiter
+        .per_token(RateLimit::new(5, Duration::from_secs(1)))
+        .cooldown(Duration::from_secs(60));
+
+    limiter.tighten(&token("a"));
+    assert_eq!(Some(Duration::from_secs(2)), limiter.refill_interval(&token("a")));
+    assert_eq!(Err(Duration::from_secs(2)), limiter.try_acquire(&[token("a")]));
+
+    limiter.tighten(&token("a"));
+    assert_eq!(Some(Duration::from_secs(4)), limiter.refill_interval(&token("a")));
+
+    for _ in 0..10 {
+        limiter.tighten(&token("a"));
+    }
+    assert_eq!(Some(Duration::from_secs(64)), limiter.refill_interval(&token("a")));
+    assert_eq!(Some(Duration::from_secs(1)), limiter.refill_interval(&token("b")));
+
+    tokio::time::advance(Duration::from_secs(60)).await;
+    assert_eq!(Some(Duration::from_secs(1)), limiter.refill_interval(&token("a")));
+}
+
+#[tokio::test(start_paused = true)]
+async fn should_limit_sends_through_the_transport() {
+    let transport = ScriptedTransport::default();
+
+    let mut limiter = RateLimiter::new();
+    limiter.per_token(RateLimit::new(1, Duration::from_secs(10)));
+
+    let client = Client::with_transport(RateLimitedTransport::new(transport.clone(), limiter));
+
+    client
+        .send(MessageBuilder::new("api_key", "a").finalize())
+        .await
+        .unwrap();
+
+    match client.send(MessageBuilder::new("api_key", "a").finalize()).await {
+        Err(e @ FcmError::RateLimited(_)) => assert!(e.is_retry_safe()),
+        result => panic!("unexpected {:?}", result.map_err(|e| e.to_string())),
+    }
+
+    assert_eq!(1, transport.requests());
+
+    client.token_info("api_key", "a").await.unwrap();
+    assert_eq!(2, transport.requests());
+}
+
+#[tokio::test(start_paused = true)]
+async fn should_tighten_tokens_reported_as_exceeding_their_rate() {
+    let body = json!({
+        "multicast_id": 1,
+        "success": 1,
+        "failure": 1,
+        "canonical_ids": 0,
+        "results": [{"message_id": "1"}, {"error": "DeviceMessageRateExceeded"}]
+    });
+    let transport = ScriptedTransport::new(StatusCode::OK, body);
+
+    let mut limiter = RateLimiter::new();
+    limiter.per_token(RateLimit::new(10, Duration::from_secs(1)));
+
+    let transport = RateLimitedTransport::new(transport, limiter);
+    let limiter = transport.limiter().clone();
+    let client = Client::with_transport(transport);
+
+    client
+        .send(MessageBuilder::new_multi("api_key", &["a", "b"]).finalize())
+        .await
+        .unwrap();
+
+    assert_eq!(Some(Duration::from_secs(1)), limiter.refill_interval(&token("a")));
+    assert_eq!(Some(Duration::from_secs(2)), limiter.refill_interval(&token("b")));
+}
+
+#[tokio::test(start_paused = true)]
+async fn should_tighten_topics_reported_as_exceeding_their_rate() {
+    let transport = ScriptedTransport::new(StatusCode::OK, json!({"error": "TopicsMessageRateExceeded"}));
+
+    let mut limiter = RateLimiter::new();
+    limiter.per_topic(RateLimit::new(10, Duration::from_secs(1)));
+
+    let transport = RateLimitedTransport::new(transport, limiter);
+    let limiter = transport.limiter().clone();
+    let client = Client::with_transport(transport);
+
+    let _ = client
+        .send(MessageBuilder::new("api_key", "/topics/news").finalize())
+        .await;
+
+    let topic = LimitKey::Topic("news".to_string());
+    assert_eq!(Some(Duration::from_secs(2)), limiter.refill_interval(&topic));
+}
+
+#[tokio::test(start_paused = true)]
+async fn should_tighten_v1_targets_on_quota_exceeded() {
+    let body = json!({
+        "error": {
+            "code": 429,
+            "message": "Quota exceeded",
+            "status": "RESOURCE_EXHAUSTED",
+            "details": [{
+                "@type": "type.googleapis.com/google.firebase.fcm.v1.FcmError",
+                "errorCode": "QUOTA_EXCEEDED"
+            }]
+        }
+    });
+    let transport = ScriptedTransport::new(StatusCode::TOO_MANY_REQUESTS, body);
+
+    let mut limiter = RateLimiter::new();
+    limiter.per_token(RateLimit::new(10, Duration::from_secs(1)));
+
+    let transport = RateLimitedTransport::new(transport, limiter);
+    let limiter = transport.limiter().clone();
+    let client = Client::with_transport(transport);
+
+    let message = crate::v1::MessageBuilder::new(crate::v1::Target::Token("a".to_string())).finalize();
+    let credentials = crate::v1::Credentials::access_token("project", "token");
+
+    assert!(client.send_v1(&credentials, &message).await.is_err());
+    assert_eq!(Some(Duration::from_secs(2)), limiter.refill_interval(&token("a")));
+}
+
+#[tokio::test(start_paused = true)]
+async fn should_limit_the_messages_of_multipart_batches() {
+    let transport = ScriptedTransport::default();
+
+    let mut limiter = RateLimiter::new();
+    limiter.per_token(RateLimit::new(1, Duration::from_secs(10)));
+
+    let mut builder = ClientBuilder::new();
+    builder
+        .transport(RateLimitedTransport::new(transport.clone(), limiter))
+        .batch_strategy(crate::v1::BatchStrategy::Multipart);
+    let client = builder.finalize().unwrap();
+
+    let message = |to: &str| crate::v1::MessageBuilder::new(crate::v1::Target::Token(to.to_string())).finalize();
+    let credentials = crate::v1::Credentials::access_token("project", "token");
+
+    let _ = client.send_each(&credentials, &[message("a"), message("b")]).await;
+    assert_eq!(1, transport.requests());
+
+    match client.send_each(&credentials, &[message("c"), message("a")]).await {
+        Err(FcmError::RateLimited(_)) => {}
+        result => panic!("unexpected {:?}", result.map_err(|e| e.to_string())),
+    }
+    assert_eq!(1, transport.requests());
+}
+
+#[tokio::test(start_paused = true)]
+async fn should_count_messages_in_a_sliding_window() {
+    let tracker = QuotaTracker::new(ProjectQuota::new(3, Duration::from_secs(60)));
+
+    assert_eq!(Ok(()), tracker.try_acquire("project"));
+    tokio::time::advance(Duration::from_secs(30)).await;
+    assert_eq!(Ok(()), tracker.try_acquire("project"));
+    assert_eq!(Ok(()), tracker.try_acquire("project"));
+    assert_eq!(Ok(()), tracker.try_acquire("other"));
+
+    assert_eq!(3, tracker.used("project"));
+
+    // The first message counts until its one second slot is a window old.
+    assert_eq!(Err(Duration::from_secs(31)), tracker.try_acquire("project"));
+
+    tokio::time::advance(Duration::from_secs(30)).await;
+    assert_eq!(3, tracker.used("project"));
+
+    tokio::time::advance(Duration::from_secs(1)).await;
+    assert_eq!(2, tracker.used("project"));
+    assert_eq!(Ok(()), tracker.try_acquire("project"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn should_not_block_a_steady_rate_below_the_quota() {
+    let tracker = QuotaTracker::new(ProjectQuota::per_minute(100));
+
+    for _ in 0..1000 {
+        assert_eq!(Ok(()), tracker.try_acquire("project"));
+        tokio::time::advance(Duration::from_millis(900)).await;
+    }
+
+    assert!(tracker.used("project") <= 68, "{}", tracker.used("project"));
+}
+
+#[tokio::test(start_paused = true)]
+async fn should_never_exceed_the_limit_within_a_window() {
+    let tracker = QuotaTracker::new(ProjectQuota::per_minute(100));
+    let start = tokio::time::Instant::now();
+    let mut sent = Vec::new();
+
+    // Open the first slot, then burst at its very end.
+    assert_eq!(Ok(()), tracker.try_acquire("project"));
+    sent.push(start.elapsed());
+    tokio::time::advance(Duration::from_millis(990)).await;
+
+    while start.elapsed() < Duration::from_secs(180) {
+        while tracker.try_acquire("project").is_ok() {
+            sent.push(start.elapsed());
+        }
+
+        tokio::time::advance(Duration::from_millis(10)).await;
+    }
+
+    for (i, first) in sent.iter().enumerate() {
+        let within = sent[i..]
+            .iter()
+            .take_while(|at| **at < *first + Duration::from_secs(60))
+            .count();
+
+        assert!(within <= 100, "{} messages within 60s of {:?}", within, first);
+    }
+
+    assert!(sent.len() >= 200);
+}
+
+#[tokio::test(start_paused = true)]
+async fn should_pause_a_project() {
+    let tracker = QuotaTracker::new(ProjectQuota::per_minute(100));
+
+    tracker.pause("project", Some(Duration::from_secs(5)));
+    tracker.pause("project", Some(Duration::from_secs(2)));
+    assert_eq!(Err(Duration::from_secs(5)), tracker.try_acquire("project"));
+    assert_eq!(Ok(()), tracker.try_acquire("other"));
+
+    let start = tokio::time::Instant::now();
+    tracker.acquire("project").await;
+    assert_eq!(Duration::from_secs(5), start.elapsed());
+
+    tracker.pause("project", None);
+    assert_eq!(Err(Duration::from_secs(1)), tracker.try_acquire("project"));
+}
+
+fn quota_client(transport: ScriptedTransport, quota: ProjectQuota) -> Client {
+    let mut builder = ClientBuilder::new();
+    builder.transport(transport).project_quota(quota);
+    builder.finalize().unwrap()
+}
+
+#[tokio::test(start_paused = true)]
+async fn should_pace_v1_sends_across_clones() {
+    let transport = ScriptedTransport::new(StatusCode::OK, json!({"name": "projects/project/messages/1"}));
+    let client = quota_client(transport.clone(), ProjectQuota::new(2, Duration::from_secs(60)));
+    let clone = client.clone();
+
+    let message = crate::v1::MessageBuilder::new(crate::v1::Target::Token("a".to_string())).finalize();
+    let credentials = crate::v1::Credentials::access_token("project", "token");
+
+    let start = tokio::time::Instant::now();
+    client.send_v1(&credentials, &message).await.unwrap();
+    clone.send_v1(&credentials, &message).await.unwrap();
+    assert_eq!(Duration::ZERO, start.elapsed());
+
+    // The first two sends count until their one second slot is a window old.
+    client.send_v1(&credentials, &message).await.unwrap();
+    assert_eq!(Duration::from_secs(61), start.elapsed());
+    assert_eq!(3, transport.requests());
+}
+
+#[tokio::test(start_paused = true)]
+async fn should_pause_v1_sends_for_the_retry_info_delay() {
+    let body = json!({
+        "error": {
+            "code": 429,
+            "message": "Quota exceeded",
+            "status": "RESOURCE_EXHAUSTED",
+            "details": [
+                {"@type": "type.googleapis.com/google.firebase.fcm.v1.FcmError", "errorCode": "QUOTA_EXCEEDED"},
+                {"@type": "type.googleapis.com/google.rpc.RetryInfo", "retryDelay": "30s"}
+            ]
+        }
+    });
+    let transport = ScriptedTransport::new(StatusCode::TOO_MANY_REQUESTS, body);
+    let client = quota_client(transport.clone(), ProjectQuota::FCM_DEFAULT);
+
+    let message = crate::v1::MessageBuilder::new(crate::v1::Target::Token("a".to_string())).finalize();
+    let credentials = crate::v1::Credentials::access_token("project", "token");
+
+    let start = tokio::time::Instant::now();
+    assert!(client.send_v1(&credentials, &message).await.is_err());
+    assert!(client.send_v1(&credentials, &message).await.is_err());
+    assert_eq!(Duration::from_secs(30), start.elapsed());
+    assert_eq!(2, transport.requests());
+}
@@ -0,0 +1,88 @@
+use crate::client::transport::BoxFuture;
+use chrono::{DateTime, Duration, Utc};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// The time source of a [Scheduler](struct.Scheduler.html).
+pub trait Clock: Send + Sync + 'static {
+    /// The current time.
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Complete once [now](#tymethod.now) reached `deadline`.
+    fn sleep_until(&self, deadline: DateTime<Utc>) -> BoxFuture<'_, ()>;
+}
+
+/// The system clock, sleeping with the timer of the tokio runtime.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+
+    fn sleep_until(&self, deadline: DateTime<Utc>) -> BoxFuture<'_, ()> {
+        Box::pin(async move {
+            // The system time may jump, so check again after sleeping.
+            loop {
+                match (deadline - Utc::now()).to_std() {
+                    Ok(wait) if !wait.is_zero() => tokio::time::sleep(wait).await,
+                    _ => return,
+                }
+            }
+        })
+    }
+}
+
+/// A clock that only moves when told to, for deterministic tests. Clones
+/// share the same time.
+///
+/// ```rust
+/// use chrono::{Duration, TimeZone, Utc};
+/// use fcm::scheduler::{Clock, ManualClock};
+///
+/// let clock = ManualClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 9, 0, 0).unwrap());
+/// clock.advance(Duration::hours(1));
+/// assert_eq!(Utc.with_ymd_and_hms(2024, 1, 1, 10, 0, 0).unwrap(), clock.now());
+/// ```
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<watch::Sender<DateTime<Utc>>>,
+}
+
+impl ManualClock {
+    /// Get a new clock that starts at `now`.
+    pub fn new(now: DateTime<Utc>) -> ManualClock {
+        ManualClock {
+            now: Arc::new(watch::channel(now).0),
+        }
+    }
+
+    /// Set the time, waking up the sleepers whose deadline passed.
+    pub fn set(&self, now: DateTime<Utc>) {
+        self.now.send_replace(now);
+    }
+
+    /// Move the time forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        self.now.send_modify(|now| *now += duration);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.borrow()
+    }
+
+    fn sleep_until(&self, deadline: DateTime<Utc>) -> BoxFuture<'_, ()> {
+        let mut now = self.now.subscribe();
+
+        Box::pin(async move {
+            while *now.borrow_and_update() < deadline {
+                if now.changed().await.is_err() {
+                    return;
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,384 @@
+//! Messages sent at a later time.
+//!
+//! Enable the `scheduler` feature to use this module. A
+//! [Scheduler](struct.Scheduler.html) sends each message through a
+//! [Client](../struct.Client.html) at its `send_at` time, and returns a
+//! [ScheduleHandle](struct.ScheduleHandle.html) to cancel or reschedule it
+//! until then. Scheduled messages are kept in memory, use an
+//! [outbox](../outbox/index.html) for messages that must survive a restart.
+//!
+//! ```no_run
+//! use chrono::{Duration, Utc};
+//! use fcm::scheduler::{ScheduledMessage, Scheduler};
+//! use fcm::{Client, MessageBuilder};
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), fcm::FcmError> {
+//! let scheduler = Scheduler::new(Client::new());
+//!
+//! let mut builder = MessageBuilder::new("<FCM API Key>", "<registration id>");
+//! builder.time_to_live(3600);
+//!
+//! let message = ScheduledMessage::legacy(&builder.finalize())?;
+//! let handle = scheduler.schedule(message, Utc::now() + Duration::hours(2));
+//!
+//! handle.reschedule(Utc::now() + Duration::hours(3));
+//! println!("{:?}", handle.outcome().await);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A message with a time to live expires that long after its `send_at`
+//! time, or at an explicit expiry given with
+//! [schedule_with_expiry](struct.Scheduler.html#method.schedule_with_expiry).
+//! When the message fires, its time to live is set to the time remaining
+//! until that expiry, and a message that already expired is not sent.
+//!
+//! With the `quiet-hours` feature, a
+//! [QuietHoursPolicy](struct.QuietHoursPolicy.html) defers messages that would
+//! arrive during the quiet hours of their recipient.
+
+use crate::v1::{self, Credentials, SendResponse};
+use crate::{Client, FcmError, FcmResponse, Message};
+use chrono::{DateTime, Duration, Utc};
+use serde_json::{json, Value};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+mod clock;
+pub use self::clock::*;
+
+#[cfg(feature = "quiet-hours")]
+mod quiet_hours;
+#[cfg(feature = "quiet-hours")]
+pub use self::quiet_hours::*;
+
+#[cfg(test)]
+mod tests;
+
+/// The longest time to live FCM accepts, four weeks.
+const MAX_TTL: i64 = 2_419_200;
+
+/// A message to send at a later time, owning everything needed to send it.
+#[derive(Clone)]
+pub enum ScheduledMessage {
+    /// The serialized body of a legacy message with its server key.
+    Legacy { api_key: String, body: Value },
+
+    /// A v1 message with the credentials to send it with.
+    V1 {
+        credentials: Arc<Credentials>,
+        message: Box<v1::Message>,
+    },
+}
+
+impl ScheduledMessage {
+    /// Schedule a legacy message.
+    pub fn legacy(message: &Message<'_>) -> Result<ScheduledMessage, FcmError> {
+        Ok(ScheduledMessage::Legacy {
+            api_key: message.api_key.to_string(),
+            body: serde_json::to_value(&message.body).map_err(FcmError::Serialization)?,
+        })
+    }
+
+    /// Schedule a v1 message, sent with `credentials`.
+    pub fn v1(credentials: Arc<Credentials>, message: v1::Message) -> ScheduledMessage {
+        ScheduledMessage::V1 {
+            credentials,
+            message: Box::new(message),
+        }
+    }
+
+    /// The time to live of the message, in seconds.
+    fn ttl(&self) -> Option<i64> {
+        match self {
+            ScheduledMessage::Legacy { body, .. } => body["time_to_live"].as_i64(),
+            ScheduledMessage::V1 { message, .. } => message
+                .android
+                .as_ref()
+                .and_then(|android| android["ttl"].as_str())
+                .and_then(|ttl| ttl.strip_suffix('s'))
+                .and_then(|seconds| seconds.parse::<f64>().ok())
+                .map(|seconds| seconds as i64),
+        }
+    }
+
+    /// `true` if the message asks for high priority delivery on any
+    /// platform.
+    #[cfg_attr(not(feature = "quiet-hours"), allow(dead_code))]
+    fn is_high_priority(&self) -> bool {
+        match self {
+            ScheduledMessage::Legacy { body, .. } => body["priority"] == "high",
+            ScheduledMessage::V1 { message, .. } => {
+                let android = message.android.as_ref().map(|android| &android["priority"]);
+                let apns = message.apns.as_ref().map(|apns| &apns["headers"]["apns-priority"]);
+
+                android.map(|priority| priority == "high").unwrap_or(false)
+                    || apns.map(|priority| priority == "10").unwrap_or(false)
+            }
+        }
+    }
+
+    /// Set the time to live to the `remaining` seconds until `expiry`.
+    fn set_ttl(&mut self, remaining: i64, expiry: DateTime<Utc>) {
+        match self {
+            ScheduledMessage::Legacy { body, .. } => {
+                body["time_to_live"] = json!(remaining);
+            }
+            ScheduledMessage::V1 { message, .. } => {
+                let android = message.android.get_or_insert_with(|| json!({}));
+                android["ttl"] = json!(format!("{}s", remaining));
+
+                let apns = message.apns.get_or_insert_with(|| json!({}));
+                apns["headers"]["apns-expiration"] = json!(expiry.timestamp().to_string());
+
+                let webpush = message.webpush.get_or_insert_with(|| json!({}));
+                webpush["headers"]["TTL"] = json!(remaining.to_string());
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ScheduledMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScheduledMessage::Legacy { body, .. } => f
+                .debug_struct("Legacy")
+                .field("api_key", &format_args!("<redacted>"))
+                .field("body", body)
+                .finish(),
+            ScheduledMessage::V1 { credentials, message } => f
+                .debug_struct("V1")
+                .field("credentials", credentials)
+                .field("message", message)
+                .finish(),
+        }
+    }
+}
+
+/// What became of a scheduled message.
+#[derive(Debug)]
+pub enum ScheduleOutcome {
+    /// The legacy message was sent, with the result of the send.
+    Sent(Result<FcmResponse, FcmError>),
+
+    /// The v1 message was sent, with the result of the send.
+    SentV1(Result<SendResponse, FcmError>),
+
+    /// The message was cancelled before it was sent.
+    Cancelled,
+
+    /// The message expired before it could be sent.
+    Expired,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum JobState {
+    Waiting(DateTime<Utc>),
+    Cancelled,
+    Fired,
+}
+
+#[derive(Debug)]
+struct Job {
+    state: Mutex<JobState>,
+    changed: Notify,
+}
+
+impl Job {
+    fn state(&self) -> MutexGuard<'_, JobState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Sends messages at their scheduled time. Cloning the scheduler is cheap,
+/// all clones share the same client and clock.
+///
+/// Scheduling spawns a task, so it must be done within a tokio runtime.
+#[derive(Clone)]
+pub struct Scheduler {
+    client: Client,
+    clock: Arc<dyn Clock>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Scheduler {
+    /// Get a new scheduler that sends through `client` on the system clock.
+    pub fn new(client: Client) -> Scheduler {
+        Self::with_clock(client, SystemClock)
+    }
+
+    /// Get a new scheduler that sends through `client` on `clock`.
+    pub fn with_clock<C: Clock>(client: Client, clock: C) -> Scheduler {
+        Scheduler {
+            client,
+            clock: Arc::new(clock),
+            next_id: Arc::new(AtomicU64::new(1)),
+        }
+    }
+
+    /// Send `message` at `send_at`, or right away if that time passed.
+    pub fn schedule(&self, message: ScheduledMessage, send_at: DateTime<Utc>) -> ScheduleHandle {
+        self.spawn(message, send_at, None)
+    }
+
+    /// Send `message` at `send_at`, unless it is past `expires_at` by then.
+    /// The time to live of the message is set to the time remaining until
+    /// `expires_at` when it fires.
+    pub fn schedule_with_expiry(
+        &self,
+        message: ScheduledMessage,
+        send_at: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+    ) -> ScheduleHandle {
+        self.spawn(message, send_at, Some(expires_at))
+    }
+
+    fn spawn(
+        &self,
+        message: ScheduledMessage,
+        send_at: DateTime<Utc>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> ScheduleHandle {
+        let job = Arc::new(Job {
+            state: Mutex::new(JobState::Waiting(send_at)),
+            changed: Notify::new(),
+        });
+
+        let task = tokio::spawn(run(
+            self.client.clone(),
+            self.clock.clone(),
+            job.clone(),
+            message,
+            expires_at,
+        ));
+
+        ScheduleHandle {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            job,
+            task,
+        }
+    }
+}
+
+/// Wait until the job is due, then send its message.
+async fn run(
+    client: Client,
+    clock: Arc<dyn Clock>,
+    job: Arc<Job>,
+    mut message: ScheduledMessage,
+    expires_at: Option<DateTime<Utc>>,
+) -> ScheduleOutcome {
+    let send_at = loop {
+        let send_at = match *job.state() {
+            JobState::Waiting(send_at) => send_at,
+            JobState::Cancelled => return ScheduleOutcome::Cancelled,
+            JobState::Fired => unreachable!("a job only fires once"),
+        };
+
+        tokio::select! {
+            _ = clock.sleep_until(send_at) => {}
+            _ = job.changed.notified() => continue,
+        }
+
+        let mut state = job.state();
+
+        if *state == JobState::Waiting(send_at) {
+            *state = JobState::Fired;
+            break send_at;
+        }
+    };
+
+    let expiry = match (expires_at, message.ttl()) {
+        (Some(expires_at), _) => Some(expires_at),
+        // A TTL of zero asks for delivery now or never, which is what firing
+        // the job does, so the message is sent with it unchanged.
+        (None, Some(0)) => None,
+        (None, ttl) => ttl
+            .and_then(Duration::try_seconds)
+            .and_then(|ttl| send_at.checked_add_signed(ttl)),
+    };
+
+    if let Some(expiry) = expiry {
+        let remaining = expiry - clock.now();
+
+        if remaining <= Duration::zero() {
+            return ScheduleOutcome::Expired;
+        }
+
+        // Round up, so that less than a second left is not sent as a TTL of
+        // zero.
+        let seconds = remaining.num_seconds() + i64::from(remaining.subsec_nanos() > 0);
+        message.set_ttl(seconds.min(MAX_TTL), expiry);
+    }
+
+    match message {
+        ScheduledMessage::Legacy { api_key, body } => match serde_json::to_vec(&body) {
+            Ok(payload) => ScheduleOutcome::Sent(client.send_payload(&api_key, payload).await),
+            Err(e) => ScheduleOutcome::Sent(Err(FcmError::Serialization(e))),
+        },
+        ScheduledMessage::V1 { credentials, message } => {
+            ScheduleOutcome::SentV1(client.send_v1(&credentials, &message).await)
+        }
+    }
+}
+
+/// A scheduled message. Dropping the handle does not cancel the message.
+#[derive(Debug)]
+pub struct ScheduleHandle {
+    id: u64,
+    job: Arc<Job>,
+    task: JoinHandle<ScheduleOutcome>,
+}
+
+impl ScheduleHandle {
+    /// Identifies the message within its scheduler.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// When the message is sent, `None` if it was cancelled or already
+    /// fired.
+    pub fn send_at(&self) -> Option<DateTime<Utc>> {
+        match *self.job.state() {
+            JobState::Waiting(send_at) => Some(send_at),
+            _ => None,
+        }
+    }
+
+    /// Cancel the message. Returns `false` if it was already sent or
+    /// cancelled.
+    pub fn cancel(&self) -> bool {
+        self.update(JobState::Cancelled)
+    }
+
+    /// Send the message at `send_at` instead. Returns `false` if it was
+    /// already sent or cancelled.
+    pub fn reschedule(&self, send_at: DateTime<Utc>) -> bool {
+        self.update(JobState::Waiting(send_at))
+    }
+
+    fn update(&self, new_state: JobState) -> bool {
+        let mut state = self.job.state();
+
+        if let JobState::Waiting(_) = *state {
+            *state = new_state;
+            self.job.changed.notify_one();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Wait until the message was sent, cancelled or expired.
+    pub async fn outcome(self) -> ScheduleOutcome {
+        match self.task.await {
+            Ok(outcome) => outcome,
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(_) => ScheduleOutcome::Cancelled,
+        }
+    }
+}
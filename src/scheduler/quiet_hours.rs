@@ -0,0 +1,166 @@
+use crate::scheduler::{ScheduleHandle, ScheduledMessage, Scheduler, MAX_TTL};
+use chrono::{DateTime, Duration, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// A daily window of local time in which non-urgent messages are not
+/// delivered. The window may span midnight, e.g. from 22:00 to 07:00.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    /// When the window starts, inclusive.
+    pub start: NaiveTime,
+
+    /// When the window ends, exclusive. A window that ends when it starts is
+    /// empty.
+    pub end: NaiveTime,
+}
+
+impl QuietHours {
+    /// Get a new window from `start` to `end` local time.
+    pub fn new(start: NaiveTime, end: NaiveTime) -> QuietHours {
+        QuietHours { start, end }
+    }
+
+    /// When the window that `now` falls into ends in `time_zone`, `None` if
+    /// `now` is outside the window.
+    pub fn ends_at(&self, now: DateTime<Utc>, time_zone: Tz) -> Option<DateTime<Utc>> {
+        let local = now.with_timezone(&time_zone).naive_local();
+        let time = local.time();
+        let date = local.date();
+
+        let end_date = if self.start < self.end && self.start <= time && time < self.end {
+            date
+        } else if self.start > self.end && time >= self.start {
+            date.succ_opt()?
+        } else if self.start > self.end && time < self.end {
+            date
+        } else {
+            return None;
+        };
+
+        Some(local_to_utc(end_date.and_time(self.end), time_zone))
+    }
+}
+
+/// The UTC time of a local time. Ambiguous times resolve to the earlier
+/// instant, and times skipped by a daylight saving change to the first
+/// instant after the gap.
+fn local_to_utc(local: NaiveDateTime, time_zone: Tz) -> DateTime<Utc> {
+    let mut candidate = local;
+
+    loop {
+        if let Some(time) = time_zone.from_local_datetime(&candidate).earliest() {
+            return time.with_timezone(&Utc);
+        }
+
+        candidate += Duration::minutes(1);
+    }
+}
+
+/// The time zone and quiet hours of the target of a message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recipient {
+    pub time_zone: Tz,
+
+    pub quiet_hours: QuietHours,
+}
+
+impl Recipient {
+    /// A recipient in `time_zone` that does not want messages during
+    /// `quiet_hours`.
+    pub fn new(time_zone: Tz, quiet_hours: QuietHours) -> Recipient {
+        Recipient { time_zone, quiet_hours }
+    }
+}
+
+/// How [QuietHoursPolicy::send](struct.QuietHoursPolicy.html#method.send)
+/// handled a message.
+#[derive(Debug)]
+pub enum Delivery {
+    /// The message is sent right away, because it is outside the quiet
+    /// hours of the recipient or urgent.
+    Now(ScheduleHandle),
+
+    /// The message is sent when the quiet hours end.
+    Deferred(ScheduleHandle),
+
+    /// The message was dropped, as its time to live would expire before the
+    /// quiet hours end at `until`.
+    Dropped { until: DateTime<Utc> },
+}
+
+impl Delivery {
+    /// The handle of a message that is sent now or later.
+    pub fn handle(self) -> Option<ScheduleHandle> {
+        match self {
+            Delivery::Now(handle) | Delivery::Deferred(handle) => Some(handle),
+            Delivery::Dropped { .. } => None,
+        }
+    }
+}
+
+/// Defers non-urgent messages that would arrive during the quiet hours of
+/// their recipient until the quiet hours end.
+///
+/// Messages with high priority bypass the quiet hours, as do messages sent
+/// with [send_urgent](#method.send_urgent). A message that is deferred keeps
+/// its original expiry, see the [module documentation](index.html).
+///
+/// ```no_run
+/// use chrono::NaiveTime;
+/// use chrono_tz::Europe::Helsinki;
+/// use fcm::scheduler::{QuietHours, QuietHoursPolicy, Recipient, ScheduledMessage, Scheduler};
+/// use fcm::{Client, MessageBuilder};
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), fcm::FcmError> {
+/// let policy = QuietHoursPolicy::new(Scheduler::new(Client::new()));
+///
+/// let quiet_hours = QuietHours::new(
+///     NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+///     NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+/// );
+/// let recipient = Recipient::new(Helsinki, quiet_hours);
+///
+/// let message = MessageBuilder::new("<FCM API Key>", "<registration id>").finalize();
+/// let delivery = policy.send(ScheduledMessage::legacy(&message)?, &recipient);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct QuietHoursPolicy {
+    scheduler: Scheduler,
+}
+
+impl QuietHoursPolicy {
+    /// Get a new policy that sends and defers messages with `scheduler`.
+    pub fn new(scheduler: Scheduler) -> QuietHoursPolicy {
+        QuietHoursPolicy { scheduler }
+    }
+
+    /// Send `message` now, unless it is not urgent and `recipient` is in
+    /// their quiet hours.
+    pub fn send(&self, message: ScheduledMessage, recipient: &Recipient) -> Delivery {
+        let now = self.scheduler.clock.now();
+
+        let ends_at = match recipient.quiet_hours.ends_at(now, recipient.time_zone) {
+            Some(ends_at) if !message.is_high_priority() => ends_at,
+            _ => return Delivery::Now(self.scheduler.schedule(message, now)),
+        };
+
+        // A TTL too long to represent never expires.
+        let expires_at = Duration::try_seconds(message.ttl().unwrap_or(MAX_TTL))
+            .and_then(|ttl| now.checked_add_signed(ttl))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+
+        if expires_at <= ends_at {
+            return Delivery::Dropped { until: ends_at };
+        }
+
+        Delivery::Deferred(self.scheduler.schedule_with_expiry(message, ends_at, expires_at))
+    }
+
+    /// Send `message` now, regardless of the quiet hours.
+    pub fn send_urgent(&self, message: ScheduledMessage) -> ScheduleHandle {
+        self.scheduler.schedule(message, self.scheduler.clock.now())
+    }
+}
@@ -0,0 +1,326 @@
+use crate::client::transport::scripted::ScriptedTransport;
+use crate::scheduler::{ManualClock, ScheduleOutcome, ScheduledMessage, Scheduler};
+use crate::v1::{self, Credentials, Target};
+use crate::{Client, MessageBuilder};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde_json::json;
+use std::sync::Arc;
+
+fn start() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 1, 1, 9, 0, 0).unwrap()
+}
+
+fn setup() -> (ScriptedTransport, ManualClock, Scheduler) {
+    let transport = ScriptedTransport::default();
+    let clock = ManualClock::new(start());
+    let scheduler = Scheduler::with_clock(Client::with_transport(transport.clone()), clock.clone());
+
+    (transport, clock, scheduler)
+}
+
+fn legacy(ttl: Option<i32>) -> ScheduledMessage {
+    let mut builder = MessageBuilder::new("api_key", "token");
+
+    if let Some(ttl) = ttl {
+        builder.time_to_live(ttl);
+    }
+
+    ScheduledMessage::legacy(&builder.finalize()).unwrap()
+}
+
+/// Let the scheduled tasks run until they wait again.
+async fn settle() {
+    for _ in 0..10 {
+        tokio::task::yield_now().await;
+    }
+}
+
+#[tokio::test]
+async fn should_send_at_the_scheduled_time() {
+    let (transport, clock, scheduler) = setup();
+
+    let handle = scheduler.schedule(legacy(None), start() + Duration::minutes(10));
+
+    clock.advance(Duration::minutes(9));
+    settle().await;
+    assert_eq!(0, transport.requests());
+    assert_eq!(Some(start() + Duration::minutes(10)), handle.send_at());
+
+    clock.advance(Duration::minutes(1));
+
+    assert!(matches!(handle.outcome().await, ScheduleOutcome::Sent(Ok(_))));
+    assert_eq!(vec![json!({"to": "token"})], transport.bodies());
+}
+
+#[tokio::test]
+async fn should_not_send_cancelled_messages() {
+    let (transport, clock, scheduler) = setup();
+
+    let handle = scheduler.schedule(legacy(None), start() + Duration::minutes(10));
+    settle().await;
+
+    assert!(handle.cancel());
+    assert!(!handle.reschedule(start()));
+    assert_eq!(None, handle.send_at());
+
+    clock.advance(Duration::hours(1));
+
+    assert!(matches!(handle.outcome().await, ScheduleOutcome::Cancelled));
+    assert_eq!(0, transport.requests());
+}
+
+#[tokio::test]
+async fn should_send_rescheduled_messages_at_the_new_time() {
+    let (transport, clock, scheduler) = setup();
+
+    let later = scheduler.schedule(legacy(None), start() + Duration::minutes(10));
+    let earlier = scheduler.schedule(legacy(None), start() + Duration::minutes(10));
+    settle().await;
+
+    assert!(later.reschedule(start() + Duration::minutes(30)));
+    assert!(earlier.reschedule(start() + Duration::minutes(5)));
+
+    clock.advance(Duration::minutes(10));
+    assert!(matches!(earlier.outcome().await, ScheduleOutcome::Sent(Ok(_))));
+    settle().await;
+    assert_eq!(1, transport.requests());
+
+    clock.advance(Duration::minutes(20));
+    assert!(matches!(later.outcome().await, ScheduleOutcome::Sent(Ok(_))));
+    assert_eq!(2, transport.requests());
+}
+
+#[tokio::test]
+async fn should_send_the_remaining_time_to_live() {
+    let (transport, clock, scheduler) = setup();
+
+    let handle = scheduler.schedule(legacy(Some(3600)), start() + Duration::minutes(10));
+
+    // The message fires 15 minutes late, e.g. because the process was busy.
+    clock.advance(Duration::minutes(25));
+    handle.outcome().await;
+
+    assert_eq!(json!({"to": "token", "time_to_live": 2700}), transport.bodies()[0]);
+}
+
+#[tokio::test]
+async fn should_not_send_expired_messages() {
+    let (transport, clock, scheduler) = setup();
+
+    let handle = scheduler.schedule_with_expiry(
+        legacy(Some(3600)),
+        start() + Duration::minutes(10),
+        start() + Duration::minutes(5),
+    );
+
+    clock.advance(Duration::minutes(10));
+
+    assert!(matches!(handle.outcome().await, ScheduleOutcome::Expired));
+    assert_eq!(0, transport.requests());
+}
+
+#[tokio::test]
+async fn should_send_messages_with_a_time_to_live_of_zero() {
+    let (transport, clock, scheduler) = setup();
+
+    let handle = scheduler.schedule(legacy(Some(0)), start() + Duration::minutes(10));
+
+    clock.advance(Duration::minutes(10));
+
+    assert!(matches!(handle.outcome().await, ScheduleOutcome::Sent(Ok(_))));
+    assert_eq!(json!({"to": "token", "time_to_live": 0}), transport.bodies()[0]);
+}
+
+#[tokio::test]
+async fn should_keep_less_than_a_second_to_live() {
+    let (transport, clock, scheduler) = setup();
+
+    let handle = scheduler.schedule(legacy(Some(3600)), start());
+
+    clock.advance(Duration::seconds(3599) + Duration::milliseconds(500));
+
+    assert!(matches!(handle.outcome().await, ScheduleOutcome::Sent(Ok(_))));
+    assert_eq!(json!({"to": "token", "time_to_live": 1}), transport.bodies()[0]);
+}
+
+#[tokio::test]
+async fn should_send_messages_whose_time_to_live_is_out_of_range() {
+    let (transport, _clock, scheduler) = setup();
+
+    let mut builder = v1::MessageBuilder::new(Target::Token("token".to_string()));
+    builder.android(json!({"ttl": "100000000000000000s"}));
+    let message = ScheduledMessage::v1(Arc::new(Credentials::access_token("p", "token")), builder.finalize());
+
+    let handle = scheduler.schedule(message, start());
+
+    assert!(matches!(handle.outcome().await, ScheduleOutcome::SentV1(Ok(_))));
+    assert_eq!(1, transport.requests());
+}
+
+#[tokio::test]
+async fn should_set_the_expiry_of_v1_messages() {
+    let (transport, clock, scheduler) = setup();
+    let credentials = Arc::new(Credentials::access_token("p", "token"));
+    let message = v1::MessageBuilder::new(Target::Token("token".to_string())).finalize();
+
+    let expires_at = start() + Duration::hours(2);
+    let handle = scheduler.schedule_with_expiry(
+        ScheduledMessage::v1(credentials, message),
+        start() + Duration::hours(1),
+        expires_at,
+    );
+
+    clock.advance(Duration::hours(1));
+    assert!(matches!(handle.outcome().await, ScheduleOutcome::SentV1(Ok(_))));
+
+    let message = &transport.bodies()[0]["message"];
+    assert_eq!(json!("3600s"), message["android"]["ttl"]);
+    assert_eq!(
+        json!(expires_at.timestamp().to_string()),
+        message["apns"]["headers"]["apns-expiration"]
+    );
+    assert_eq!(json!("3600"), message["webpush"]["headers"]["TTL"]);
+}
+
+#[tokio::test]
+async fn should_send_past_times_right_away() {
+    let (transport, _clock, scheduler) = setup();
+
+    let handle = scheduler.schedule(legacy(None), start() - Duration::minutes(1));
+
+    assert!(matches!(handle.outcome().await, ScheduleOutcome::Sent(Ok(_))));
+    assert_eq!(1, transport.requests());
+}
+
+#[test]
+fn should_not_print_server_keys() {
+    let message = ScheduledMessage::legacy(&MessageBuilder::new("secret-key", "token").finalize()).unwrap();
+    let printed = format!("{:?}", message);
+
+    assert!(printed.contains("token"));
+    assert!(!printed.contains("secret-key"));
+}
+
+#[cfg(feature = "quiet-hours")]
+mod quiet_hours {
+    use super::*;
+    use crate::scheduler::{Delivery, QuietHours, QuietHoursPolicy, Recipient};
+    use crate::Priority;
+    use chrono::NaiveTime;
+    use chrono_tz::America::New_York;
+    use chrono_tz::Europe::Helsinki;
+
+    fn night() -> QuietHours {
+        QuietHours::new(
+            NaiveTime::from_hms_opt(22, 0, 0).unwrap(),
+            NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+        )
+    }
+
+    fn utc(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 1, 1, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn should_find_the_end_of_the_window_in_the_recipient_time_zone() {
+        // Helsinki is UTC+2 in winter, New York UTC-5.
+        assert_eq!(None, night().ends_at(utc(12, 0), Helsinki));
+        assert_eq!(Some(utc(5, 0)), night().ends_at(utc(1, 0), Helsinki));
+        assert_eq!(
+            Some(Utc.with_ymd_and_hms(2024, 1, 2, 5, 0, 0).unwrap()),
+            night().ends_at(utc(20, 30), Helsinki)
+        );
+        assert_eq!(Some(utc(12, 0)), night().ends_at(utc(4, 0), New_York));
+        assert_eq!(None, night().ends_at(utc(12, 0), New_York));
+
+        let lunch = QuietHours::new(
+            NaiveTime::from_hms_opt(12, 0, 0).unwrap(),
+            NaiveTime::from_hms_opt(13, 0, 0).unwrap(),
+        );
+        assert_eq!(Some(utc(11, 0)), lunch.ends_at(utc(10, 30), Helsinki));
+        assert_eq!(None, lunch.ends_at(utc(11, 0), Helsinki));
+    }
+
+    fn policy_at(now: DateTime<Utc>) -> (ScriptedTransport, ManualClock, QuietHoursPolicy) {
+        let (transport, clock, scheduler) = setup();
+        clock.set(now);
+
+        (transport, clock, QuietHoursPolicy::new(scheduler))
+    }
+
+    #[tokio::test]
+    async fn should_send_outside_of_the_quiet_hours() {
+        let (transport, _clock, policy) = policy_at(utc(10, 0));
+
+        let delivery = policy.send(legacy(None), &Recipient::new(Helsinki, night()));
+
+        assert!(matches!(delivery, Delivery::Now(_)));
+        delivery.handle().unwrap().outcome().await;
+        assert_eq!(1, transport.requests());
+    }
+
+    #[tokio::test]
+    async fn should_defer_until_the_quiet_hours_end() {
+        let (transport, clock, policy) = policy_at(utc(1, 0));
+
+        let handle = match policy.send(legacy(Some(6 * 3600)), &Recipient::new(Helsinki, night())) {
+            Delivery::Deferred(handle) => handle,
+            delivery => panic!("unexpected {:?}", delivery),
+        };
+
+        assert_eq!(Some(utc(5, 0)), handle.send_at());
+        settle().await;
+        assert_eq!(0, transport.requests());
+
+        clock.set(utc(5, 0));
+        handle.outcome().await;
+
+        // Two of the six hours to live are left when the quiet hours end.
+        assert_eq!(json!({"to": "token", "time_to_live": 7200}), transport.bodies()[0]);
+    }
+
+    #[tokio::test]
+    async fn should_drop_messages_that_expire_during_the_quiet_hours() {
+        let (transport, _clock, policy) = policy_at(utc(1, 0));
+
+        let delivery = policy.send(legacy(Some(3600)), &Recipient::new(Helsinki, night()));
+
+        assert!(matches!(delivery, Delivery::Dropped { until } if until == utc(5, 0)));
+        settle().await;
+        assert_eq!(0, transport.requests());
+    }
+
+    #[tokio::test]
+    async fn should_defer_messages_whose_time_to_live_is_out_of_range() {
+        let (_transport, _clock, policy) = policy_at(utc(1, 0));
+
+        let mut builder = v1::MessageBuilder::new(Target::Token("token".to_string()));
+        builder.android(json!({"ttl": "100000000000000000s"}));
+        let message = ScheduledMessage::v1(Arc::new(Credentials::access_token("p", "t")), builder.finalize());
+
+        let delivery = policy.send(message, &Recipient::new(Helsinki, night()));
+
+        assert!(matches!(delivery, Delivery::Deferred(_)));
+    }
+
+    #[tokio::test]
+    async fn should_let_urgent_messages_bypass_the_quiet_hours() {
+        let (transport, _clock, policy) = policy_at(utc(1, 0));
+        let recipient = Recipient::new(Helsinki, night());
+
+        let mut builder = MessageBuilder::new("api_key", "token");
+        builder.priority(Priority::High);
+        let high = ScheduledMessage::legacy(&builder.finalize()).unwrap();
+
+        let mut android = v1::MessageBuilder::new(Target::Token("token".to_string()));
+        android.android(json!({"priority": "high"}));
+        let high_v1 = ScheduledMessage::v1(Arc::new(Credentials::access_token("p", "t")), android.finalize());
+
+        assert!(matches!(policy.send(high, &recipient), Delivery::Now(_)));
+        assert!(matches!(policy.send(high_v1, &recipient), Delivery::Now(_)));
+        policy.send_urgent(legacy(None)).outcome().await;
+
+        settle().await;
+        assert_eq!(3, transport.requests());
+    }
+}
@@ -0,0 +1,527 @@
+use crate::client::transport::{BoxFuture, HttpRequest, HttpResponse, Transport};
+use crate::FcmError;
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+/// The value recorded in place of redacted headers and fields.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Replaces the boundary of multipart bodies when they are matched, since
+/// each batch request gets a new one.
+const BOUNDARY_PLACEHOLDER: &str = "[BOUNDARY]";
+
+/// Body fields that are redacted unless configured otherwise: the signed
+/// assertion of OAuth token requests and the issued access token.
+const DEFAULT_REDACTED_FIELDS: [&str; 2] = ["assertion", "access_token"];
+
+/// A request or response body as stored in a cassette.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordedBody {
+    /// A body that parsed as JSON.
+    Json(Value),
+
+    /// Any other body, e.g. a form or an HTML error page.
+    Text(String),
+}
+
+impl RecordedBody {
+    fn new(body: &[u8], redacted_fields: &[String]) -> RecordedBody {
+        match serde_json::from_slice(body) {
+            Ok(mut json) => {
+                if let Value::Object(ref mut object) = json {
+                    for (key, value) in object.iter_mut() {
+                        if redacted_fields.contains(key) {
+                            *value = Value::String(REDACTED.to_string());
+                        }
+                    }
+                }
+
+                RecordedBody::Json(json)
+            }
+            Err(_) => {
+                let text = String::from_utf8_lossy(body);
+
+                if !text.contains('=') {
+                    return RecordedBody::Text(text.into_owned());
+                }
+
+                let pairs: Vec<String> = text
+                    .split('&')
+                    .map(|pair| match pair.split_once('=') {
+                        Some((key, _)) if redacted_fields.iter().any(|field| field == key) => {
+                            format!("{}={}", key, REDACTED)
+                        }
+                        _ => pair.to_string(),
+                    })
+                    .collect();
+
+                RecordedBody::Text(pairs.join("&"))
+            }
+        }
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            RecordedBody::Json(json) => serde_json::to_vec(json).expect("JSON values serialize"),
+            RecordedBody::Text(text) => text.clone().into_bytes(),
+        }
+    }
+}
+
+/// A request as stored in a cassette.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    pub body: RecordedBody,
+}
+
+/// A response as stored in a cassette.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedResponse {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    pub body: RecordedBody,
+}
+
+/// A single request and the response it got.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+/// Recorded interactions, stored as a JSON file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Cassette {
+    pub interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    /// Read a cassette from `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Cassette> {
+        let json = std::fs::read(path)?;
+        serde_json::from_slice(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Write the cassette to `path`, replacing any existing file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let json = serde_json::to_vec_pretty(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        std::fs::write(path, json)
+    }
+}
+
+/// A [Transport](../transport/trait.Transport.html) that sends requests with
+/// another transport and records every exchange to a cassette file.
+///
+/// The file is rewritten after each exchange. The `Authorization` header and
+/// the `assertion` and `access_token` body fields are recorded as
+/// [REDACTED](constant.REDACTED.html). Requests that fail without a response,
+/// e.g. with a connection error, are not recorded.
+///
+/// # Examples
+///
+/// ```no_run
+/// use fcm::testing::RecordingTransport;
+/// use fcm::transport::ReqwestTransport;
+///
+/// # fn main() -> Result<(), fcm::FcmError> {
+/// let mut recorder = RecordingTransport::new(ReqwestTransport::new()?, "tests/cassettes/send.json");
+/// recorder.redact_header("x-goog-user-project");
+///
+/// let client = fcm::Client::with_transport(recorder);
+/// # Ok(())
+/// # }
+/// ```
+pub struct RecordingTransport<T> {
+    inner: T,
+    path: PathBuf,
+    redacted_headers: Vec<String>,
+    redacted_fields: Vec<String>,
+    cassette: Mutex<Cassette>,
+}
+
+impl<T: Transport> RecordingTransport<T> {
+    /// Record the exchanges of `inner` to a new cassette at `path`.
+    pub fn new<P: Into<PathBuf>>(inner: T, path: P) -> RecordingTransport<T> {
+        RecordingTransport {
+            inner,
+            path: path.into(),
+            redacted_headers: vec![http::header::AUTHORIZATION.as_str().to_string()],
+            redacted_fields: DEFAULT_REDACTED_FIELDS.iter().map(|field| field.to_string()).collect(),
+            cassette: Mutex::new(Cassette::default()),
+        }
+    }
+
+    /// Record the value of the request and response header `name` as
+    /// [REDACTED](constant.REDACTED.html).
+    pub fn redact_header(&mut self, name: &str) -> &mut Self {
+        self.redacted_headers.push(name.to_ascii_lowercase());
+        self
+    }
+
+    /// Record the top-level JSON or form field `name` of request and response
+    /// bodies as [REDACTED](constant.REDACTED.html).
+    pub fn redact_field(&mut self, name: &str) -> &mut Self {
+        self.redacted_fields.push(name.to_string());
+        self
+    }
+
+    /// The interactions recorded so far.
+    pub fn cassette(&self) -> Cassette {
+        self.lock().clone()
+    }
+
+    fn record(&self, request: &HttpRequest, response: &HttpResponse) -> io::Result<()> {
+        let interaction = Interaction {
+            request: RecordedRequest {
+                method: request.method.to_string(),
+                url: request.url.clone(),
+                headers: record_headers(&request.headers, &self.redacted_headers),
+                body: RecordedBody::new(&request.body, &self.redacted_fields),
+            },
+            response: RecordedResponse {
+                status: response.status.as_u16(),
+                headers: record_headers(&response.headers, &self.redacted_headers),
+                body: RecordedBody::new(&response.body, &self.redacted_fields),
+            },
+        };
+
+        let mut cassette = self.lock();
+        cassette.interactions.push(interaction);
+        cassette.save(&self.path)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Cassette> {
+        self.cassette.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<T: Transport> Transport for RecordingTransport<T> {
+    fn execute(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, FcmError>> {
+        Box::pin(async move {
+            let response = self.inner.execute(request.clone()).await?;
+
+            self.record(&request, &response)
+                .map_err(|e| FcmError::Transport(e.into()))?;
+
+            Ok(response)
+        })
+    }
+}
+
+/// Decides whether a request matches a recorded one.
+///
+/// By default the method, URL and body must be equal, and headers are
+/// ignored. A recorded [REDACTED](constant.REDACTED.html) header or
+/// top-level field matches any value, as long as it is present. The
+/// boundaries of `multipart` bodies may differ.
+#[derive(Debug, Clone, Default)]
+pub struct Matcher {
+    headers: Vec<String>,
+    ignore_registration_ids_order: bool,
+    ignored_fields: Vec<String>,
+}
+
+impl Matcher {
+    /// Get a new instance of Matcher with the default rules.
+    pub fn new() -> Matcher {
+        Self::default()
+    }
+
+    /// Also require the header `name` to match, e.g. `authorization`.
+    pub fn match_header(&mut self, name: &str) -> &mut Self {
+        self.headers.push(name.to_ascii_lowercase());
+        self
+    }
+
+    /// When set to `true`, the `registration_ids` and `registration_tokens`
+    /// of a body may be in any order. The positional `results` of a replayed
+    /// response are then reordered to follow the ids of the request.
+    pub fn ignore_registration_ids_order(&mut self, ignore: bool) -> &mut Self {
+        self.ignore_registration_ids_order = ignore;
+        self
+    }
+
+    /// Ignore the top-level JSON or form field `name` of request bodies.
+    pub fn ignore_field(&mut self, name: &str) -> &mut Self {
+        self.ignored_fields.push(name.to_string());
+        self
+    }
+
+    /// `true` if `request` matches the `recorded` request.
+    pub fn matches(&self, recorded: &RecordedRequest, request: &RecordedRequest) -> bool {
+        recorded.method == request.method
+            && recorded.url == request.url
+            && self.headers.iter().all(|name| {
+                match_value(
+                    recorded.headers.get(name).map(String::as_str),
+                    request.headers.get(name).map(String::as_str),
+                )
+            })
+            && self.bodies_match(&without_boundary(recorded), &without_boundary(request))
+    }
+
+    fn bodies_match(&self, recorded: &RecordedBody, request: &RecordedBody) -> bool {
+        match (recorded, request) {
+            (RecordedBody::Json(Value::Object(recorded)), RecordedBody::Json(Value::Object(request))) => {
+                let keys = recorded.keys().chain(request.keys());
+
+                keys.filter(|key| !self.ignored_fields.contains(key)).all(|key| {
+                    match (recorded.get(key), request.get(key)) {
+                        (Some(Value::String(value)), Some(_)) if value == REDACTED => true,
+                        (Some(recorded), Some(request)) if self.is_id_list(key) => {
+                            sorted_ids(recorded) == sorted_ids(request)
+                        }
+                        (recorded, request) => recorded == request,
+                    }
+                })
+            }
+            (RecordedBody::Text(recorded), RecordedBody::Text(request)) if recorded.contains('=') => {
+                let pairs = |text: &'_ str| -> Vec<(String, String)> {
+                    text.split('&')
+                        .filter_map(|pair| pair.split_once('='))
+                        .filter(|(key, _)| !self.ignored_fields.iter().any(|field| field == key))
+                        .map(|(key, value)| (key.to_string(), value.to_string()))
+                        .collect()
+                };
+
+                let (recorded, request) = (pairs(recorded), pairs(request));
+
+                recorded.len() == request.len()
+                    && recorded.iter().zip(request.iter()).all(|(recorded, request)| {
+                        recorded.0 == request.0 && match_value(Some(&recorded.1), Some(&request.1))
+                    })
+            }
+            (recorded, request) => recorded == request,
+        }
+    }
+
+    fn is_id_list(&self, key: &str) -> bool {
+        self.ignore_registration_ids_order && (key == "registration_ids" || key == "registration_tokens")
+    }
+
+    /// The body to replay for `request`, with the `results` of the `recorded`
+    /// response moved to the positions of their ids in `request`.
+    fn replayed_body(
+        &self,
+        recorded: &RecordedRequest,
+        request: &RecordedRequest,
+        body: &RecordedBody,
+    ) -> RecordedBody {
+        let reordered = match (&recorded.body, &request.body, body) {
+            (RecordedBody::Json(recorded), RecordedBody::Json(request), RecordedBody::Json(body)) => {
+                ["registration_ids", "registration_tokens"]
+                    .iter()
+                    .filter(|key| self.is_id_list(key))
+                    .find_map(|key| reorder_results(recorded.get(key)?, request.get(key)?, body))
+            }
+            _ => None,
+        };
+
+        reordered.map_or_else(|| body.clone(), RecordedBody::Json)
+    }
+}
+
+/// A [Transport](../transport/trait.Transport.html) that answers requests
+/// from a cassette, without touching the network.
+///
+/// Each recorded interaction is replayed at most once, for the first request
+/// that matches it. A request without an unused match fails with
+/// `FcmError::Transport`.
+///
+/// # Examples
+///
+/// ```no_run
+/// use fcm::testing::{Matcher, ReplayTransport};
+///
+/// # fn main() -> std::io::Result<()> {
+/// let mut matcher = Matcher::new();
+/// matcher.ignore_registration_ids_order(true);
+///
+/// let replay = ReplayTransport::from_file("tests/cassettes/send.json")?.with_matcher(matcher);
+/// let client = fcm::Client::with_transport(replay);
+/// # Ok(())
+/// # }
+/// ```
+pub struct ReplayTransport {
+    interactions: Vec<Interaction>,
+    used: Mutex<Vec<bool>>,
+    matcher: Matcher,
+}
+
+impl ReplayTransport {
+    /// Replay the interactions of `cassette` with the default
+    /// [Matcher](struct.Matcher.html).
+    pub fn new(cassette: Cassette) -> ReplayTransport {
+        ReplayTransport {
+            used: Mutex::new(vec![false; cassette.interactions.len()]),
+            interactions: cassette.interactions,
+            matcher: Matcher::default(),
+        }
+    }
+
+    /// Replay the cassette stored at `path`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<ReplayTransport> {
+        Cassette::load(path).map(Self::new)
+    }
+
+    /// Match requests with `matcher`.
+    pub fn with_matcher(mut self, matcher: Matcher) -> ReplayTransport {
+        self.matcher = matcher;
+        self
+    }
+
+    /// The number of recorded interactions that have not been replayed yet.
+    pub fn remaining(&self) -> usize {
+        self.lock().iter().filter(|used| !**used).count()
+    }
+
+    fn replay(&self, request: &HttpRequest) -> Result<HttpResponse, FcmError> {
+        let request = RecordedRequest {
+            method: request.method.to_string(),
+            url: request.url.clone(),
+            headers: record_headers(&request.headers, &[]),
+            body: RecordedBody::new(&request.body, &[]),
+        };
+
+        let mut used = self.lock();
+
+        let index = self
+            .interactions
+            .iter()
+            .zip(used.iter())
+            .position(|(interaction, used)| !used && self.matcher.matches(&interaction.request, &request))
+            .ok_or_else(|| {
+                FcmError::Transport(
+                    format!("no recorded interaction matches {} {}", request.method, request.url).into(),
+                )
+            })?;
+
+        used[index] = true;
+
+        let interaction = &self.interactions[index];
+        let response = &interaction.response;
+        let mut headers = HeaderMap::new();
+
+        for (name, value) in &response.headers {
+            if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(value)) {
+                headers.insert(name, value);
+            }
+        }
+
+        Ok(HttpResponse {
+            status: StatusCode::from_u16(response.status).map_err(|e| FcmError::Transport(e.into()))?,
+            headers,
+            body: self
+                .matcher
+                .replayed_body(&interaction.request, &request, &response.body)
+                .to_bytes(),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<bool>> {
+        self.used.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn execute(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, FcmError>> {
+        let result = self.replay(&request);
+        Box::pin(async move { result })
+    }
+}
+
+fn record_headers(headers: &HeaderMap, redacted: &[String]) -> BTreeMap<String, String> {
+    let mut recorded = BTreeMap::new();
+
+    for name in headers.keys() {
+        let value = if redacted.iter().any(|redacted| redacted == name.as_str()) {
+            REDACTED.to_string()
+        } else {
+            headers
+                .get_all(name)
+                .iter()
+                .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        recorded.insert(name.as_str().to_string(), value);
+    }
+
+    recorded
+}
+
+fn match_value(recorded: Option<&str>, request: Option<&str>) -> bool {
+    match (recorded, request) {
+        (Some(REDACTED), Some(_)) => true,
+        (recorded, request) => recorded == request,
+    }
+}
+
+/// The body of `request`, with the boundary of a multipart body replaced by
+/// a placeholder.
+fn without_boundary(request: &RecordedRequest) -> Cow<'_, RecordedBody> {
+    let boundary = request
+        .headers
+        .get("content-type")
+        .filter(|content_type| content_type.starts_with("multipart/"))
+        .and_then(|content_type| {
+            content_type
+                .split(';')
+                .find_map(|parameter| parameter.trim().strip_prefix("boundary="))
+        })
+        .map(|boundary| boundary.trim_matches('"'))
+        .filter(|boundary| !boundary.is_empty());
+
+    match (boundary, &request.body) {
+        (Some(boundary), RecordedBody::Text(text)) => {
+            Cow::Owned(RecordedBody::Text(text.replace(boundary, BOUNDARY_PLACEHOLDER)))
+        }
+        (_, body) => Cow::Borrowed(body),
+    }
+}
+
+fn sorted_ids(value: &Value) -> Option<Vec<String>> {
+    let mut ids: Vec<String> = value.as_array()?.iter().map(Value::to_string).collect();
+    ids.sort();
+    Some(ids)
+}
+
+/// `body` with its `results`, given in the order of `recorded` ids, in the
+/// order of `request` ids instead.
+fn reorder_results(recorded: &Value, request: &Value, body: &Value) -> Option<Value> {
+    let (recorded, request) = (recorded.as_array()?, request.as_array()?);
+    let results = body.get("results")?.as_array()?;
+
+    if recorded.len() != request.len() || recorded.len() != results.len() {
+        return None;
+    }
+
+    let mut remaining: Vec<Option<&Value>> = results.iter().map(Some).collect();
+    let reordered = request
+        .iter()
+        .map(|id| {
+            let index = recorded
+                .iter()
+                .zip(remaining.iter())
+                .position(|(recorded, result)| recorded == id && result.is_some())?;
+
+            remaining[index].take().cloned()
+        })
+        .collect::<Option<Vec<Value>>>()?;
+
+    let mut body = body.clone();
+    body["results"] = Value::Array(reordered);
+    Some(body)
+}
@@ -0,0 +1,419 @@
+//! Test doubles for code that sends messages with [Client](../struct.Client.html).
+//!
+//! Enable the `testing` feature to use this module. A
+//! [MockSender](struct.MockSender.html) hands out clients that never touch
+//! the network. It records every message they send and answers with scripted
+//! responses:
+//!
+//! ```rust
+//! use fcm::testing::MockSender;
+//! use fcm::{ErrorReason, MessageBuilder, NotificationBuilder};
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! let mock = MockSender::new();
+//! mock.token_error("stale-token", ErrorReason::NotRegistered);
+//!
+//! let client = mock.client();
+//!
+//! let mut notification = NotificationBuilder::new();
+//! notification.title("Hey!");
+//!
+//! let mut builder = MessageBuilder::new("<FCM API Key>", "fresh-token");
+//! builder.notification(notification.finalize());
+//! client.send(builder.finalize()).await.unwrap();
+//!
+//! let response = client
+//!     .send(MessageBuilder::new("<FCM API Key>", "stale-token").finalize())
+//!     .await
+//!     .unwrap();
+//! assert_eq!(Some(ErrorReason::NotRegistered), response.results.unwrap()[0].error);
+//!
+//! mock.assert_notification_sent("fresh-token", "Hey!");
+//! mock.assert_no_message_to_removed_tokens();
+//! # }
+//! ```
+//!
+//! To test against real FCM responses without calling FCM in every run, record
+//! the exchanges once with a [RecordingTransport](struct.RecordingTransport.html)
+//! and replay the resulting cassette with a
+//! [ReplayTransport](struct.ReplayTransport.html).
+
+use crate::client::transport::{BoxFuture, HttpRequest, HttpResponse, Transport};
+use crate::{
+    Client, DeviceGroupResponse, ErrorReason, FcmError, FcmResponse, MessageResult, TokenInfo, TopicManagementResponse,
+    TopicManagementResult,
+};
+use http::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE};
+use http::StatusCode;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+mod cassette;
+pub use self::cassette::*;
+
+#[cfg(test)]
+mod tests;
+
+/// A message as it was sent through a mock client.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentMessage {
+    /// The API key from the `Authorization` header.
+    pub api_key: String,
+
+    /// The message as it was serialized to JSON.
+    pub body: Value,
+}
+
+impl SentMessage {
+    /// The registration tokens or topic the message was addressed to, from
+    /// the `to` and `registration_ids` fields.
+    pub fn targets(&self) -> Vec<&str> {
+        let to = self.body.get("to").and_then(Value::as_str);
+        let ids = self
+            .body
+            .get("registration_ids")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str);
+
+        to.into_iter().chain(ids).collect()
+    }
+
+    /// `true` if the message was addressed to `target`.
+    pub fn is_sent_to(&self, target: &str) -> bool {
+        self.targets().contains(&target)
+    }
+
+    /// The title of the notification, if the message has one.
+    pub fn notification_title(&self) -> Option<&str> {
+        self.body.pointer("/notification/title").and_then(Value::as_str)
+    }
+
+    /// The custom data of the message, if it has any.
+    pub fn data(&self) -> Option<&Value> {
+        self.body.get("data")
+    }
+}
+
+/// The scripted outcome of a message for a single registration token.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenOutcome {
+    /// The message was accepted.
+    Delivered,
+
+    /// The message was accepted, but the token has been replaced by the
+    /// given canonical registration id.
+    CanonicalId(String),
+
+    /// The message was rejected for this token.
+    Error(ErrorReason),
+}
+
+enum Reply {
+    Response(FcmResponse),
+    Error(FcmError),
+}
+
+#[derive(Default)]
+struct State {
+    sent: Vec<SentMessage>,
+    topic_requests: Vec<SentMessage>,
+    replies: VecDeque<Reply>,
+    tokens: HashMap<String, TokenOutcome>,
+    removed: Vec<(usize, String)>,
+    next_message_id: u64,
+}
+
+/// A mock of FCM that records sent messages and answers with scripted
+/// replies.
+///
+/// Each send is answered with the next reply queued with
+/// [push_response](#method.push_response) or
+/// [push_error](#method.push_error). Without a queued reply, the mock answers
+/// like FCM would, with one result per target token: the outcome scripted
+/// with [token_outcome](#method.token_outcome), or a successful delivery.
+/// Topic subscription and device group changes always succeed, and token
+/// details are always empty. Other requests, e.g. to the v1 API, fail with
+/// `FcmError::Transport`.
+///
+/// Clones share the same recordings and scripts.
+#[derive(Clone, Default)]
+pub struct MockSender {
+    state: Arc<Mutex<State>>,
+}
+
+impl MockSender {
+    /// Get a new mock without any scripted replies.
+    pub fn new() -> MockSender {
+        Self::default()
+    }
+
+    /// Get a client that sends all its requests to this mock.
+    pub fn client(&self) -> Client {
+        Client::with_transport(self.clone())
+    }
+
+    /// Answer the next send that has no earlier queued reply with `response`.
+    pub fn push_response(&self, response: FcmResponse) -> &Self {
+        self.state().replies.push_back(Reply::Response(response));
+        self
+    }
+
+    /// Fail the next send that has no earlier queued reply with `error`.
+    pub fn push_error(&self, error: FcmError) -> &Self {
+        self.state().replies.push_back(Reply::Error(error));
+        self
+    }
+
+    /// Use `outcome` for every message sent to `token` without a queued reply.
+    pub fn token_outcome(&self, token: &str, outcome: TokenOutcome) -> &Self {
+        self.state().tokens.insert(token.to_string(), outcome);
+        self
+    }
+
+    /// Reject every message sent to `token` without a queued reply with
+    /// `reason`.
+    pub fn token_error(&self, token: &str, reason: ErrorReason) -> &Self {
+        self.token_outcome(token, TokenOutcome::Error(reason))
+    }
+
+    /// All messages sent so far, in order.
+    pub fn sent_messages(&self) -> Vec<SentMessage> {
+        self.state().sent.clone()
+    }
+
+    /// All messages sent to `target` so far, in order.
+    pub fn sent_to(&self, target: &str) -> Vec<SentMessage> {
+        self.state()
+            .sent
+            .iter()
+            .filter(|message| message.is_sent_to(target))
+            .cloned()
+            .collect()
+    }
+
+    /// All topic subscription changes so far, in order.
+    pub fn topic_requests(&self) -> Vec<SentMessage> {
+        self.state().topic_requests.clone()
+    }
+
+    /// Forget all recorded messages and removed tokens. Scripts are kept.
+    pub fn clear(&self) {
+        let mut state = self.state();
+        state.sent.clear();
+        state.topic_requests.clear();
+        state.removed.clear();
+    }
+
+    /// Panic unless a notification with `title` was sent to `target`.
+    pub fn assert_notification_sent(&self, target: &str, title: &str) {
+        let sent = self.sent_to(target);
+
+        assert!(
+            sent.iter().any(|message| message.notification_title() == Some(title)),
+            "no notification with title {:?} was sent to {:?}, sent: {:#?}",
+            title,
+            target,
+            sent
+        );
+    }
+
+    /// Panic if any message was sent to `target`.
+    pub fn assert_nothing_sent_to(&self, target: &str) {
+        let sent = self.sent_to(target);
+
+        assert!(
+            sent.is_empty(),
+            "expected no message to {:?}, sent: {:#?}",
+            target,
+            sent
+        );
+    }
+
+    /// Panic if a message was sent to a token after FCM reported that the
+    /// token should be removed, see
+    /// [ErrorReason::should_remove_token](../enum.ErrorReason.html#method.should_remove_token).
+    pub fn assert_no_message_to_removed_tokens(&self) {
+        let state = self.state();
+
+        for (removed_at, token) in &state.removed {
+            let later = state.sent[removed_at + 1..]
+                .iter()
+                .find(|message| message.is_sent_to(token));
+
+            if let Some(message) = later {
+                panic!("message sent to removed token {:?}: {:#?}", token, message);
+            }
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn reply_to_send(&self, message: SentMessage) -> Result<HttpResponse, FcmError> {
+        let mut state = self.state();
+        let index = state.sent.len();
+        state.sent.push(message.clone());
+
+        let response = match state.replies.pop_front() {
+            Some(Reply::Error(error)) => return Err(error),
+            Some(Reply::Response(response)) => response,
+            None => state.respond(&message),
+        };
+
+        for (token, result) in message.targets().iter().zip(response.results.iter().flatten()) {
+            if matches!(result.error, Some(ref reason) if reason.should_remove_token()) {
+                state.removed.push((index, token.to_string()));
+            }
+        }
+
+        Ok(json_response(&response))
+    }
+
+    fn reply_to_topic_request(&self, message: SentMessage) -> HttpResponse {
+        let tokens = message
+            .body
+            .get("registration_tokens")
+            .and_then(Value::as_array)
+            .map_or(0, Vec::len);
+
+        self.state().topic_requests.push(message);
+
+        json_response(&TopicManagementResponse {
+            results: vec![TopicManagementResult::default(); tokens],
+        })
+    }
+}
+
+impl State {
+    fn message_id(&mut self) -> u64 {
+        self.next_message_id += 1;
+        self.next_message_id
+    }
+
+    fn respond(&mut self, message: &SentMessage) -> FcmResponse {
+        let targets = message.targets();
+
+        if let [topic] = targets.as_slice() {
+            if topic.starts_with("/topics/") {
+                return FcmResponse {
+                    message_id: Some(self.message_id()),
+                    ..FcmResponse::default()
+                };
+            }
+        }
+
+        let results: Vec<MessageResult> = targets
+            .iter()
+            .map(|token| {
+                let outcome = self.tokens.get(*token).cloned().unwrap_or(TokenOutcome::Delivered);
+                let message_id = Some(format!("0:{}", self.message_id()));
+
+                match outcome {
+                    TokenOutcome::Delivered => MessageResult {
+                        message_id,
+                        ..MessageResult::default()
+                    },
+                    TokenOutcome::CanonicalId(id) => MessageResult {
+                        message_id,
+                        registration_id: Some(id),
+                        ..MessageResult::default()
+                    },
+                    TokenOutcome::Error(reason) => MessageResult {
+                        error: Some(reason),
+                        ..MessageResult::default()
+                    },
+                }
+            })
+            .collect();
+
+        let failure = results.iter().filter(|result| result.error.is_some()).count() as u64;
+        let canonical_ids = results.iter().filter(|result| result.registration_id.is_some()).count() as u64;
+
+        FcmResponse {
+            multicast_id: Some(self.message_id() as i64),
+            success: Some(results.len() as u64 - failure),
+            failure: Some(failure),
+            canonical_ids: Some(canonical_ids),
+            results: Some(results),
+            ..FcmResponse::default()
+        }
+    }
+}
+
+impl Transport for MockSender {
+    fn execute(&self, request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, FcmError>> {
+        let api_key = request
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim_start_matches("key=").to_string())
+            .unwrap_or_default();
+
+        let url = request.url.split('?').next().unwrap_or_default();
+
+        if url.contains("/iid/info/") {
+            let result = Ok(json_response(&TokenInfo::default()));
+            return Box::pin(async move { result });
+        }
+
+        let known = [
+            "/fcm/send",
+            "/fcm/notification",
+            "/iid/v1:batchAdd",
+            "/iid/v1:batchRemove",
+        ];
+        if !known.iter().any(|path| url.ends_with(path)) {
+            let error = FcmError::Transport(format!("MockSender does not support {}", request.url).into());
+            return Box::pin(async move { Err(error) });
+        }
+
+        let result = match serde_json::from_slice(&request.body) {
+            Ok(body) => {
+                let message = SentMessage { api_key, body };
+
+                if url.ends_with("/fcm/send") {
+                    self.reply_to_send(message)
+                } else if url.ends_with("/fcm/notification") {
+                    Ok(reply_to_device_group_request(&message))
+                } else {
+                    Ok(self.reply_to_topic_request(message))
+                }
+            }
+            Err(_) => Ok(HttpResponse {
+                status: StatusCode::BAD_REQUEST,
+                headers: HeaderMap::new(),
+                body: Vec::new(),
+            }),
+        };
+
+        Box::pin(async move { result })
+    }
+}
+
+fn reply_to_device_group_request(message: &SentMessage) -> HttpResponse {
+    let name = message
+        .body
+        .get("notification_key_name")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+
+    json_response(&DeviceGroupResponse {
+        notification_key: format!("mock-notification-key-{}", name),
+    })
+}
+
+fn json_response<T: serde::Serialize>(body: &T) -> HttpResponse {
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+    HttpResponse {
+        status: StatusCode::OK,
+        headers,
+        body: serde_json::to_vec(body).expect("responses serialize to JSON"),
+    }
+}
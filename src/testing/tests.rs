@@ -0,0 +1,361 @@
+use crate::testing::{
+    Cassette, Interaction, Matcher, MockSender, RecordedBody, RecordedRequest, RecordedResponse, RecordingTransport,
+    ReplayTransport, TokenOutcome, REDACTED,
+};
+use crate::{v1, Client, ErrorReason, FcmError, FcmResponse, MessageBuilder, NotificationBuilder};
+use serde_json::json;
+
+#[tokio::test]
+async fn should_record_sent_messages() {
+    let mock = MockSender::new();
+    let client = mock.client();
+
+    let mut builder = MessageBuilder::new_multi("api_key", &["one", "two"]);
+    builder.data(&json!({"foo": "bar"})).unwrap();
+    let response = client.send(builder.finalize()).await.unwrap();
+
+    assert_eq!(Some(2), response.success);
+
+    let sent = mock.sent_messages();
+
+    assert_eq!(1, sent.len());
+    assert_eq!("api_key", sent[0].api_key);
+    assert_eq!(vec!["one", "two"], sent[0].targets());
+    assert_eq!(Some(&json!({"foo": "bar"})), sent[0].data());
+    assert_eq!(1, mock.sent_to("two").len());
+    mock.assert_nothing_sent_to("three");
+}
+
+#[tokio::test]
+async fn should_answer_with_queued_replies_first() {
+    let mock = MockSender::new();
+    let client = mock.client();
+
+    mock.push_response(FcmResponse {
+        message_id: Some(7),
+        ..FcmResponse::default()
+    })
+    .push_error(FcmError::Unauthorized);
+
+    let first = client.send(MessageBuilder::new("api_key", "token").finalize()).await;
+    let second = client.send(MessageBuilder::new("api_key", "token").finalize()).await;
+    let third = client.send(MessageBuilder::new("api_key", "token").finalize()).await;
+
+    assert_eq!(Some(7), first.unwrap().message_id);
+    assert!(matches!(second, Err(FcmError::Unauthorized)));
+    assert_eq!(Some(1), third.unwrap().success);
+}
+
+#[tokio::test]
+async fn should_answer_with_token_outcomes() {
+    let mock = MockSender::new();
+    let client = mock.client();
+
+    mock.token_error("gone", ErrorReason::NotRegistered)
+        .token_outcome("moved", TokenOutcome::CanonicalId("new".to_string()));
+
+    let response = client
+        .send(MessageBuilder::new_multi("api_key", &["ok", "gone", "moved"]).finalize())
+        .await
+        .unwrap();
+
+    let results = response.results.unwrap();
+
+    assert_eq!(Some(2), response.success);
+    assert_eq!(Some(1), response.failure);
+    assert_eq!(Some(1), response.canonical_ids);
+    assert!(results[0].message_id.is_some());
+    assert_eq!(Some(ErrorReason::NotRegistered), results[1].error);
+    assert_eq!(Some("new"), results[2].registration_id.as_deref());
+}
+
+#[tokio::test]
+async fn should_answer_topic_messages_with_a_message_id() {
+    let mock = MockSender::new();
+    let client = mock.client();
+
+    let response = client
+        .send(MessageBuilder::new("api_key", "/topics/news").finalize())
+        .await
+        .unwrap();
+
+    assert!(response.message_id.is_some());
+    assert!(response.results.is_none());
+}
+
+#[tokio::test]
+async fn should_record_topic_requests() {
+    let mock = MockSender::new();
+    let client = mock.client();
+
+    let response = client.subscribe_to_topic("api_key", "news", &["a", "b"]).await.unwrap();
+
+    assert_eq!(2, response.success_count());
+    assert_eq!(json!("/topics/news"), mock.topic_requests()[0].body["to"]);
+    assert!(mock.sent_messages().is_empty());
+}
+
+#[tokio::test]
+async fn should_find_sent_notifications() {
+    let mock = MockSender::new();
+    let client = mock.client();
+
+    let mut notification = NotificationBuilder::new();
+    notification.title("Hey!");
+
+    let mut builder = MessageBuilder::new("api_key", "token");
+    builder.notification(notification.finalize());
+    client.send(builder.finalize()).await.unwrap();
+
+    mock.assert_notification_sent("token", "Hey!");
+}
+
+#[tokio::test]
+#[should_panic(expected = "no notification with title")]
+async fn should_panic_for_missing_notifications() {
+    let mock = MockSender::new();
+    let client = mock.client();
+
+    client
+        .send(MessageBuilder::new("api_key", "token").finalize())
+        .await
+        .unwrap();
+
+    mock.assert_notification_sent("token", "Hey!");
+}
+
+#[tokio::test]
+#[should_panic(expected = "message sent to removed token")]
+async fn should_panic_for_messages_to_removed_tokens() {
+    let mock = MockSender::new();
+    let client = mock.client();
+
+    mock.token_error("gone", ErrorReason::NotRegistered);
+
+    client
+        .send(MessageBuilder::new("api_key", "gone").finalize())
+        .await
+        .unwrap();
+    mock.assert_no_message_to_removed_tokens();
+
+    client
+        .send(MessageBuilder::new("api_key", "gone").finalize())
+        .await
+        .unwrap();
+    mock.assert_no_message_to_removed_tokens();
+}
+
+#[tokio::test]
+async fn should_reject_unsupported_endpoints() {
+    let mock = MockSender::new();
+    let client = mock.client();
+
+    let message = v1::MessageBuilder::new(v1::Target::Token("token".to_string())).finalize();
+    let result = client
+        .send_v1(&v1::Credentials::access_token("project", "token"), &message)
+        .await;
+
+    assert!(matches!(result, Err(FcmError::Transport(_))));
+    assert!(mock.sent_messages().is_empty());
+    assert!(mock.topic_requests().is_empty());
+}
+
+fn cassette_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("fcm-cassette-{}-{}.json", std::process::id(), name))
+}
+
+#[tokio::test]
+async fn should_record_and_replay_exchanges() {
+    let path = cassette_path("replay");
+    let mock = MockSender::new();
+    mock.token_error("gone", ErrorReason::NotRegistered);
+
+    let recorder = Client::with_transport(RecordingTransport::new(mock, &path));
+    let recorded = recorder
+        .send(MessageBuilder::new_multi("secret_key", &["one", "gone"]).finalize())
+        .await
+        .unwrap();
+
+    let cassette = Cassette::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let request = &cassette.interactions[0].request;
+    assert_eq!("POST", request.method);
+    assert_eq!(Some(REDACTED), request.headers.get("authorization").map(String::as_str));
+    assert_eq!(200, cassette.interactions[0].response.status);
+
+    let replay = ReplayTransport::new(cassette);
+    let client = Client::with_transport(replay);
+
+    let replayed = client
+        .send(MessageBuilder::new_multi("other_key", &["one", "gone"]).finalize())
+        .await
+        .unwrap();
+
+    assert_eq!(recorded.multicast_id, replayed.multicast_id);
+    assert_eq!(Some(ErrorReason::NotRegistered), replayed.results.unwrap()[1].error);
+
+    let again = client
+        .send(MessageBuilder::new_multi("other_key", &["one", "gone"]).finalize())
+        .await;
+    assert!(matches!(again, Err(FcmError::Transport(_))));
+}
+
+#[tokio::test]
+async fn should_replay_multipart_batches() {
+    use crate::client::transport::{BoxFuture, HttpRequest, HttpResponse, Transport};
+    use crate::v1::{BatchStrategy, Target};
+    use crate::ClientBuilder;
+    use http::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+
+    struct BatchServer;
+
+    impl Transport for BatchServer {
+        fn execute(&self, _request: HttpRequest) -> BoxFuture<'_, Result<HttpResponse, FcmError>> {
+            let part = |id: u32| {
+                format!(
+                    "--batch_abc\r\n\
+                     Content-Type: application/http\r\n\
+                     Content-ID: response-{id}\r\n\
+                     \r\n\
+                     HTTP/1.1 200 OK\r\n\
+                     Content-Type: application/json\r\n\
+                     \r\n\
+                     {{\"name\": \"projects/p/messages/{id}\"}}\r\n",
+                    id = id
+                )
+            };
+
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("multipart/mixed; boundary=batch_abc"),
+            );
+
+            Box::pin(async move {
+                Ok(HttpResponse {
+                    status: http::StatusCode::OK,
+                    headers,
+                    body: format!("{}{}--batch_abc--\r\n", part(1), part(2)).into_bytes(),
+                })
+            })
+        }
+    }
+
+    fn client<T: Transport + 'static>(transport: T) -> Client {
+        let mut builder = ClientBuilder::new();
+        builder.transport(transport).batch_strategy(BatchStrategy::Multipart);
+        builder.finalize().unwrap()
+    }
+
+    let path = cassette_path("multipart");
+    let credentials = v1::Credentials::access_token("p", "token");
+    let messages: Vec<v1::Message> = ["a", "b"]
+        .iter()
+        .map(|token| v1::MessageBuilder::new(Target::Token(token.to_string())).finalize())
+        .collect();
+
+    let recorded = client(RecordingTransport::new(BatchServer, &path))
+        .send_each(&credentials, &messages)
+        .await
+        .unwrap();
+
+    let cassette = Cassette::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let replay = ReplayTransport::new(cassette);
+    let replayed = client(replay).send_each(&credentials, &messages).await.unwrap();
+
+    assert_eq!(2, recorded.success_count);
+    assert_eq!(2, replayed.success_count);
+    assert_eq!("projects/p/messages/2", replayed.responses[1].as_ref().unwrap().name);
+}
+
+fn recorded_send(ids: &[&str]) -> Cassette {
+    Cassette {
+        interactions: vec![Interaction {
+            request: RecordedRequest {
+                method: "POST".to_string(),
+                url: "https://fcm.googleapis.com/fcm/send".to_string(),
+                headers: std::iter::once(("authorization".to_string(), REDACTED.to_string())).collect(),
+                body: RecordedBody::Json(json!({ "registration_ids": ids })),
+            },
+            response: RecordedResponse {
+                status: 200,
+                headers: Default::default(),
+                body: RecordedBody::Json(json!({ "multicast_id": 1, "success": 2, "failure": 0 })),
+            },
+        }],
+    }
+}
+
+#[tokio::test]
+async fn should_only_ignore_registration_ids_order_when_configured() {
+    let strict = Client::with_transport(ReplayTransport::new(recorded_send(&["a", "b"])));
+    let result = strict
+        .send(MessageBuilder::new_multi("key", &["b", "a"]).finalize())
+        .await;
+    assert!(matches!(result, Err(FcmError::Transport(_))));
+
+    let mut matcher = Matcher::new();
+    matcher
+        .ignore_registration_ids_order(true)
+        .match_header("authorization");
+
+    let replay = ReplayTransport::new(recorded_send(&["a", "b"])).with_matcher(matcher);
+    let lenient = Client::with_transport(replay);
+    let response = lenient
+        .send(MessageBuilder::new_multi("key", &["b", "a"]).finalize())
+        .await
+        .unwrap();
+
+    assert_eq!(Some(2), response.success);
+}
+
+#[tokio::test]
+async fn should_reorder_replayed_results_to_the_request_ids() {
+    let mut cassette = recorded_send(&["a", "b", "c"]);
+    cassette.interactions[0].response.body = RecordedBody::Json(json!({
+        "multicast_id": 1,
+        "success": 2,
+        "failure": 1,
+        "results": [
+            { "message_id": "1" },
+            { "error": "NotRegistered" },
+            { "message_id": "3" },
+        ],
+    }));
+
+    let mut matcher = Matcher::new();
+    matcher.ignore_registration_ids_order(true);
+
+    let client = Client::with_transport(ReplayTransport::new(cassette).with_matcher(matcher));
+    let response = client
+        .send(MessageBuilder::new_multi("key", &["c", "b", "a"]).finalize())
+        .await
+        .unwrap();
+
+    let results = response.results.unwrap();
+
+    assert_eq!(Some("3".to_string()), results[0].message_id);
+    assert_eq!(Some(ErrorReason::NotRegistered), results[1].error);
+    assert_eq!(Some("1".to_string()), results[2].message_id);
+}
+
+#[test]
+fn should_match_redacted_form_fields() {
+    let matcher = Matcher::new();
+
+    let request = |body: &str| RecordedRequest {
+        method: "POST".to_string(),
+        url: "https://oauth2.googleapis.com/token".to_string(),
+        headers: Default::default(),
+        body: RecordedBody::Text(body.to_string()),
+    };
+
+    let recorded = request(&format!("grant_type=jwt&assertion={}", REDACTED));
+
+    assert!(matcher.matches(&recorded, &request("grant_type=jwt&assertion=eyJ.abc.def")));
+    assert!(!matcher.matches(&recorded, &request("grant_type=other&assertion=eyJ.abc.def")));
+    assert!(!matcher.matches(&recorded, &request("grant_type=jwt")));
+}
@@ -0,0 +1,129 @@
+use crate::v1::{Message, MessageBuilder, Notification, SendResponse, Target};
+use crate::FcmError;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// The most messages [Client::send_each](../struct.Client.html#method.send_each)
+/// and tokens
+/// [Client::send_each_for_multicast](../struct.Client.html#method.send_each_for_multicast)
+/// accept at once.
+pub const MAX_BATCH_SIZE: usize = 500;
+
+/// How [Client::send_each](../struct.Client.html#method.send_each) sends its
+/// messages, set with
+/// [ClientBuilder::batch_strategy](../struct.ClientBuilder.html#method.batch_strategy).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BatchStrategy {
+    /// One `messages:send` request per message, several of them in flight at
+    /// once. The default.
+    #[default]
+    Each,
+
+    /// A single request to the batch endpoint with a `multipart/mixed` body
+    /// that wraps all messages, saving connection and header overhead.
+    Multipart,
+}
+
+/// A v1 message sent to several registration tokens, one request per token.
+/// The fields are those of a [Message](struct.Message.html) without its
+/// target.
+///
+/// # Examples
+///
+/// ```rust
+/// use fcm::v1::{MulticastMessage, Notification};
+///
+/// let message = MulticastMessage {
+///     tokens: vec!["<registration id>".to_string()],
+///     notification: Some(Notification {
+///         title: Some("Hey!".to_string()),
+///         ..Notification::default()
+///     }),
+///     ..MulticastMessage::default()
+/// };
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MulticastMessage {
+    /// The registration tokens, at most [MAX_BATCH_SIZE](constant.MAX_BATCH_SIZE.html).
+    pub tokens: Vec<String>,
+
+    pub data: HashMap<String, String>,
+
+    pub notification: Option<Notification>,
+
+    pub android: Option<Value>,
+
+    pub apns: Option<Value>,
+
+    pub webpush: Option<Value>,
+
+    pub fcm_options: Option<Value>,
+
+    /// Only validate the messages, without delivering them.
+    pub validate_only: bool,
+}
+
+impl MulticastMessage {
+    /// The message sent to each token, in the order of the tokens.
+    pub fn messages(&self) -> Vec<Message> {
+        self.tokens
+            .iter()
+            .map(|token| {
+                let mut builder = MessageBuilder::new(Target::Token(token.clone()));
+                builder.data(self.data.clone()).validate_only(self.validate_only);
+
+                if let Some(ref notification) = self.notification {
+                    builder.notification(notification.clone());
+                }
+                if let Some(ref android) = self.android {
+                    builder.android(android.clone());
+                }
+                if let Some(ref apns) = self.apns {
+                    builder.apns(apns.clone());
+                }
+                if let Some(ref webpush) = self.webpush {
+                    builder.webpush(webpush.clone());
+                }
+                if let Some(ref fcm_options) = self.fcm_options {
+                    builder.fcm_options(fcm_options.clone());
+                }
+
+                builder.finalize()
+            })
+            .collect()
+    }
+}
+
+/// The results of sending several v1 messages, one per message in the order
+/// they were given. Errors reported by FCM are `FcmError::V1`, see
+/// [FcmError::v1_status](../enum.FcmError.html#method.v1_status).
+#[derive(Debug)]
+pub struct BatchResponse {
+    pub responses: Vec<Result<SendResponse, FcmError>>,
+
+    /// How many messages were sent.
+    pub success_count: usize,
+
+    /// How many messages could not be sent.
+    pub failure_count: usize,
+}
+
+impl BatchResponse {
+    pub(crate) fn new(responses: Vec<Result<SendResponse, FcmError>>) -> BatchResponse {
+        let success_count = responses.iter().filter(|response| response.is_ok()).count();
+
+        BatchResponse {
+            failure_count: responses.len() - success_count,
+            success_count,
+            responses,
+        }
+    }
+
+    /// The errors with the index of their message.
+    pub fn errors(&self) -> impl Iterator<Item = (usize, &FcmError)> {
+        self.responses
+            .iter()
+            .enumerate()
+            .filter_map(|(i, response)| response.as_ref().err().map(|e| (i, e)))
+    }
+}
@@ -0,0 +1,460 @@
+use chrono::Duration;
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{error::Error, fmt};
+
+use crate::RetryAfter;
+
+const FCM_ERROR_TYPE: &str = "type.googleapis.com/google.firebase.fcm.v1.FcmError";
+const APNS_ERROR_TYPE: &str = "type.googleapis.com/google.firebase.fcm.v1.ApnsError";
+const BAD_REQUEST_TYPE: &str = "type.googleapis.com/google.rpc.BadRequest";
+const RETRY_INFO_TYPE: &str = "type.googleapis.com/google.rpc.RetryInfo";
+
+/// An error code returned by the v1 API in an `FcmError` detail. Referred
+/// from [Firebase
+/// documentation](https://firebase.google.com/docs/reference/fcm/rest/v1/ErrorCode)
+#[derive(Deserialize, Debug, PartialEq, Eq, Copy, Clone)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// Request parameters were invalid. A `BadRequest` detail describes which
+    /// field was invalid.
+    InvalidArgument,
+
+    /// The registration token is no longer valid. Remove it from the app
+    /// server and stop using it to send messages.
+    Unregistered,
+
+    /// The authenticated sender ID is different from the sender ID for the
+    /// registration token.
+    SenderIdMismatch,
+
+    /// Sending limit exceeded for the message target. Retry with exponential
+    /// backoff, honoring the `RetryInfo` detail if included.
+    QuotaExceeded,
+
+    /// The server is overloaded. Retry with exponential backoff.
+    Unavailable,
+
+    /// An unknown internal error occurred. Retry with exponential backoff.
+    Internal,
+
+    /// The APNs certificate or web push auth key was invalid or missing.
+    ThirdPartyAuthError,
+
+    /// No more information is available about this error. Also used for codes
+    /// this crate does not know about.
+    #[serde(other)]
+    UnspecifiedError,
+}
+
+impl ErrorCode {
+    /// `true` if the same request may succeed when retried later.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ErrorCode::QuotaExceeded | ErrorCode::Unavailable | ErrorCode::Internal
+        )
+    }
+
+    /// `true` if the registration token should be removed from the app
+    /// server. See
+    /// [ErrorReason::should_remove_token](../enum.ErrorReason.html#method.should_remove_token).
+    pub fn should_remove_token(&self) -> bool {
+        matches!(self, ErrorCode::Unregistered)
+    }
+}
+
+/// A request field that failed validation, from a `BadRequest` detail.
+#[derive(Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct FieldViolation {
+    /// Path to the offending field, e.g. `message.token`.
+    #[serde(default)]
+    pub field: String,
+
+    /// Why the field is invalid.
+    #[serde(default)]
+    pub description: String,
+}
+
+/// A typed entry of the `details` list of a v1 error.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ErrorDetail {
+    /// The FCM specific error code.
+    FcmError { error_code: ErrorCode },
+
+    /// The error APNs returned when FCM forwarded the message to it.
+    ApnsError {
+        status_code: Option<u16>,
+        reason: Option<String>,
+    },
+
+    /// Request fields that failed validation.
+    BadRequest { field_violations: Vec<FieldViolation> },
+
+    /// How long to wait before retrying the request.
+    RetryInfo { retry_delay: Duration },
+
+    /// A detail type this crate does not know about, as received.
+    Unknown(Value),
+}
+
+impl<'de> Deserialize<'de> for ErrorDetail {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Fcm {
+            error_code: ErrorCode,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Apns {
+            status_code: Option<u16>,
+            reason: Option<String>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct BadRequest {
+            #[serde(default)]
+            field_violations: Vec<FieldViolation>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct RetryInfo {
+            retry_delay: String,
+        }
+
+        let value = Value::deserialize(deserializer)?;
+
+        let detail = match value.get("@type").and_then(Value::as_str) {
+            Some(FCM_ERROR_TYPE) => {
+                let Fcm { error_code } = Fcm::deserialize(value).map_err(de::Error::custom)?;
+                ErrorDetail::FcmError { error_code }
+            }
+            Some(APNS_ERROR_TYPE) => {
+                let Apns { status_code, reason } = Apns::deserialize(value).map_err(de::Error::custom)?;
+                ErrorDetail::ApnsError { status_code, reason }
+            }
+            Some(BAD_REQUEST_TYPE) => {
+                let BadRequest { field_violations } = BadRequest::deserialize(value).map_err(de::Error::custom)?;
+                ErrorDetail::BadRequest { field_violations }
+            }
+            Some(RETRY_INFO_TYPE) => {
+                let retry_delay = RetryInfo::deserialize(&value)
+                    .ok()
+                    .and_then(|RetryInfo { retry_delay }| parse_proto_duration(&retry_delay));
+
+                match retry_delay {
+                    Some(retry_delay) => ErrorDetail::RetryInfo { retry_delay },
+                    None => {
+                        log::warn!("ignoring invalid retryDelay in {}", value);
+                        ErrorDetail::Unknown(value)
+                    }
+                }
+            }
+            _ => ErrorDetail::Unknown(value),
+        };
+
+        Ok(detail)
+    }
+}
+
+/// Parses the JSON form of a `google.protobuf.Duration`, e.g. `30s` or
+/// `1.500s`.
+fn parse_proto_duration(s: &str) -> Option<Duration> {
+    let seconds = s.strip_suffix('s')?;
+
+    let (negative, seconds) = match seconds.strip_prefix('-') {
+        Some(seconds) => (true, seconds),
+        None => (false, seconds),
+    };
+
+    let (whole, fraction) = match seconds.find('.') {
+        Some(idx) => (&seconds[..idx], &seconds[idx + 1..]),
+        None => (seconds, ""),
+    };
+
+    if whole.is_empty()
+        || !whole.chars().all(|c| c.is_ascii_digit())
+        || fraction.len() > 9
+        || !fraction.chars().all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+
+    let whole: i64 = whole.parse().ok()?;
+    let nanos: i64 = if fraction.is_empty() {
+        0
+    } else {
+        format!("{:0<9}", fraction).parse().ok()?
+    };
+
+    let duration = Duration::try_seconds(whole)?.checked_add(&Duration::nanoseconds(nanos))?;
+
+    Some(if negative { -duration } else { duration })
+}
+
+/// A v1 API error, modeled after `google.rpc.Status`. Referred from [Firebase
+/// documentation](https://firebase.google.com/docs/reference/fcm/rest/v1/ErrorCode)
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+pub struct Status {
+    /// The HTTP status code of the error.
+    pub code: u16,
+
+    /// A developer-facing description of the error.
+    #[serde(default)]
+    pub message: String,
+
+    /// The canonical error status, e.g. `INVALID_ARGUMENT` or `NOT_FOUND`.
+    #[serde(default)]
+    pub status: String,
+
+    /// Typed details of the error.
+    #[serde(default)]
+    pub details: Vec<ErrorDetail>,
+}
+
+impl Status {
+    /// Parse the body of a failed v1 response, which wraps the status in an
+    /// `error` object.
+    pub fn from_response_body(body: &[u8]) -> Result<Status, serde_json::Error> {
+        #[derive(Deserialize)]
+        struct ErrorResponse {
+            error: Status,
+        }
+
+        serde_json::from_slice::<ErrorResponse>(body).map(|response| response.error)
+    }
+
+    /// The FCM error code from the `FcmError` detail, if present.
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        self.details.iter().find_map(|detail| match detail {
+            ErrorDetail::FcmError { error_code } => Some(*error_code),
+            _ => None,
+        })
+    }
+
+    /// The APNs status code and reason from the `ApnsError` detail, if present.
+    pub fn apns_error(&self) -> Option<(Option<u16>, Option<&str>)> {
+        self.details.iter().find_map(|detail| match detail {
+            ErrorDetail::ApnsError { status_code, reason } => Some((*status_code, reason.as_deref())),
+            _ => None,
+        })
+    }
+
+    /// All field violations from `BadRequest` details.
+    pub fn field_violations(&self) -> Vec<&FieldViolation> {
+        self.details
+            .iter()
+            .filter_map(|detail| match detail {
+                ErrorDetail::BadRequest { field_violations } => Some(field_violations),
+                _ => None,
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// The delay from the `RetryInfo` detail, if present.
+    pub fn retry_after(&self) -> Option<RetryAfter> {
+        self.details.iter().find_map(|detail| match detail {
+            ErrorDetail::RetryInfo { retry_delay } => Some(RetryAfter::Delay(*retry_delay)),
+            _ => None,
+        })
+    }
+
+    /// `true` if the same request may succeed when retried later. This is
+    /// the case for `QUOTA_EXCEEDED`, `UNAVAILABLE` and `INTERNAL` errors, and
+    /// for HTTP 429 and 5xx responses without an FCM error code.
+    pub fn is_retryable(&self) -> bool {
+        match self.error_code() {
+            Some(ErrorCode::UnspecifiedError) | None => self.code == 429 || self.code >= 500,
+            Some(code) => code.is_retryable(),
+        }
+    }
+
+    /// `true` if the registration token should be removed from the app
+    /// server. This is the case for `UNREGISTERED` errors, and for
+    /// `INVALID_ARGUMENT` errors that point at the token.
+    pub fn should_remove_token(&self) -> bool {
+        match self.error_code() {
+            Some(ErrorCode::InvalidArgument) => self
+                .field_violations()
+                .iter()
+                .any(|violation| violation.field == "message.token"),
+            Some(code) => code.should_remove_token(),
+            None => false,
+        }
+    }
+}
+
+impl Error for Status {}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.error_code() {
+            Some(code) => write!(f, "{} ({:?}, {}): {}", self.status, code, self.code, self.message),
+            None => write!(f, "{} ({}): {}", self.status, self.code, self.message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_unregistered_status() {
+        let body = json!({
+            "error": {
+                "code": 404,
+                "message": "Requested entity was not found.",
+                "status": "NOT_FOUND",
+                "details": [
+                    {
+                        "@type": "type.googleapis.com/google.firebase.fcm.v1.FcmError",
+                        "errorCode": "UNREGISTERED"
+                    }
+                ]
+            }
+        });
+
+        let status = Status::from_response_body(body.to_string().as_bytes()).unwrap();
+
+        assert_eq!(404, status.code);
+        assert_eq!("NOT_FOUND", status.status);
+        assert_eq!(Some(ErrorCode::Unregistered), status.error_code());
+        assert!(status.should_remove_token());
+        assert!(!status.is_retryable());
+    }
+
+    #[test]
+    fn test_all_detail_types() {
+        let body = json!({
+            "error": {
+                "code": 400,
+                "message": "The registration token is not a valid FCM registration token",
+                "status": "INVALID_ARGUMENT",
+                "details": [
+                    {
+                        "@type": "type.googleapis.com/google.firebase.fcm.v1.FcmError",
+                        "errorCode": "INVALID_ARGUMENT"
+                    },
+                    {
+                        "@type": "type.googleapis.com/google.firebase.fcm.v1.ApnsError",
+                        "statusCode": 400,
+                        "reason": "BadDeviceToken"
+                    },
+                    {
+                        "@type": "type.googleapis.com/google.rpc.BadRequest",
+                        "fieldViolations": [
+                            {
+                                "field": "message.token",
+                                "description": "Invalid registration token"
+                            }
+                        ]
+                    },
+                    {
+                        "@type": "type.googleapis.com/google.rpc.RetryInfo",
+                        "retryDelay": "1.500s"
+                    },
+                    {
+                        "@type": "type.googleapis.com/google.rpc.DebugInfo",
+                        "detail": "something"
+                    }
+                ]
+            }
+        });
+
+        let status = Status::from_response_body(body.to_string().as_bytes()).unwrap();
+
+        assert_eq!(Some(ErrorCode::InvalidArgument), status.error_code());
+        assert_eq!(Some((Some(400), Some("BadDeviceToken"))), status.apns_error());
+        assert_eq!("message.token", status.field_violations()[0].field);
+        assert_eq!(
+            Some(RetryAfter::Delay(Duration::milliseconds(1500))),
+            status.retry_after()
+        );
+        assert!(matches!(status.details[4], ErrorDetail::Unknown(_)));
+        assert!(status.should_remove_token());
+    }
+
+    #[test]
+    fn test_quota_exceeded_is_retryable() {
+        let body = json!({
+            "error": {
+                "code": 429,
+                "message": "Quota exceeded",
+                "status": "RESOURCE_EXHAUSTED",
+                "details": [
+                    {
+                        "@type": "type.googleapis.com/google.firebase.fcm.v1.FcmError",
+                        "errorCode": "QUOTA_EXCEEDED"
+                    },
+                    {
+                        "@type": "type.googleapis.com/google.rpc.RetryInfo",
+                        "retryDelay": "30s"
+                    }
+                ]
+            }
+        });
+
+        let status = Status::from_response_body(body.to_string().as_bytes()).unwrap();
+
+        assert!(status.is_retryable());
+        assert!(!status.should_remove_token());
+        assert_eq!(Some(RetryAfter::Delay(Duration::seconds(30))), status.retry_after());
+    }
+
+    #[test]
+    fn test_unknown_error_code() {
+        let detail: ErrorDetail = serde_json::from_value(json!({
+            "@type": "type.googleapis.com/google.firebase.fcm.v1.FcmError",
+            "errorCode": "SOMETHING_NEW"
+        }))
+        .unwrap();
+
+        assert_eq!(
+            ErrorDetail::FcmError {
+                error_code: ErrorCode::UnspecifiedError
+            },
+            detail
+        );
+    }
+
+    #[test]
+    fn test_proto_duration() {
+        assert_eq!(Some(Duration::seconds(3)), parse_proto_duration("3s"));
+        assert_eq!(Some(Duration::milliseconds(10)), parse_proto_duration("0.010s"));
+        assert_eq!(None, parse_proto_duration("3"));
+        assert_eq!(None, parse_proto_duration("1.x5s"));
+        assert_eq!(Some(Duration::milliseconds(-1500)), parse_proto_duration("-1.5s"));
+        assert_eq!(None, parse_proto_duration("+3s"));
+        assert_eq!(None, parse_proto_duration("9223372036854775807s"));
+    }
+
+    #[test]
+    fn should_skip_an_invalid_retry_delay() {
+        let body = json!({
+            "error": {
+                "code": 429,
+                "status": "RESOURCE_EXHAUSTED",
+                "details": [{
+                    "@type": "type.googleapis.com/google.rpc.RetryInfo",
+                    "retryDelay": "99999999999999999999s"
+                }]
+            }
+        });
+
+        let status = Status::from_response_body(body.to_string().as_bytes()).unwrap();
+
+        assert_eq!(None, status.retry_after());
+        assert!(matches!(status.details[0], ErrorDetail::Unknown(_)));
+    }
+}
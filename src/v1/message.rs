@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Who a v1 [Message](struct.Message.html) is sent to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    /// A single registration token.
+    Token(String),
+
+    /// All subscribers of a topic, given without the `/topics/` prefix.
+    Topic(String),
+
+    /// All devices matching a topic condition, e.g. `'news' in topics`.
+    Condition(String),
+}
+
+/// A notification shown on every platform. Platform specific options can be
+/// set with the `android`, `apns` and `webpush` configs of the message.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Notification {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
+
+/// A message for the FCM HTTP v1 API. Use
+/// [MessageBuilder](struct.MessageBuilder.html) to construct one. Referred
+/// from [Firebase
+/// documentation](https://firebase.google.com/docs/reference/fcm/rest/v1/projects.messages)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Message {
+    #[serde(flatten)]
+    pub target: Target,
+
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub data: HashMap<String, String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notification: Option<Notification>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub android: Option<Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apns: Option<Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webpush: Option<Value>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fcm_options: Option<Value>,
+
+    /// Only validate the message, without delivering it. Sent next to the
+    /// message in the request.
+    #[serde(skip)]
+    pub validate_only: bool,
+}
+
+impl Message {
+    /// The body of a `messages:send` request for this message.
+    pub(crate) fn request_body(&self) -> Result<Vec<u8>, serde_json::Error> {
+        #[derive(Serialize)]
+        struct SendRequest<'a> {
+            message: &'a Message,
+            #[serde(skip_serializing_if = "std::ops::Not::not")]
+            validate_only: bool,
+        }
+
+        serde_json::to_vec(&SendRequest {
+            message: self,
+            validate_only: self.validate_only,
+        })
+    }
+}
+
+/// A builder to get a v1 [Message](struct.Message.html) instance.
+///
+/// # Examples
+///
+/// ```rust
+/// use fcm::v1::{MessageBuilder, Notification, Target};
+///
+/// let mut builder = MessageBuilder::new(Target::Token("<registration id>".to_string()));
+/// builder
+///     .data(vec![("message", "Howdy!")])
+///     .notification(Notification {
+///         title: Some("Hey!".to_string()),
+///         ..Notification::default()
+///     });
+/// let message = builder.finalize();
+/// ```
+#[derive(Debug)]
+pub struct MessageBuilder {
+    message: Message,
+}
+
+impl MessageBuilder {
+    /// Get a new instance of Message. You need to supply the target.
+    pub fn new(target: Target) -> Self {
+        MessageBuilder {
+            message: Message {
+                target,
+                data: HashMap::new(),
+                notification: None,
+                android: None,
+                apns: None,
+                webpush: None,
+                fcm_options: None,
+                validate_only: false,
+            },
+        }
+    }
+
+    /// Add custom key-value pairs to the message. The v1 API only accepts
+    /// string values.
+    pub fn data<I, K, V>(&mut self, data: I) -> &mut Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.message
+            .data
+            .extend(data.into_iter().map(|(key, value)| (key.into(), value.into())));
+        self
+    }
+
+    /// Set the notification shown on all platforms.
+    pub fn notification(&mut self, notification: Notification) -> &mut Self {
+        self.message.notification = Some(notification);
+        self
+    }
+
+    /// Set the [Android specific
+    /// options](https://firebase.google.com/docs/reference/fcm/rest/v1/projects.messages#androidconfig).
+    pub fn android(&mut self, android: Value) -> &mut Self {
+        self.message.android = Some(android);
+        self
+    }
+
+    /// Set the [APNs specific
+    /// options](https://firebase.google.com/docs/reference/fcm/rest/v1/projects.messages#apnsconfig).
+    pub fn apns(&mut self, apns: Value) -> &mut Self {
+        self.message.apns = Some(apns);
+        self
+    }
+
+    /// Set the [Webpush specific
+    /// options](https://firebase.google.com/docs/reference/fcm/rest/v1/projects.messages#webpushconfig).
+    pub fn webpush(&mut self, webpush: Value) -> &mut Self {
+        self.message.webpush = Some(webpush);
+        self
+    }
+
+    /// Set options for features provided by the FCM SDK, e.g. the analytics
+    /// label.
+    pub fn fcm_options(&mut self, fcm_options: Value) -> &mut Self {
+        self.message.fcm_options = Some(fcm_options);
+        self
+    }
+
+    /// When set to `true`, FCM only validates the message without delivering
+    /// it.
+    pub fn validate_only(&mut self, validate_only: bool) -> &mut Self {
+        self.message.validate_only = validate_only;
+        self
+    }
+
+    /// Complete the build and get a `Message` instance
+    pub fn finalize(self) -> Message {
+        self.message
+    }
+}
+
+/// The response to a successful v1 send.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SendResponse {
+    /// The identifier of the message, in the format
+    /// `projects/*/messages/{message_id}`.
+    pub name: String,
+}
@@ -0,0 +1,22 @@
+//! Types for the FCM HTTP v1 API.
+//!
+//! Build a [Message](struct.Message.html) with
+//! [MessageBuilder](struct.MessageBuilder.html) and send it with
+//! [Client::send_v1](../struct.Client.html#method.send_v1), authorized by
+//! [Credentials](struct.Credentials.html).
+//! [Client::send_each](../struct.Client.html#method.send_each) and
+//! [Client::send_each_for_multicast](../struct.Client.html#method.send_each_for_multicast)
+//! send several messages and return a [BatchResponse](struct.BatchResponse.html).
+//!
+//! The v1 API reports errors as a
+//! [google.rpc.Status](https://cloud.google.com/apis/design/errors) with
+//! typed details. Use [Status](struct.Status.html) to inspect them.
+
+mod auth;
+pub use self::auth::*;
+mod batch;
+pub use self::batch::*;
+mod error;
+pub use self::error::*;
+mod message;
+pub use self::message::*;